@@ -0,0 +1,14 @@
+#!/usr/bin/env python3
+# An example plugin, copy it to the plugins directory and run `.wordcount` in the REPL
+import json
+import sys
+
+request = json.loads(sys.stdin.readline())
+if request["type"] == "describe":
+    response = {"description": "Count the words of the last reply"}
+elif request["type"] == "command":
+    words = len(request["last_reply"].split())
+    response = {"output": f"The last reply has {words} words"}
+else:
+    response = {"error": f"unknown request type {request['type']}"}
+print(json.dumps(response))
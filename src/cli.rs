@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -12,30 +12,85 @@ pub struct Cli {
     /// Create or reuse a session
     #[clap(short = 's', long)]
     pub session: Option<Option<String>>,
+    /// Start a session from a conversation template
+    #[clap(long, value_name = "NAME", conflicts_with = "session")]
+    pub new_from_template: Option<String>,
     /// Execute commands using natural language
     #[clap(short = 'e', long)]
     pub execute: bool,
     /// Generate only code
     #[clap(short = 'c', long)]
     pub code: bool,
-    /// Attach files to the message to be sent.
+    /// The language of the generated code, e.g. rust
+    #[clap(long, requires = "code")]
+    pub lang: Option<String>,
+    /// Attach files to the message to be sent, `-` reads one from stdin
     #[clap(short = 'f', long, num_args = 1.., value_name = "FILE")]
     pub file: Option<Vec<String>>,
+    /// Render a prompt template file and send it
+    #[clap(long, value_name = "FILE")]
+    pub template: Option<String>,
+    /// Set a template variable, use @<file> to load the value from a file
+    #[clap(long = "var", value_name = "KEY=VALUE")]
+    pub vars: Vec<String>,
     /// Disable syntax highlighting
     #[clap(short = 'H', long)]
     pub no_highlight: bool,
     /// No stream output
     #[clap(short = 'S', long)]
     pub no_stream: bool,
+    /// Sample the answer K times and keep the best one, by majority vote or by asking the model
+    #[clap(long, value_name = "K")]
+    pub best_of: Option<usize>,
+    /// Run the prompt through a pipeline of models defined in the config
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = ["best_of", "file", "code", "max_time"]
+    )]
+    pub pipeline: Option<String>,
+    /// Print the output of every pipeline stage, not only the final one
+    #[clap(long, requires = "pipeline")]
+    pub show_stages: bool,
+    /// Require the reply to be JSON matching the schema file, retrying with the validation errors
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["best_of", "pipeline"])]
+    pub schema: Option<String>,
+    /// Specify the output format
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
     /// Specify the text-wrapping mode (no, auto, <max-width>)
     #[clap(short = 'w', long)]
     pub wrap: Option<String>,
     /// Use light theme
     #[clap(long)]
     pub light_theme: bool,
+    /// Start the full-screen TUI instead of the REPL
+    #[clap(long)]
+    pub tui: bool,
+    /// Ask a single question in a compact prompt, suited for tmux display-popup
+    #[clap(long)]
+    pub popup: bool,
+    /// Stop the reply at this deadline (e.g. 20s, 2m) and keep the partial output
+    #[clap(long, value_name = "DURATION")]
+    pub max_time: Option<String>,
     /// Run in dry run mode
     #[clap(long)]
     pub dry_run: bool,
+    /// Write nothing to disk: no messages, sessions or usage
+    #[clap(long)]
+    pub no_save: bool,
+    /// Disable network features, only models served on this machine are usable
+    #[clap(long)]
+    pub offline: bool,
+    /// Low-bandwidth mode: no streaming, compressed responses, minimal rendering
+    #[clap(long)]
+    pub lite: bool,
+    /// Record the API interactions to a cassette file
+    #[clap(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<String>,
+    /// Replay the API interactions of a cassette file, without the network
+    #[clap(long, value_name = "FILE")]
+    pub replay: Option<String>,
     /// Print related information
     #[clap(long)]
     pub info: bool,
@@ -48,6 +103,39 @@ pub struct Cli {
     /// List all available sessions
     #[clap(long)]
     pub list_sessions: bool,
+    /// Archive or delete the temp and per-directory sessions beyond `session_retention`
+    #[clap(long)]
+    pub prune_sessions: bool,
+    /// Check the roles for mistakes in their prompts
+    #[clap(long)]
+    pub lint_roles: bool,
+    /// Ping every configured client and report reachability, auth and latency
+    #[clap(long)]
+    pub healthcheck: bool,
+    /// Check the usage of the week and of the month against `usage_alerts`, exit 1 if exceeded
+    #[clap(long)]
+    pub check_budget: bool,
+    /// Talk with a parent process over stdin and stdout with length-prefixed JSON messages
+    #[clap(long)]
+    pub coprocess: bool,
+    /// Send each line of a JSONL file, `{"id", "text", "model"?, "role"?, "temperature"?}`,
+    /// concurrently and print the replies as JSON lines as they finish
+    #[clap(long, value_name = "FILE")]
+    pub batch: Option<String>,
+    /// How many batch items are sent at the same time
+    #[clap(long, value_name = "N", default_value_t = 4, requires = "batch")]
+    pub batch_concurrency: usize,
+    /// Back up the config directory to a .tar.gz file
+    #[clap(long, value_name = "FILE")]
+    pub backup: Option<String>,
+    /// Exclude API keys from the backup
+    #[clap(long, requires = "backup")]
+    pub exclude_keys: bool,
+    /// Restore the config directory from a backup file
+    #[clap(long, value_name = "FILE")]
+    pub restore: Option<String>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
     /// Input text
     #[clap(trailing_var_arg = true)]
     text: Vec<String>,
@@ -55,15 +143,91 @@ pub struct Cli {
 
 impl Cli {
     pub fn text(&self) -> Option<String> {
-        let text = self
-            .text
-            .iter()
-            .map(|x| x.trim().to_string())
-            .collect::<Vec<String>>()
-            .join(" ");
-        if text.is_empty() {
-            return None;
-        }
-        Some(text)
+        join_text(&self.text)
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Plain or highlighted text
+    Text,
+    /// Nushell object notation, lists and tables in the reply become structured data
+    Nuon,
+    /// A JSON line for each streamed delta, then one for the usage, for programs wrapping aichat
+    JsonlStream,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the same prompt across temperatures or models and compare the replies
+    Sweep(SweepArgs),
+    /// Score a text against a rubric and print the scores as JSON
+    Judge(JudgeArgs),
+    /// Upgrade aichat to the latest release
+    Upgrade(UpgradeArgs),
+    /// Install, update and remove collections of roles
+    #[clap(subcommand)]
+    RolePack(RolePackCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RolePackCommand {
+    /// Install a role pack by its name, its URL or the path of a tarball
+    Install { source: String },
+    /// Fetch the installed role packs again, or only the given one
+    Update { name: Option<String> },
+    /// Remove an installed role pack and its roles
+    Remove { name: String },
+    /// List the installed role packs
+    List,
+}
+
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// Temperatures to try, separated by commas
+    #[clap(long, value_delimiter = ',', value_name = "VALUES")]
+    pub temperature: Vec<f64>,
+    /// Models to try, separated by commas
+    #[clap(long, value_delimiter = ',', value_name = "MODELS")]
+    pub model: Vec<String>,
+    /// Input text
+    #[clap(trailing_var_arg = true)]
+    text: Vec<String>,
+}
+
+impl SweepArgs {
+    pub fn text(&self) -> Option<String> {
+        join_text(&self.text)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct JudgeArgs {
+    /// The rubric file, listing the criteria and how to score them
+    #[clap(long, value_name = "FILE")]
+    pub criteria: String,
+    /// The judge model, defaults to `judge_model` of the config
+    #[clap(long)]
+    pub model: Option<String>,
+    /// The file to score, reads stdin if omitted
+    pub file: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct UpgradeArgs {
+    /// Only check whether a newer release is available
+    #[clap(long)]
+    pub check: bool,
+}
+
+fn join_text(text: &[String]) -> Option<String> {
+    let text = text
+        .iter()
+        .map(|x| x.trim().to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    if text.is_empty() {
+        return None;
     }
+    Some(text)
 }
@@ -8,11 +8,15 @@ use async_trait::async_trait;
 use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
 
+const API_VERSION: &str = "2023-05-15";
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AzureOpenAIConfig {
     pub name: Option<String>,
     pub api_base: Option<String>,
     pub api_key: Option<String>,
+    /// The `api-version` query parameter, defaults to 2023-05-15
+    pub api_version: Option<String>,
     pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
 }
@@ -22,6 +26,7 @@ openai_compatible_client!(AzureOpenAIClient);
 impl AzureOpenAIClient {
     config_get_fn!(api_base, get_api_base);
     config_get_fn!(api_key, get_api_key);
+    config_get_fn!(api_version, get_api_version);
 
     pub const PROMPTS: [PromptType<'static>; 4] = [
         ("api_base", "API Base:", true, PromptKind::String),
@@ -45,6 +50,10 @@ impl AzureOpenAIClient {
                 Model::new(client_name, &v.name)
                     .set_max_input_tokens(v.max_input_tokens)
                     .set_capabilities(v.capabilities)
+                    .set_no_stream(v.no_stream)
+                    .set_input_price(v.input_price)
+                    .set_output_price(v.output_price)
+                    .set_reasoning_effort(v.reasoning_effort.clone())
                     .set_tokens_count_factors(OPENAI_TOKENS_COUNT_FACTORS)
             })
             .collect()
@@ -53,12 +62,22 @@ impl AzureOpenAIClient {
     fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
         let api_base = self.get_api_base()?;
         let api_key = self.get_api_key()?;
+        let api_version = self
+            .get_api_version()
+            .unwrap_or_else(|_| API_VERSION.to_string());
+        let deployment = self
+            .config
+            .models
+            .iter()
+            .find(|v| v.name == self.model.name)
+            .and_then(|v| v.deployment.as_deref())
+            .unwrap_or(&self.model.name);
 
         let body = openai_build_body(data, self.model.name.clone());
 
         let url = format!(
-            "{}/openai/deployments/{}/chat/completions?api-version=2023-05-15",
-            &api_base, self.model.name
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            &api_base, deployment, api_version
         );
 
         debug!("AzureOpenAI Request: {url} {body}");
@@ -0,0 +1,661 @@
+use super::{
+    ApiError, BedrockClient, Client, ExtraConfig, ImageUrl, MessageContent, MessageContentPart,
+    MessageRole, Model, ModelConfig, PromptType, SendData, TokensCountFactors,
+};
+
+use crate::{render::ReplyHandler, utils::PromptKind};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{Client as ReqwestClient, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+const SERVICE: &str = "bedrock";
+
+// (name, max_input_tokens, input_price, capabilities)
+const MODELS: [(&str, usize, f64, &str); 5] = [
+    // https://docs.aws.amazon.com/bedrock/latest/userguide/model-ids.html
+    (
+        "anthropic.claude-3-opus-20240229-v1:0",
+        200000,
+        15.0,
+        "text,vision",
+    ),
+    (
+        "anthropic.claude-3-sonnet-20240229-v1:0",
+        200000,
+        3.0,
+        "text,vision",
+    ),
+    (
+        "anthropic.claude-3-haiku-20240307-v1:0",
+        200000,
+        0.25,
+        "text,vision",
+    ),
+    ("meta.llama3-70b-instruct-v1:0", 8192, 2.65, "text"),
+    ("meta.llama3-8b-instruct-v1:0", 8192, 0.3, "text"),
+];
+
+const TOKENS_COUNT_FACTORS: TokensCountFactors = (5, 2);
+
+/// The credentials and the region fall back to the `AWS_*` environment variables, then to the
+/// profile of `~/.aws/credentials` and `~/.aws/config`
+#[derive(Debug, Clone, Deserialize)]
+pub struct BedrockConfig {
+    pub name: Option<String>,
+    pub region: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    /// Defaults to `AWS_PROFILE`, then `default`
+    pub profile: Option<String>,
+    /// Models added to the builtin ones, or overriding those with the same name
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[derive(Debug, Clone)]
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+#[async_trait]
+impl Client for BedrockClient {
+    client_common_fns!();
+
+    async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String> {
+        let builder = self.request_builder(client, data)?;
+        send_message(builder).await
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        let builder = self.request_builder(client, data)?;
+        send_message_streaming(builder, handler).await
+    }
+}
+
+impl BedrockClient {
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("region", "AWS Region:", false, PromptKind::String)];
+
+    pub fn list_models(local_config: &BedrockConfig) -> Vec<Model> {
+        let client_name = Self::name(local_config);
+        let custom_models = local_config.models.iter().map(|v| {
+            Model::new(client_name, &v.name)
+                .set_capabilities(v.capabilities)
+                .set_no_stream(v.no_stream)
+                .set_input_price(v.input_price)
+                .set_output_price(v.output_price)
+                .set_max_input_tokens(v.max_input_tokens)
+                .set_extra_fields(v.extra_fields.clone())
+                .set_tokens_count_factors(TOKENS_COUNT_FACTORS)
+        });
+        MODELS
+            .into_iter()
+            .filter(|(name, ..)| !local_config.models.iter().any(|v| v.name == *name))
+            .map(|(name, max_input_tokens, input_price, capabilities)| {
+                Model::new(client_name, name)
+                    .set_capabilities(capabilities.into())
+                    .set_max_input_tokens(Some(max_input_tokens))
+                    .set_input_price(Some(input_price))
+                    .set_tokens_count_factors(TOKENS_COUNT_FACTORS)
+            })
+            .chain(custom_models)
+            .collect()
+    }
+
+    fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
+        let region = self.get_region()?;
+        let credentials = self.get_credentials()?;
+
+        let func = match data.stream {
+            true => "converse-stream",
+            false => "converse",
+        };
+
+        let mut body = build_body(data)?;
+
+        self.model.merge_extra_fields(&mut body);
+
+        let host = format!("bedrock-runtime.{region}.amazonaws.com");
+        let path = format!("/model/{}/{func}", uri_encode(&self.model.name));
+        let body = body.to_string();
+
+        debug!("Bedrock Request: https://{host}{path} {body}");
+
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let headers = sign_request(
+            &credentials,
+            &region,
+            SERVICE,
+            "POST",
+            &host,
+            &path,
+            body.as_bytes(),
+            &amz_date,
+        );
+
+        let mut builder = client
+            .post(format!("https://{host}{path}"))
+            .header("content-type", "application/json")
+            .body(body);
+        for (key, value) in headers {
+            builder = builder.header(key, value);
+        }
+
+        Ok(builder)
+    }
+
+    fn profile(&self) -> String {
+        self.config
+            .profile
+            .clone()
+            .or_else(|| env::var("AWS_PROFILE").ok())
+            .unwrap_or_else(|| "default".into())
+    }
+
+    fn get_region(&self) -> Result<String> {
+        if let Some(region) = self.config.region.clone().or_else(|| {
+            env::var("AWS_REGION")
+                .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+                .ok()
+        }) {
+            return Ok(region);
+        }
+        let profile = self.profile();
+        let section = match profile.as_str() {
+            "default" => profile.clone(),
+            _ => format!("profile {profile}"),
+        };
+        aws_file("AWS_CONFIG_FILE", "config")
+            .and_then(|path| read_ini_section(&path, &section))
+            .and_then(|mut v| v.remove("region"))
+            .ok_or_else(|| anyhow!("Miss region, set it in the config or with AWS_REGION"))
+    }
+
+    fn get_credentials(&self) -> Result<AwsCredentials> {
+        let config = &self.config;
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&config.access_key_id, &config.secret_access_key)
+        {
+            return Ok(AwsCredentials {
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+                session_token: config.session_token.clone(),
+            });
+        }
+        if let (Ok(access_key_id), Ok(secret_access_key)) = (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+        let profile = self.profile();
+        let mut values = aws_file("AWS_SHARED_CREDENTIALS_FILE", "credentials")
+            .and_then(|path| read_ini_section(&path, &profile))
+            .ok_or_else(|| anyhow!("Miss AWS credentials for the profile '{profile}'"))?;
+        match (
+            values.remove("aws_access_key_id"),
+            values.remove("aws_secret_access_key"),
+        ) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: values.remove("aws_session_token"),
+            }),
+            _ => bail!("Miss AWS credentials for the profile '{profile}'"),
+        }
+    }
+}
+
+async fn send_message(builder: RequestBuilder) -> Result<String> {
+    let res = builder.send().await?;
+    let status = res.status();
+    let data: Value = res.json().await?;
+    if status != 200 {
+        check_error(status.as_u16(), &data)?;
+    }
+    let output = data["output"]["message"]["content"]
+        .as_array()
+        .and_then(|list| list.iter().find_map(|v| v["text"].as_str()))
+        .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+    Ok(output.to_string())
+}
+
+async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHandler) -> Result<()> {
+    let res = builder.send().await?;
+    let status = res.status();
+    if status != 200 {
+        let data: Value = res.json().await?;
+        check_error(status.as_u16(), &data)?;
+        bail!("Invalid response data: {data}");
+    }
+    let mut stream = res.bytes_stream();
+    let mut buffer = vec![];
+    while let Some(chunk) = stream.next().await {
+        buffer.extend_from_slice(&chunk?);
+        for message in decode_event_stream(&mut buffer)? {
+            if message.message_type == "error" {
+                let text = format!("{}: {}", message.event_type, message.error_message);
+                return Err(ApiError::new(None, Some(message.event_type.as_str()), text).into());
+            }
+            let data: Value = serde_json::from_slice(&message.payload)
+                .map_err(|_| anyhow!("Invalid stream data of '{}'", message.event_type))?;
+            if message.message_type == "exception" {
+                let text = format!(
+                    "{}: {}",
+                    message.event_type,
+                    data["message"].as_str().unwrap_or_default()
+                );
+                return Err(ApiError::new(None, Some(message.event_type.as_str()), text).into());
+            }
+            if message.event_type == "contentBlockDelta" {
+                if let Some(text) = data["delta"]["text"].as_str() {
+                    handler.text(text)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn build_body(data: SendData) -> Result<Value> {
+    let SendData {
+        messages,
+        temperature,
+        ..
+    } = data;
+
+    let mut system = vec![];
+    let mut network_image_urls = vec![];
+    let mut list = vec![];
+    for message in messages {
+        if message.role.is_system() {
+            if let MessageContent::Text(text) = message.content {
+                system.push(json!({ "text": text }));
+            }
+            continue;
+        }
+        let role = match message.role {
+            MessageRole::User => "user",
+            _ => "assistant",
+        };
+        let content: Vec<Value> = match message.content {
+            MessageContent::Text(text) => vec![json!({ "text": text })],
+            MessageContent::Array(parts) => parts
+                .into_iter()
+                .map(|item| match item {
+                    MessageContentPart::Text { text } => json!({ "text": text }),
+                    MessageContentPart::ImageUrl {
+                        image_url: ImageUrl { url },
+                    } => {
+                        if let Some((mime_type, data)) = url
+                            .strip_prefix("data:")
+                            .and_then(|v| v.split_once(";base64,"))
+                        {
+                            let format = mime_type.trim_start_matches("image/");
+                            json!({ "image": { "format": format, "source": { "bytes": data } } })
+                        } else {
+                            network_image_urls.push(url.clone());
+                            json!({ "url": url })
+                        }
+                    }
+                })
+                .collect(),
+        };
+        list.push(json!({ "role": role, "content": content }));
+    }
+
+    if !network_image_urls.is_empty() {
+        bail!(
+            "The model does not support network images: {:?}",
+            network_image_urls
+        );
+    }
+
+    let mut body = json!({ "messages": list });
+    if !system.is_empty() {
+        body["system"] = system.into();
+    }
+    if let Some(temperature) = temperature {
+        body["inferenceConfig"] = json!({ "temperature": temperature });
+    }
+    Ok(body)
+}
+
+fn check_error(status: u16, data: &Value) -> Result<()> {
+    let message = match data["message"]
+        .as_str()
+        .or_else(|| data["Message"].as_str())
+    {
+        Some(message) => format!("{status}, {message}"),
+        None => format!("{status}, {data}"),
+    };
+    Err(ApiError::new(Some(status), None, message).into())
+}
+
+/// The headers authenticating a request with AWS Signature Version 4
+#[allow(clippy::too_many_arguments)]
+fn sign_request(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    host: &str,
+    path: &str,
+    body: &[u8],
+    amz_date: &str,
+) -> Vec<(String, String)> {
+    let date = &amz_date[..8];
+    let mut headers = vec![
+        ("host".to_string(), host.to_string()),
+        ("x-amz-date".to_string(), amz_date.to_string()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(k, v)| format!("{k}:{}\n", v.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    // Every service but S3 encodes the path segments once more
+    let canonical_uri = path
+        .split('/')
+        .map(uri_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    let canonical_request = format!(
+        "{method}\n{canonical_uri}\n\n{canonical_headers}\n{signed_headers}\n{}",
+        to_hex(&Sha256::digest(body))
+    );
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        to_hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(&credentials.secret_access_key, date, region, service);
+    let signature = to_hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    );
+    headers.remove(0);
+    headers.push(("authorization".to_string(), authorization));
+    headers
+}
+
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    [date, region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{secret_access_key}").into_bytes(), |key, v| {
+            hmac_sha256(&key, v.as_bytes())
+        })
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|v| format!("{v:02x}")).collect()
+}
+
+/// Percent-encode everything but the unreserved characters, e.g. the `:` of the model ids
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|v| match v {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (v as char).to_string()
+            }
+            _ => format!("%{v:02X}"),
+        })
+        .collect()
+}
+
+fn aws_file(env_name: &str, file_name: &str) -> Option<PathBuf> {
+    match env::var(env_name) {
+        Ok(path) => Some(path.into()),
+        Err(_) => dirs::home_dir().map(|v| v.join(".aws").join(file_name)),
+    }
+}
+
+/// The `key = value` pairs of a section of an AWS ini file
+fn read_ini_section(path: &PathBuf, section: &str) -> Option<HashMap<String, String>> {
+    let content = read_to_string(path).ok()?;
+    parse_ini_section(&content, section)
+}
+
+fn parse_ini_section(content: &str, section: &str) -> Option<HashMap<String, String>> {
+    let mut values = None;
+    for line in content.lines().map(|v| v.trim()) {
+        if let Some(name) = line.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            if values.is_some() {
+                break;
+            }
+            if name.trim() == section {
+                values = Some(HashMap::new());
+            }
+        } else if let (Some(values), Some((key, value))) = (values.as_mut(), line.split_once('=')) {
+            if !line.starts_with(['#', ';']) {
+                values.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+    }
+    values
+}
+
+/// A message of the `application/vnd.amazon.eventstream` replies
+#[derive(Debug)]
+struct EventMessage {
+    message_type: String,
+    /// The `:event-type` header, the `:exception-type` header of the exceptions or the
+    /// `:error-code` header of the errors
+    event_type: String,
+    /// The `:error-message` header of the errors
+    error_message: String,
+    payload: Vec<u8>,
+}
+
+/// Take the complete messages off the buffer, leaving a partial one for the next chunk
+fn decode_event_stream(buffer: &mut Vec<u8>) -> Result<Vec<EventMessage>> {
+    let mut messages = vec![];
+    while buffer.len() >= 12 {
+        let total_len = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+        let headers_len = u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]) as usize;
+        if total_len < 16 + headers_len {
+            bail!("Invalid event stream message");
+        }
+        if buffer.len() < total_len {
+            break;
+        }
+        let headers = parse_event_headers(&buffer[12..12 + headers_len])?;
+        let payload = buffer[12 + headers_len..total_len - 4].to_vec();
+        buffer.drain(..total_len);
+        let get = |name: &str| headers.get(name).cloned().unwrap_or_default();
+        let message_type = get(":message-type");
+        let event_type = match message_type.as_str() {
+            "exception" => get(":exception-type"),
+            "error" => get(":error-code"),
+            _ => get(":event-type"),
+        };
+        messages.push(EventMessage {
+            message_type,
+            event_type,
+            error_message: get(":error-message"),
+            payload,
+        });
+    }
+    Ok(messages)
+}
+
+/// The string headers of a message, the other types are skipped
+fn parse_event_headers(data: &[u8]) -> Result<HashMap<String, String>> {
+    let invalid = || anyhow!("Invalid event stream headers");
+    let mut headers = HashMap::new();
+    let mut i = 0;
+    while i < data.len() {
+        let name_len = *data.get(i).ok_or_else(invalid)? as usize;
+        let name = data.get(i + 1..i + 1 + name_len).ok_or_else(invalid)?;
+        i += 1 + name_len;
+        let value_type = *data.get(i).ok_or_else(invalid)?;
+        i += 1;
+        let value_len = match value_type {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,
+            9 => 16,
+            6 | 7 => {
+                let len = data.get(i..i + 2).ok_or_else(invalid)?;
+                i += 2;
+                u16::from_be_bytes([len[0], len[1]]) as usize
+            }
+            _ => bail!("Invalid event stream header type {value_type}"),
+        };
+        let value = data.get(i..i + value_len).ok_or_else(invalid)?;
+        i += value_len;
+        if value_type == 7 {
+            headers.insert(
+                String::from_utf8_lossy(name).to_string(),
+                String::from_utf8_lossy(value).to_string(),
+            );
+        }
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_request() {
+        // The example of deriving a signing key in the AWS documentation, then the get-vanilla
+        // case of the AWS Signature Version 4 test suite
+        assert_eq!(
+            to_hex(&signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20150830",
+                "us-east-1",
+                "iam"
+            )),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        };
+        let headers = sign_request(
+            &credentials,
+            "us-east-1",
+            "service",
+            "GET",
+            "example.amazonaws.com",
+            "/",
+            b"",
+            "20150830T123600Z",
+        );
+        assert_eq!(
+            headers.last().unwrap().1,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+        assert_eq!(
+            uri_encode("anthropic.claude-v2:1"),
+            "anthropic.claude-v2%3A1"
+        );
+    }
+
+    fn encode_event(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+        let mut encoded_headers = vec![];
+        for (name, value) in headers {
+            encoded_headers.push(name.len() as u8);
+            encoded_headers.extend_from_slice(name.as_bytes());
+            encoded_headers.push(7);
+            encoded_headers.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            encoded_headers.extend_from_slice(value.as_bytes());
+        }
+        let total_len = 16 + encoded_headers.len() + payload.len();
+        let mut data = vec![];
+        data.extend_from_slice(&(total_len as u32).to_be_bytes());
+        data.extend_from_slice(&(encoded_headers.len() as u32).to_be_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&encoded_headers);
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    #[test]
+    fn test_decode_event_stream() {
+        let payload = br#"{"delta":{"text":"Hi"}}"#;
+        let data = encode_event(
+            &[
+                (":message-type", "event"),
+                (":event-type", "contentBlockDelta"),
+            ],
+            payload,
+        );
+
+        let mut buffer = data[..20].to_vec();
+        assert!(decode_event_stream(&mut buffer).unwrap().is_empty());
+        buffer.extend_from_slice(&data[20..]);
+        let messages = decode_event_stream(&mut buffer).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].event_type, "contentBlockDelta");
+        assert_eq!(messages[0].payload, payload);
+        assert!(buffer.is_empty());
+
+        let mut buffer = encode_event(
+            &[
+                (":message-type", "error"),
+                (":error-code", "InternalServerException"),
+                (":error-message", "Try again"),
+            ],
+            b"",
+        );
+        let messages = decode_event_stream(&mut buffer).unwrap();
+        assert_eq!(messages[0].message_type, "error");
+        assert_eq!(messages[0].event_type, "InternalServerException");
+        assert_eq!(messages[0].error_message, "Try again");
+    }
+
+    #[test]
+    fn test_parse_ini_section() {
+        let content = "[default]\nregion = us-east-1\n\n[profile work]\nregion=eu-west-1\n";
+        let values = parse_ini_section(content, "profile work").unwrap();
+        assert_eq!(values["region"], "eu-west-1");
+        assert!(parse_ini_section(content, "missing").is_none());
+    }
+}
@@ -0,0 +1,184 @@
+use super::{Message, Model, SendData};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+
+/// A request and its reply, as recorded by `--record`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Interaction {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<usize>,
+    pub messages: Vec<Message>,
+    pub response: String,
+    /// All the replies of a request for several candidates, the first one is `response`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<String>,
+}
+
+impl Interaction {
+    fn new(model: &Model, data: &SendData, response: &str, candidates: Vec<String>) -> Self {
+        Self {
+            model: model.id(),
+            temperature: data.temperature,
+            n: data.n,
+            messages: data.messages.clone(),
+            response: response.to_string(),
+            candidates,
+        }
+    }
+
+    fn matches(&self, model: &Model, data: &SendData) -> bool {
+        self.model == model.id()
+            && self.temperature == data.temperature
+            && self.n == data.n
+            && serde_json::to_value(&self.messages).ok()
+                == serde_json::to_value(&data.messages).ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+/// The API interactions recorded to a file, to replay them without the network
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Cassette {
+    #[serde(skip)]
+    mode: Option<CassetteMode>,
+    #[serde(skip)]
+    path: PathBuf,
+    interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Start an empty cassette, the file is overwritten by the first interaction
+    pub fn record(path: &Path) -> Self {
+        Self {
+            mode: Some(CassetteMode::Record),
+            path: path.to_path_buf(),
+            interactions: vec![],
+        }
+    }
+
+    pub fn replay(path: &Path) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to load cassette at {}", path.display()))?;
+        let mut cassette: Self = serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid cassette at {}", path.display()))?;
+        cassette.mode = Some(CassetteMode::Replay);
+        cassette.path = path.to_path_buf();
+        Ok(cassette)
+    }
+
+    /// The recorded reply of the request when replaying, an error if there is none
+    pub fn lookup(&self, model: &Model, data: &SendData) -> Result<Option<String>> {
+        Ok(self.find(model, data)?.map(|v| v.response.clone()))
+    }
+
+    /// The recorded candidate replies of the request when replaying, an error if there are none
+    pub fn lookup_candidates(&self, model: &Model, data: &SendData) -> Result<Option<Vec<String>>> {
+        Ok(self.find(model, data)?.map(|v| {
+            if v.candidates.is_empty() {
+                vec![v.response.clone()]
+            } else {
+                v.candidates.clone()
+            }
+        }))
+    }
+
+    /// Add the interaction and save the cassette when recording
+    pub fn add(&mut self, model: &Model, data: &SendData, response: &str) -> Result<()> {
+        self.push(Interaction::new(model, data, response, vec![]))
+    }
+
+    /// Add the candidate replies of the request and save the cassette when recording
+    pub fn add_candidates(
+        &mut self,
+        model: &Model,
+        data: &SendData,
+        responses: &[String],
+    ) -> Result<()> {
+        let response = responses.first().map(|v| v.as_str()).unwrap_or_default();
+        self.push(Interaction::new(model, data, response, responses.to_vec()))
+    }
+
+    fn find(&self, model: &Model, data: &SendData) -> Result<Option<&Interaction>> {
+        if self.mode != Some(CassetteMode::Replay) {
+            return Ok(None);
+        }
+        self.interactions
+            .iter()
+            .find(|v| v.matches(model, data))
+            .map(Some)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No recorded reply of '{}' for this request in {}",
+                    model.id(),
+                    self.path.display()
+                )
+            })
+    }
+
+    fn push(&mut self, interaction: Interaction) -> Result<()> {
+        if self.mode != Some(CassetteMode::Record) {
+            return Ok(());
+        }
+        self.interactions.push(interaction);
+        let content = serde_yaml::to_string(&self).with_context(|| "Failed to serde cassette")?;
+        write(&self.path, content)
+            .with_context(|| format!("Failed to save cassette to {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::MessageRole;
+    use crate::config::Input;
+
+    #[test]
+    fn test_lookup() {
+        let model = Model::new("openai", "gpt-4");
+        let data = SendData {
+            messages: vec![Message::new(&Input::from_str("hi"))],
+            temperature: None,
+            stream: false,
+            n: None,
+            reasoning_effort: None,
+            thinking_budget: None,
+        };
+        let mut cassette = Cassette {
+            mode: Some(CassetteMode::Replay),
+            ..Default::default()
+        };
+        assert!(cassette.lookup(&model, &data).is_err());
+        cassette
+            .interactions
+            .push(Interaction::new(&model, &data, "hello", vec![]));
+        assert_eq!(
+            cassette.lookup(&model, &data).unwrap().as_deref(),
+            Some("hello")
+        );
+        let mut other = data.clone();
+        other.messages[0].role = MessageRole::System;
+        assert!(cassette.lookup(&model, &other).is_err());
+        let mut candidates = data.clone();
+        candidates.n = Some(2);
+        assert!(cassette.lookup_candidates(&model, &candidates).is_err());
+        let replies = vec!["a".to_string(), "b".to_string()];
+        cassette
+            .interactions
+            .push(Interaction::new(&model, &candidates, "a", replies.clone()));
+        assert_eq!(
+            cassette.lookup_candidates(&model, &candidates).unwrap(),
+            Some(replies)
+        );
+    }
+}
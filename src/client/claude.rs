@@ -1,6 +1,6 @@
 use super::{
-    patch_system_message, ClaudeClient, Client, ExtraConfig, Model, PromptType, SendData,
-    TokensCountFactors,
+    parse_stream_frame, patch_system_message, ApiError, ClaudeClient, Client, ExtraConfig, Model,
+    ModelConfig, PromptType, SendData, TokensCountFactors,
 };
 
 use crate::{
@@ -19,13 +19,14 @@ use serde_json::{json, Value};
 
 const API_BASE: &str = "https://api.anthropic.com/v1/messages";
 
-const MODELS: [(&str, usize, &str); 5] = [
+// (name, max_input_tokens, input_price, capabilities)
+const MODELS: [(&str, usize, f64, &str); 5] = [
     // https://docs.anthropic.com/claude/docs/models-overview
-    ("claude-3-opus-20240229", 200000, "text,vision"),
-    ("claude-3-sonnet-20240229", 200000, "text,vision"),
-    ("claude-2.1", 200000, "text"),
-    ("claude-2.0", 100000, "text"),
-    ("claude-instant-1.2", 100000, "text"),
+    ("claude-3-opus-20240229", 200000, 15.0, "text,vision"),
+    ("claude-3-sonnet-20240229", 200000, 3.0, "text,vision"),
+    ("claude-2.1", 200000, 8.0, "text"),
+    ("claude-2.0", 100000, 8.0, "text"),
+    ("claude-instant-1.2", 100000, 0.8, "text"),
 ];
 
 const TOKENS_COUNT_FACTORS: TokensCountFactors = (5, 2);
@@ -34,6 +35,9 @@ const TOKENS_COUNT_FACTORS: TokensCountFactors = (5, 2);
 pub struct ClaudeConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
+    /// Models added to the builtin ones, or overriding those with the same name
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
 }
 
@@ -41,6 +45,10 @@ pub struct ClaudeConfig {
 impl Client for ClaudeClient {
     client_common_fns!();
 
+    fn supports_prefill(&self) -> bool {
+        true
+    }
+
     async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String> {
         let builder = self.request_builder(client, data)?;
         send_message(builder).await
@@ -65,21 +73,36 @@ impl ClaudeClient {
 
     pub fn list_models(local_config: &ClaudeConfig) -> Vec<Model> {
         let client_name = Self::name(local_config);
+        let custom_models = local_config.models.iter().map(|v| {
+            Model::new(client_name, &v.name)
+                .set_capabilities(v.capabilities)
+                .set_no_stream(v.no_stream)
+                .set_input_price(v.input_price)
+                .set_output_price(v.output_price)
+                .set_max_input_tokens(v.max_input_tokens)
+                .set_extra_fields(v.extra_fields.clone())
+                .set_tokens_count_factors(TOKENS_COUNT_FACTORS)
+        });
         MODELS
             .into_iter()
-            .map(|(name, max_input_tokens, capabilities)| {
+            .filter(|(name, ..)| !local_config.models.iter().any(|v| v.name == *name))
+            .map(|(name, max_input_tokens, input_price, capabilities)| {
                 Model::new(client_name, name)
                     .set_capabilities(capabilities.into())
                     .set_max_input_tokens(Some(max_input_tokens))
+                    .set_input_price(Some(input_price))
                     .set_tokens_count_factors(TOKENS_COUNT_FACTORS)
             })
+            .chain(custom_models)
             .collect()
     }
 
     fn request_builder(&self, client: &ReqwestClient, data: SendData) -> Result<RequestBuilder> {
         let api_key = self.get_api_key().ok();
 
-        let body = build_body(data, self.model.name.clone())?;
+        let mut body = build_body(data, self.model.name.clone())?;
+
+        self.model.merge_extra_fields(&mut body);
 
         let url = API_BASE;
 
@@ -96,11 +119,15 @@ impl ClaudeClient {
 }
 
 async fn send_message(builder: RequestBuilder) -> Result<String> {
-    let data: Value = builder.send().await?.json().await?;
-    check_error(&data)?;
+    let res = builder.send().await?;
+    let status = res.status().as_u16();
+    let data: Value = res.json().await?;
+    check_error(status, &data)?;
 
-    let output = data["content"][0]["text"]
-        .as_str()
+    // The thinking blocks come before the text
+    let output = data["content"]
+        .as_array()
+        .and_then(|list| list.iter().find_map(|v| v["text"].as_str()))
         .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
 
     Ok(output.to_string())
@@ -112,8 +139,7 @@ async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHand
         match event {
             Ok(Event::Open) => {}
             Ok(Event::Message(message)) => {
-                let data: Value = serde_json::from_str(&message.data)?;
-                check_error(&data)?;
+                let data = parse_stream_frame(&message.data)?;
                 if let Some(typ) = data["type"].as_str() {
                     if typ == "content_block_delta" {
                         if let Some(text) = data["delta"]["text"].as_str() {
@@ -127,8 +153,9 @@ async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHand
                     EventSourceError::StreamEnded => {}
                     EventSourceError::InvalidStatusCode(code, res) => {
                         let data: Value = res.json().await?;
-                        check_error(&data)?;
-                        bail!("Invalid status code: {code}");
+                        check_error(code.as_u16(), &data)?;
+                        let message = format!("Invalid status code: {code}");
+                        return Err(ApiError::new(Some(code.as_u16()), None, message).into());
                     }
                     _ => {
                         bail!("{}", err);
@@ -147,6 +174,8 @@ fn build_body(data: SendData, model: String) -> Result<Value> {
         mut messages,
         temperature,
         stream,
+        thinking_budget,
+        ..
     } = data;
 
     patch_system_message(&mut messages);
@@ -202,7 +231,11 @@ fn build_body(data: SendData, model: String) -> Result<Value> {
         "messages": messages,
     });
 
-    if let Some(v) = temperature {
+    if let Some(budget_tokens) = thinking_budget {
+        // The temperature cannot be changed while thinking, and the output must leave room for it
+        body["max_tokens"] = (budget_tokens + 4096).into();
+        body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget_tokens });
+    } else if let Some(v) = temperature {
         body["temperature"] = (v / 2.0).into();
     }
     if stream {
@@ -211,13 +244,14 @@ fn build_body(data: SendData, model: String) -> Result<Value> {
     Ok(body)
 }
 
-fn check_error(data: &Value) -> Result<()> {
+fn check_error(status: u16, data: &Value) -> Result<()> {
     if let Some(error) = data["error"].as_object() {
-        if let (Some(typ), Some(message)) = (error["type"].as_str(), error["message"].as_str()) {
-            bail!("{typ}: {message}");
-        } else {
-            bail!("{}", Value::Object(error.clone()))
-        }
+        let typ = error["type"].as_str();
+        let message = match (typ, error["message"].as_str()) {
+            (Some(typ), Some(message)) => format!("{typ}: {message}"),
+            _ => Value::Object(error.clone()).to_string(),
+        };
+        return Err(ApiError::new(Some(status), typ, message).into());
     }
     Ok(())
 }
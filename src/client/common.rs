@@ -1,15 +1,18 @@
-use super::{openai::OpenAIConfig, ClientConfig, Message, MessageContent, Model};
+use super::{
+    init_client, init_client_with_model, is_stream_unsupported, openai::OpenAIConfig, rate_limit,
+    ApiError, ClientConfig, Message, MessageContent, MessageRole, Model,
+};
 
 use crate::{
     config::{GlobalConfig, Input},
     render::ReplyHandler,
     utils::{
-        init_tokio_runtime, prompt_input_integer, prompt_input_string, tokenize, AbortSignal,
-        PromptKind,
+        init_tokio_runtime, prompt_input_integer, prompt_input_string, sanitize_output, tokenize,
+        AbortSignal, PromptKind,
     },
 };
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use reqwest::{Client as ReqwestClient, ClientBuilder, Proxy, RequestBuilder};
 use serde::Deserialize;
@@ -17,6 +20,9 @@ use serde_json::{json, Value};
 use std::{env, future::Future, time::Duration};
 use tokio::time::sleep;
 
+/// How many times an interrupted stream is resumed before giving up
+const MAX_STREAM_RESUMES: usize = 2;
+
 #[macro_export]
 macro_rules! register_client {
     (
@@ -52,8 +58,7 @@ macro_rules! register_client {
             impl $client {
                 pub const NAME: &'static str = $name;
 
-                pub fn init(global_config: &$crate::config::GlobalConfig) -> Option<Box<dyn Client>> {
-                    let model = global_config.read().model.clone();
+                pub fn init(global_config: &$crate::config::GlobalConfig, model: &$crate::client::Model) -> Option<Box<dyn Client>> {
                     let config = global_config.read().clients.iter().find_map(|client_config| {
                         if let ClientConfig::$config(c) = client_config {
                             if Self::name(c) == &model.client_name {
@@ -66,7 +71,7 @@ macro_rules! register_client {
                     Some(Box::new(Self {
                         global_config: global_config.clone(),
                         config,
-                        model,
+                        model: model.clone(),
                     }))
                 }
 
@@ -78,10 +83,23 @@ macro_rules! register_client {
         )+
 
         pub fn init_client(config: &$crate::config::GlobalConfig) -> anyhow::Result<Box<dyn Client>> {
+            let model = config.read().model.clone();
+            init_client_with_model(config, &model)
+        }
+
+        pub fn init_client_with_model(config: &$crate::config::GlobalConfig, model: &$crate::client::Model) -> anyhow::Result<Box<dyn Client>> {
+            {
+                let config = config.read();
+                if config.offline && !$crate::client::is_local_model(&config, model) {
+                    anyhow::bail!(
+                        "'{}' is a remote model, which is unavailable in offline mode. Use a model served on this machine, e.g. by Ollama.",
+                        model.id()
+                    );
+                }
+            }
             None
-            $(.or_else(|| $client::init(config)))+
+            $(.or_else(|| $client::init(config, model)))+
             .ok_or_else(|| {
-                let model = config.read().model.clone();
                 anyhow::anyhow!("Unknown client '{}'", &model.client_name)
             })
         }
@@ -114,6 +132,7 @@ macro_rules! register_client {
         }
 
         pub fn list_models(config: &$crate::config::Config) -> Vec<$crate::client::Model> {
+            discover_models(config, None);
             config
                 .clients
                 .iter()
@@ -124,6 +143,21 @@ macro_rules! register_client {
                 .collect()
         }
 
+        /// The models of the client of `<client>[:<model>]` only, so the other clients are not
+        /// asked for the models they discover
+        pub fn list_client_models(config: &$crate::config::Config, value: &str) -> Vec<$crate::client::Model> {
+            let client_name = value.split_once(':').map(|(v, _)| v).unwrap_or(value);
+            discover_models(config, Some(client_name));
+            config
+                .clients
+                .iter()
+                .flat_map(|v| match v {
+                    $(ClientConfig::$config(c) if $client::name(c) == client_name => $client::list_models(c),)+
+                    _ => vec![],
+                })
+                .collect()
+        }
+
     };
 }
 
@@ -178,6 +212,15 @@ macro_rules! openai_compatible_client {
                 let builder = self.request_builder(client, data)?;
                 $crate::client::openai::openai_send_message_streaming(builder, handler).await
             }
+
+            async fn send_message_candidates_inner(
+                &self,
+                client: &reqwest::Client,
+                data: $crate::client::SendData,
+            ) -> anyhow::Result<Vec<String>> {
+                let builder = self.request_builder(client, data)?;
+                $crate::client::openai::openai_send_message_candidates(builder).await
+            }
         }
     };
 }
@@ -199,8 +242,17 @@ macro_rules! config_get_fn {
     };
 }
 
+async fn watch_abort(abort: AbortSignal) {
+    loop {
+        if abort.aborted() {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
 #[async_trait]
-pub trait Client {
+pub trait Client: Sync {
     fn config(&self) -> (&GlobalConfig, &Option<ExtraConfig>);
 
     fn models(&self) -> Vec<Model>;
@@ -212,6 +264,7 @@ pub trait Client {
     fn build_client(&self) -> Result<ReqwestClient> {
         let mut builder = ReqwestClient::builder();
         let options = self.config().1;
+        let lite = self.config().0.read().lite;
         let timeout = options
             .as_ref()
             .and_then(|v| v.connect_timeout)
@@ -220,35 +273,78 @@ pub trait Client {
         builder = set_proxy(builder, &proxy)?;
         let client = builder
             .connect_timeout(Duration::from_secs(timeout))
+            .gzip(lite)
+            .brotli(lite)
             .build()
             .with_context(|| "Failed to build client")?;
         Ok(client)
     }
 
     fn send_message(&self, input: Input) -> Result<String> {
+        init_tokio_runtime()?.block_on(self.send_message_async(input))
+    }
+
+    /// Like `send_message`, but gives up with an empty reply once aborted, e.g. at the
+    /// `--max-time` deadline
+    fn send_message_with_abort(&self, input: Input, abort: AbortSignal) -> Result<String> {
+        init_tokio_runtime()?.block_on(async {
+            tokio::select! {
+                ret = self.send_message_async(input) => ret,
+                _ = watch_abort(abort) => Ok(String::new()),
+            }
+        })
+    }
+
+    async fn send_message_async(&self, input: Input) -> Result<String> {
+        let global_config = self.config().0;
+        if global_config.read().dry_run {
+            let content = global_config.read().echo_messages(&input);
+            return Ok(content);
+        }
+        let client = self.build_client()?;
+        let data = global_config
+            .read()
+            .prepare_send_data(&input, self.model(), false)?;
+        if let Some(output) = replay_reply(global_config, self.model(), &data)? {
+            return Ok(output);
+        }
+        self.acquire_rate_limit(&data).await;
+        let output = self
+            .send_message_inner(&client, data.clone())
+            .await
+            .with_context(|| "Failed to get answer")?;
+        let output = sanitize_output(&output);
+        record_reply(global_config, self.model(), &data, &output)?;
+        Ok(output)
+    }
+
+    fn send_message_candidates(&self, input: Input, n: usize) -> Result<Vec<String>> {
         init_tokio_runtime()?.block_on(async {
             let global_config = self.config().0;
             if global_config.read().dry_run {
                 let content = global_config.read().echo_messages(&input);
-                return Ok(content);
+                return Ok(vec![content]);
             }
             let client = self.build_client()?;
-            let data = global_config.read().prepare_send_data(&input, false)?;
-            self.send_message_inner(&client, data)
+            let mut data = global_config
+                .read()
+                .prepare_send_data(&input, self.model(), false)?;
+            data.n = Some(n);
+            if let Some(outputs) = replay_candidates(global_config, self.model(), &data)? {
+                return Ok(outputs);
+            }
+            self.acquire_rate_limit(&data).await;
+            let outputs = self
+                .send_message_candidates_inner(&client, data.clone())
                 .await
-                .with_context(|| "Failed to get answer")
+                .with_context(|| "Failed to get answer")?;
+            let outputs: Vec<String> = outputs.iter().map(|v| sanitize_output(v)).collect();
+            record_candidates(global_config, self.model(), &data, &outputs)?;
+            Ok(outputs)
         })
     }
 
     fn send_message_streaming(&self, input: &Input, handler: &mut ReplyHandler) -> Result<()> {
-        async fn watch_abort(abort: AbortSignal) {
-            loop {
-                if abort.aborted() {
-                    break;
-                }
-                sleep(Duration::from_millis(100)).await;
-            }
-        }
         let abort = handler.get_abort();
         let input = input.clone();
         init_tokio_runtime()?.block_on(async move {
@@ -265,8 +361,63 @@ pub trait Client {
                         return Ok(());
                     }
                     let client = self.build_client()?;
-                    let data = global_config.read().prepare_send_data(&input, true)?;
-                    self.send_message_streaming_inner(&client, handler, data).await
+                    let data = global_config.read().prepare_send_data(&input, self.model(), true)?;
+                    if let Some(output) = replay_reply(global_config, self.model(), &data)? {
+                        for token in tokenize(&output) {
+                            handler.text(&token)?;
+                        }
+                        return Ok(());
+                    }
+                    self.acquire_rate_limit(&data).await;
+                    let mut resumed_data = data.clone();
+                    let mut resumes = 0;
+                    loop {
+                        match self
+                            .send_message_streaming_inner(&client, handler, resumed_data)
+                            .await
+                        {
+                            Ok(()) => break,
+                            Err(err)
+                                if resumes == 0
+                                    && handler.get_buffer().is_empty()
+                                    && is_stream_unsupported(&err) =>
+                            {
+                                debug!("Fall back to a non-streamed request: {err}");
+                                if let Err(err) = global_config.write().remember_no_stream(self.model()) {
+                                    debug!("Failed to cache the capabilities: {err}");
+                                }
+                                let mut data = data.clone();
+                                data.stream = false;
+                                let output = self.send_message_inner(&client, data).await?;
+                                handler.text(&sanitize_output(&output))?;
+                                break;
+                            }
+                            Err(err)
+                                if resumes < MAX_STREAM_RESUMES
+                                    && !handler.get_buffer().is_empty()
+                                    && !abort.aborted() =>
+                            {
+                                resumes += 1;
+                                debug!("Resume the interrupted stream: {err}");
+                                self.acquire_rate_limit(&data).await;
+                                resumed_data = data.clone();
+                                if self.supports_prefill() {
+                                    let partial = handler.resume_continue();
+                                    resumed_data.messages.push(Message {
+                                        role: MessageRole::Assistant,
+                                        content: MessageContent::Text(partial),
+                                        pinned: false,
+                                        model: None,
+                                        truncated: false,
+                                    });
+                                } else {
+                                    handler.resume_dedupe();
+                                }
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    record_reply(global_config, self.model(), &data, handler.get_buffer())
                 } => {
                     handler.done()?;
                     ret.with_context(|| "Failed to get answer")
@@ -279,6 +430,17 @@ pub trait Client {
         })
     }
 
+    /// Wait for the `rpm` and `tpm` limits of the client before sending the request
+    async fn acquire_rate_limit(&self, data: &SendData) {
+        let tokens = self.model().total_tokens(&data.messages);
+        rate_limit::acquire(&self.model().client_name, self.config().1, tokens).await
+    }
+
+    /// Whether the API continues a trailing assistant message, used to resume interrupted streams
+    fn supports_prefill(&self) -> bool {
+        false
+    }
+
     async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String>;
 
     async fn send_message_streaming_inner(
@@ -287,6 +449,20 @@ pub trait Client {
         handler: &mut ReplyHandler,
         data: SendData,
     ) -> Result<()>;
+
+    /// Fallback for providers that cannot return multiple choices in one call
+    async fn send_message_candidates_inner(
+        &self,
+        client: &ReqwestClient,
+        data: SendData,
+    ) -> Result<Vec<String>> {
+        let n = data.n.unwrap_or(1);
+        let mut outputs = vec![];
+        for _ in 0..n {
+            outputs.push(self.send_message_inner(client, data.clone()).await?);
+        }
+        Ok(outputs)
+    }
 }
 
 impl Default for ClientConfig {
@@ -299,17 +475,91 @@ impl Default for ClientConfig {
 pub struct ExtraConfig {
     pub proxy: Option<String>,
     pub connect_timeout: Option<u64>,
+    /// Requests per minute, further requests wait
+    pub rpm: Option<usize>,
+    /// Input tokens per minute, further requests wait
+    pub tpm: Option<usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SendData {
     pub messages: Vec<Message>,
     pub temperature: Option<f64>,
     pub stream: bool,
+    pub n: Option<usize>,
+    /// Forwarded as the OpenAI `reasoning_effort`
+    pub reasoning_effort: Option<String>,
+    /// Forwarded as the Anthropic `thinking.budget_tokens`
+    pub thinking_budget: Option<usize>,
 }
 
 pub type PromptType<'a> = (&'a str, &'a str, bool, PromptKind);
 
+/// The reply recorded in the cassette of `--replay`
+pub(crate) fn replay_reply(
+    config: &GlobalConfig,
+    model: &Model,
+    data: &SendData,
+) -> Result<Option<String>> {
+    match &config.read().cassette {
+        Some(cassette) => cassette.lookup(model, data),
+        None => Ok(None),
+    }
+}
+
+/// The candidate replies recorded in the cassette of `--replay`
+fn replay_candidates(
+    config: &GlobalConfig,
+    model: &Model,
+    data: &SendData,
+) -> Result<Option<Vec<String>>> {
+    match &config.read().cassette {
+        Some(cassette) => cassette.lookup_candidates(model, data),
+        None => Ok(None),
+    }
+}
+
+/// Add the candidate replies to the cassette of `--record`
+fn record_candidates(
+    config: &GlobalConfig,
+    model: &Model,
+    data: &SendData,
+    outputs: &[String],
+) -> Result<()> {
+    match config.write().cassette.as_mut() {
+        Some(cassette) => cassette.add_candidates(model, data, outputs),
+        None => Ok(()),
+    }
+}
+
+/// Add the reply to the cassette of `--record`
+pub(crate) fn record_reply(
+    config: &GlobalConfig,
+    model: &Model,
+    data: &SendData,
+    output: &str,
+) -> Result<()> {
+    match config.write().cassette.as_mut() {
+        Some(cassette) => cassette.add(model, data, output),
+        None => Ok(()),
+    }
+}
+
+pub fn init_client_for_input(config: &GlobalConfig, input: &Input) -> Result<Box<dyn Client>> {
+    let fallback = config.read().fallback_model(input)?;
+    match fallback {
+        Some(model) => {
+            eprintln!(
+                "The input exceeds the context window of '{}', use '{}' instead.",
+                config.read().model.id(),
+                model.id()
+            );
+            init_client_with_model(config, &model)
+        }
+        None => init_client(config),
+    }
+}
+
 pub fn create_config(list: &[PromptType], client: &str) -> Result<Value> {
     let mut config = json!({
         "type": client,
@@ -348,6 +598,27 @@ where
     Ok(())
 }
 
+/// Parse a frame of a streaming response.
+///
+/// Providers may send an error frame in the middle of the stream, surface it as a readable error.
+pub fn parse_stream_frame(data: &str) -> Result<Value> {
+    let value: Value =
+        serde_json::from_str(data).map_err(|_| anyhow!("Invalid stream data: {data}"))?;
+    let error = &value["error"];
+    if let Some(message) = error["message"].as_str() {
+        let typ = error["type"].as_str().or_else(|| error["status"].as_str());
+        let message = match typ {
+            Some(typ) => format!("{typ}: {message}"),
+            None => message.to_string(),
+        };
+        let code = error["code"].as_str().or(typ);
+        return Err(ApiError::new(None, code, message).into());
+    } else if let Some(message) = error.as_str() {
+        return Err(ApiError::new(None, None, message).into());
+    }
+    Ok(value)
+}
+
 pub fn patch_system_message(messages: &mut Vec<Message>) {
     if messages[0].role.is_system() {
         let system_message = messages.remove(0);
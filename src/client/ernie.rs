@@ -1,4 +1,7 @@
-use super::{patch_system_message, Client, ErnieClient, ExtraConfig, Model, PromptType, SendData};
+use super::{
+    parse_stream_frame, patch_system_message, ApiError, Client, ErnieClient, ExtraConfig, Model,
+    PromptType, SendData,
+};
 
 use crate::{render::ReplyHandler, utils::PromptKind};
 
@@ -142,7 +145,8 @@ async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHand
         match event {
             Ok(Event::Open) => {}
             Ok(Event::Message(message)) => {
-                let data: Value = serde_json::from_str(&message.data)?;
+                let data = parse_stream_frame(&message.data)?;
+                check_error(&data)?;
                 if let Some(text) = data["result"].as_str() {
                     handler.text(text)?;
                 }
@@ -188,9 +192,10 @@ fn check_error(data: &Value) -> Result<()> {
             if code == 110 {
                 unsafe { ACCESS_TOKEN = String::new() }
             }
-            bail!("{err_msg}. err_code: {code}");
+            let message = format!("{err_msg}. err_code: {code}");
+            return Err(ApiError::new(None, Some(code.to_string().as_str()), message).into());
         } else {
-            bail!("{err_msg}");
+            return Err(ApiError::new(None, None, err_msg).into());
         }
     }
     Ok(())
@@ -201,6 +206,7 @@ fn build_body(data: SendData, _model: String) -> Value {
         mut messages,
         temperature,
         stream,
+        ..
     } = data;
 
     patch_system_message(&mut messages);
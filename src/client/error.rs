@@ -0,0 +1,227 @@
+/// Failure types that scripts can branch on through the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Auth,
+    Quota,
+    RateLimit,
+    ContextLength,
+    ContentFilter,
+    ModelNotFound,
+    Network,
+    Other,
+}
+
+impl ErrorKind {
+    /// Classify an error by the HTTP status and the error code of the provider
+    pub fn classify(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<ApiError>() {
+                return err
+                    .code
+                    .as_deref()
+                    .and_then(Self::from_code)
+                    .or_else(|| err.status.map(Self::from_status))
+                    .unwrap_or(Self::Other);
+            }
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                if err.is_connect() || err.is_timeout() || err.is_request() {
+                    return Self::Network;
+                }
+                if let Some(status) = err.status() {
+                    return Self::from_status(status.as_u16());
+                }
+            }
+        }
+        Self::Other
+    }
+
+    /// The error codes of OpenAI, Claude, Gemini, Qianwen, Ernie and Bedrock, which tell apart
+    /// the failures sharing a status, e.g. the quota and the rate limit behind 429
+    fn from_code(code: &str) -> Option<Self> {
+        let kind = match code.to_lowercase().as_str() {
+            "context_length_exceeded" | "string_above_max_length" | "request_too_large" => {
+                Self::ContextLength
+            }
+            "content_filter" | "content_policy_violation" | "data_inspection_failed" => {
+                Self::ContentFilter
+            }
+            "model_not_found" | "not_found_error" | "not_found" | "resourcenotfoundexception" => {
+                Self::ModelNotFound
+            }
+            "insufficient_quota"
+            | "billing_hard_limit_reached"
+            | "arrearage"
+            | "17"
+            | "servicequotaexceededexception" => Self::Quota,
+            "rate_limit_exceeded"
+            | "rate_limit_error"
+            | "overloaded_error"
+            | "resource_exhausted"
+            | "throttling"
+            | "throttling.ratequota"
+            | "throttlingexception"
+            | "18"
+            | "336501"
+            | "336502" => Self::RateLimit,
+            "invalid_api_key"
+            | "authentication_error"
+            | "permission_error"
+            | "unauthenticated"
+            | "permission_denied"
+            | "api_key_invalid"
+            | "invalidapikey"
+            | "accessdeniedexception"
+            | "unrecognizedclientexception"
+            | "expiredtokenexception"
+            | "110"
+            | "111" => Self::Auth,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 => Self::Auth,
+            402 => Self::Quota,
+            404 => Self::ModelNotFound,
+            413 => Self::ContextLength,
+            429 | 529 => Self::RateLimit,
+            408 | 502 | 503 | 504 => Self::Network,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::Auth => 3,
+            Self::Quota => 4,
+            Self::RateLimit => 5,
+            Self::ContextLength => 6,
+            Self::ContentFilter => 7,
+            Self::Network => 8,
+            Self::ModelNotFound => 9,
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        let hint = match self {
+            Self::Auth => "Authentication failed, check the API key of the client.",
+            Self::Quota => {
+                "The quota of the account is exhausted, check the billing of the provider."
+            }
+            Self::RateLimit => "Rate limited by the provider, wait a moment and try again.",
+            Self::ContextLength => "The input is too long for the model.",
+            Self::ContentFilter => {
+                "The request or reply was blocked by the content filter of the provider."
+            }
+            Self::ModelNotFound => {
+                "The model is unavailable, it may have been retired by the provider."
+            }
+            Self::Network => {
+                "Unable to reach the provider, check the network or the proxy settings."
+            }
+            Self::Other => return None,
+        };
+        Some(hint)
+    }
+}
+
+/// Whether the provider rejected the request because the model cannot stream
+pub fn is_stream_unsupported(err: &anyhow::Error) -> bool {
+    let message = format!("{err:#}").to_lowercase();
+    message.contains("stream")
+        && [
+            "not supported",
+            "unsupported",
+            "does not support",
+            "not allowed",
+        ]
+        .iter()
+        .any(|v| message.contains(v))
+}
+
+/// The error of a request, with the HTTP status and the error code the provider returned, or
+/// found before sending it, such as an input exceeding the context window
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Option<u16>,
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: Option<u16>, code: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: code.map(|v| v.to_string()),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Build the error for a request or reply blocked by the content filter of the provider
+pub fn content_filter_error(categories: &[String]) -> anyhow::Error {
+    let message = if categories.is_empty() {
+        "Blocked by the content filter".to_string()
+    } else {
+        format!(
+            "Blocked by the content filter, categories: {}",
+            categories.join(", ")
+        )
+    };
+    ApiError::new(None, Some("content_filter"), message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            (Some(401), None, ErrorKind::Auth),
+            (Some(403), Some("PERMISSION_DENIED"), ErrorKind::Auth),
+            (Some(429), Some("insufficient_quota"), ErrorKind::Quota),
+            (Some(429), Some("rate_limit_exceeded"), ErrorKind::RateLimit),
+            (Some(429), None, ErrorKind::RateLimit),
+            (Some(529), Some("overloaded_error"), ErrorKind::RateLimit),
+            (
+                Some(400),
+                Some("context_length_exceeded"),
+                ErrorKind::ContextLength,
+            ),
+            (Some(400), Some("content_filter"), ErrorKind::ContentFilter),
+            (Some(404), Some("model_not_found"), ErrorKind::ModelNotFound),
+            (Some(404), None, ErrorKind::ModelNotFound),
+            (Some(400), Some("invalid_request_error"), ErrorKind::Other),
+            (Some(500), None, ErrorKind::Other),
+            (Some(503), None, ErrorKind::Network),
+            (None, Some("Throttling.RateQuota"), ErrorKind::RateLimit),
+            (None, Some("110"), ErrorKind::Auth),
+            (None, None, ErrorKind::Other),
+        ];
+        for (status, code, kind) in cases {
+            let err = anyhow::Error::from(ApiError::new(status, code, "failed"));
+            assert_eq!(ErrorKind::classify(&err), kind, "{status:?} {code:?}");
+        }
+        let err = anyhow::Error::from(ApiError::new(Some(401), None, "denied"))
+            .context("Failed to get answer");
+        assert_eq!(ErrorKind::classify(&err), ErrorKind::Auth);
+        let err = anyhow::anyhow!("401 unauthorized, rate limit");
+        assert_eq!(ErrorKind::classify(&err), ErrorKind::Other);
+        assert_eq!(
+            ErrorKind::classify(&content_filter_error(&[])),
+            ErrorKind::ContentFilter
+        );
+    }
+}
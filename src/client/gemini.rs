@@ -7,6 +7,7 @@ use anyhow::Result;
 use async_trait::async_trait;
 use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
+use serde_json::Value;
 
 const API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta/models/";
 
@@ -22,6 +23,8 @@ const TOKENS_COUNT_FACTORS: TokensCountFactors = (5, 2);
 pub struct GeminiConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
+    /// Replaces the default `BLOCK_ONLY_HIGH` threshold of every harm category
+    pub safety_settings: Option<Value>,
     pub extra: Option<ExtraConfig>,
 }
 
@@ -72,7 +75,11 @@ impl GeminiClient {
             false => "generateContent",
         };
 
-        let body = build_body(data, self.model.name.clone())?;
+        let body = build_body(
+            data,
+            self.model.name.clone(),
+            self.config.safety_settings.clone(),
+        )?;
 
         let model = self.model.name.clone();
 
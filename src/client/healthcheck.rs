@@ -0,0 +1,193 @@
+use super::{
+    init_client_with_model, is_local_model, list_models, Client, ErrorKind, Message, SendData,
+};
+
+use crate::config::{GlobalConfig, Input};
+use crate::utils::init_tokio_runtime;
+
+use anyhow::{anyhow, Result};
+use futures_util::future::join_all;
+use std::time::{Duration, Instant};
+
+/// How long a client has to answer the ping
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The result of pinging a client with a tiny request
+#[derive(Debug)]
+pub struct HealthReport {
+    pub client_name: String,
+    pub model: String,
+    pub status: HealthStatus,
+    pub latency: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HealthStatus {
+    Ok,
+    AuthFailed(String),
+    Unreachable(String),
+    TimedOut,
+    Failed(String),
+    /// A remote client, not pinged in offline mode
+    Skipped,
+}
+
+impl HealthStatus {
+    /// The status of a failed ping, by the kind of its error
+    fn from_error(kind: ErrorKind, message: String) -> Self {
+        match kind {
+            ErrorKind::Auth => Self::AuthFailed(message),
+            ErrorKind::Network => Self::Unreachable(message),
+            _ => Self::Failed(message),
+        }
+    }
+}
+
+impl HealthReport {
+    pub fn is_ok(&self) -> bool {
+        matches!(self.status, HealthStatus::Ok | HealthStatus::Skipped)
+    }
+
+    pub fn summary(&self) -> String {
+        let latency = self
+            .latency
+            .map(|v| format!("{}ms", v.as_millis()))
+            .unwrap_or_else(|| "-".into());
+        let status = match &self.status {
+            HealthStatus::Ok => "ok".to_string(),
+            HealthStatus::AuthFailed(err) => format!("auth failed: {err}"),
+            HealthStatus::Unreachable(err) => format!("unreachable: {err}"),
+            HealthStatus::TimedOut => format!("timed out after {}s", HEALTHCHECK_TIMEOUT.as_secs()),
+            HealthStatus::Failed(err) => format!("error: {err}"),
+            HealthStatus::Skipped => "skipped in offline mode".to_string(),
+        };
+        format!(
+            "{:<16}{:<40}{:>8}  {status}",
+            self.client_name, self.model, latency
+        )
+    }
+}
+
+/// Ping every configured client concurrently, with the first model of each. The remote clients
+/// are skipped in offline mode.
+pub fn healthcheck(config: &GlobalConfig) -> Result<Vec<HealthReport>> {
+    let mut models = list_models(&config.read());
+    let mut seen = vec![];
+    models.retain(|v| {
+        if seen.contains(&v.client_name) {
+            return false;
+        }
+        seen.push(v.client_name.clone());
+        true
+    });
+    if models.is_empty() {
+        return Err(anyhow!("No clients configured"));
+    }
+    let offline = config.read().offline;
+    let clients: Vec<_> = models
+        .iter()
+        .map(|model| {
+            let skipped = offline && !is_local_model(&config.read(), model);
+            let client = (!skipped).then(|| init_client_with_model(config, model));
+            (model, client)
+        })
+        .collect();
+    let reports = init_tokio_runtime()?.block_on(async {
+        join_all(clients.iter().map(|(model, client)| async move {
+            let (status, latency) = match client {
+                Some(Ok(client)) => ping(client.as_ref()).await,
+                Some(Err(err)) => (HealthStatus::Failed(err.to_string()), None),
+                None => (HealthStatus::Skipped, None),
+            };
+            HealthReport {
+                client_name: model.client_name.clone(),
+                model: model.name.clone(),
+                status,
+                latency,
+            }
+        }))
+        .await
+    });
+    Ok(reports)
+}
+
+async fn ping(client: &dyn Client) -> (HealthStatus, Option<Duration>) {
+    let data = SendData {
+        messages: vec![Message::new(&Input::from_str("Reply with OK."))],
+        temperature: None,
+        stream: false,
+        n: None,
+        reasoning_effort: None,
+        thinking_budget: None,
+    };
+    let http_client = match client.build_client() {
+        Ok(v) => v,
+        Err(err) => return (HealthStatus::Failed(err.to_string()), None),
+    };
+    let start = Instant::now();
+    let ret = tokio::time::timeout(
+        HEALTHCHECK_TIMEOUT,
+        client.send_message_inner(&http_client, data),
+    )
+    .await;
+    let latency = start.elapsed();
+    match ret {
+        Ok(Ok(_)) => (HealthStatus::Ok, Some(latency)),
+        Ok(Err(err)) => {
+            let status = HealthStatus::from_error(ErrorKind::classify(&err), format!("{err:#}"));
+            (status, Some(latency))
+        }
+        Err(_) => (HealthStatus::TimedOut, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(status: HealthStatus, latency: Option<Duration>) -> HealthReport {
+        HealthReport {
+            client_name: "openai".into(),
+            model: "gpt-4".into(),
+            status,
+            latency,
+        }
+    }
+
+    #[test]
+    fn test_from_error() {
+        assert_eq!(
+            HealthStatus::from_error(ErrorKind::Auth, "401".into()),
+            HealthStatus::AuthFailed("401".into())
+        );
+        assert_eq!(
+            HealthStatus::from_error(ErrorKind::Network, "refused".into()),
+            HealthStatus::Unreachable("refused".into())
+        );
+        assert_eq!(
+            HealthStatus::from_error(ErrorKind::RateLimit, "429".into()),
+            HealthStatus::Failed("429".into())
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let ok = report(HealthStatus::Ok, Some(Duration::from_millis(120)));
+        assert!(ok.is_ok());
+        assert_eq!(
+            ok.summary(),
+            format!("{:<16}{:<40}{:>8}  ok", "openai", "gpt-4", "120ms")
+        );
+        let skipped = report(HealthStatus::Skipped, None);
+        assert!(skipped.is_ok());
+        assert!(skipped
+            .summary()
+            .ends_with("       -  skipped in offline mode"));
+        let failed = report(HealthStatus::AuthFailed("invalid api key".into()), None);
+        assert!(!failed.is_ok());
+        assert!(failed.summary().ends_with("auth failed: invalid api key"));
+        assert!(report(HealthStatus::TimedOut, None)
+            .summary()
+            .ends_with("timed out after 30s"));
+    }
+}
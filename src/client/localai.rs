@@ -44,8 +44,12 @@ impl LocalAIClient {
             .map(|v| {
                 Model::new(client_name, &v.name)
                     .set_capabilities(v.capabilities)
+                    .set_no_stream(v.no_stream)
+                    .set_input_price(v.input_price)
+                    .set_output_price(v.output_price)
                     .set_max_input_tokens(v.max_input_tokens)
                     .set_extra_fields(v.extra_fields.clone())
+                    .set_reasoning_effort(v.reasoning_effort.clone())
                     .set_tokens_count_factors(OPENAI_TOKENS_COUNT_FACTORS)
             })
             .collect()
@@ -6,6 +6,15 @@ use serde::{Deserialize, Serialize};
 pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
+    /// Pinned messages of a session are kept when the session is compressed
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pinned: bool,
+    /// The model that produced an assistant message of a session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// An assistant message of a session cut short by Ctrl-C
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub truncated: bool,
 }
 
 impl Message {
@@ -13,10 +22,27 @@ impl Message {
         Self {
             role: MessageRole::User,
             content: input.to_message_content(),
+            pinned: false,
+            model: None,
+            truncated: false,
+        }
+    }
+
+    pub fn system(text: &str) -> Self {
+        Self {
+            role: MessageRole::System,
+            content: MessageContent::Text(text.to_string()),
+            pinned: false,
+            model: None,
+            truncated: false,
         }
     }
 }
 
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MessageRole {
@@ -40,7 +66,7 @@ impl MessageRole {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum MessageContent {
     Text(String),
@@ -87,14 +113,14 @@ impl MessageContent {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum MessageContentPart {
     Text { text: String },
     ImageUrl { image_url: ImageUrl },
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ImageUrl {
     pub url: String,
 }
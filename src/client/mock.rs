@@ -0,0 +1,104 @@
+use super::{
+    message::*, Client, ExtraConfig, MockClient, Model, ModelConfig, PromptType, SendData,
+};
+
+use crate::{
+    render::ReplyHandler,
+    utils::{tokenize, PromptKind},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client as ReqwestClient;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const DEFAULT_MODEL: &str = "mock";
+const DEFAULT_RESPONSE: &str = "This is a mock reply to: {{input}}";
+
+/// A client that answers without any API, for scripts, tests and demos
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct MockConfig {
+    pub name: Option<String>,
+    /// The reply, `{{input}}` is replaced by the last user message and `{{model}}` by the model
+    pub response: Option<String>,
+    /// Milliseconds to wait before replying
+    pub latency: Option<u64>,
+    /// Milliseconds between the streamed tokens
+    pub chunk_delay: Option<u64>,
+    #[serde(default)]
+    pub models: Vec<ModelConfig>,
+    pub extra: Option<ExtraConfig>,
+}
+
+#[async_trait]
+impl Client for MockClient {
+    client_common_fns!();
+
+    async fn send_message_inner(&self, _client: &ReqwestClient, data: SendData) -> Result<String> {
+        self.wait(self.config.latency).await;
+        Ok(self.build_response(&data))
+    }
+
+    async fn send_message_streaming_inner(
+        &self,
+        _client: &ReqwestClient,
+        handler: &mut ReplyHandler,
+        data: SendData,
+    ) -> Result<()> {
+        self.wait(self.config.latency).await;
+        for token in tokenize(&self.build_response(&data)) {
+            self.wait(self.config.chunk_delay).await;
+            handler.text(&token)?;
+        }
+        Ok(())
+    }
+}
+
+impl MockClient {
+    pub const PROMPTS: [PromptType<'static>; 1] =
+        [("response", "Response:", false, PromptKind::String)];
+
+    pub fn list_models(local_config: &MockConfig) -> Vec<Model> {
+        let client_name = Self::name(local_config);
+
+        if local_config.models.is_empty() {
+            return vec![Model::new(client_name, DEFAULT_MODEL)];
+        }
+        local_config
+            .models
+            .iter()
+            .map(|v| {
+                Model::new(client_name, &v.name)
+                    .set_capabilities(v.capabilities)
+                    .set_no_stream(v.no_stream)
+                    .set_input_price(v.input_price)
+                    .set_output_price(v.output_price)
+                    .set_max_input_tokens(v.max_input_tokens)
+            })
+            .collect()
+    }
+
+    fn build_response(&self, data: &SendData) -> String {
+        let input = data
+            .messages
+            .iter()
+            .rev()
+            .find(|v| v.role == MessageRole::User)
+            .map(|v| v.content.render_input(|url| url.to_string()))
+            .unwrap_or_default();
+        self.config
+            .response
+            .as_deref()
+            .unwrap_or(DEFAULT_RESPONSE)
+            .replace("{{input}}", &input)
+            .replace("{{model}}", &self.model.id())
+    }
+
+    async fn wait(&self, millis: Option<u64>) {
+        if let Some(millis) = millis {
+            sleep(Duration::from_millis(millis)).await;
+        }
+    }
+}
@@ -1,11 +1,22 @@
+mod cassette;
 #[macro_use]
 mod common;
+mod error;
+pub(crate) mod healthcheck;
 mod message;
 mod model;
+mod rate_limit;
+mod stream;
 
+pub use cassette::Cassette;
 pub use common::*;
+pub use error::*;
 pub use message::*;
 pub use model::*;
+pub use stream::*;
+
+use crate::config::Config;
+use crate::utils::is_local_url;
 
 register_client!(
     (openai, "openai", OpenAIConfig, OpenAIClient),
@@ -23,4 +34,45 @@ register_client!(
     (ernie, "ernie", ErnieConfig, ErnieClient),
     (qianwen, "qianwen", QianwenConfig, QianwenClient),
     (vertexai, "vertexai", VertexAIConfig, VertexAIClient),
+    (bedrock, "bedrock", BedrockConfig, BedrockClient),
+    (mock, "mock", MockConfig, MockClient),
 );
+
+/// Whether the model is served on this machine, the only kind of model usable offline
+pub fn is_local_model(config: &Config, model: &Model) -> bool {
+    config.clients.iter().any(|v| {
+        let (name, api_base) = match v {
+            ClientConfig::OpenAIConfig(c) => (OpenAIClient::name(c), c.api_base.as_deref()),
+            ClientConfig::GeminiConfig(c) => (GeminiClient::name(c), None),
+            ClientConfig::ClaudeConfig(c) => (ClaudeClient::name(c), None),
+            ClientConfig::MistralConfig(c) => (MistralClient::name(c), None),
+            ClientConfig::LocalAIConfig(c) => (LocalAIClient::name(c), Some(c.api_base.as_str())),
+            ClientConfig::OllamaConfig(c) => (OllamaClient::name(c), Some(c.api_base.as_str())),
+            ClientConfig::AzureOpenAIConfig(c) => {
+                (AzureOpenAIClient::name(c), c.api_base.as_deref())
+            }
+            ClientConfig::ErnieConfig(c) => (ErnieClient::name(c), None),
+            ClientConfig::QianwenConfig(c) => (QianwenClient::name(c), None),
+            ClientConfig::VertexAIConfig(c) => (VertexAIClient::name(c), c.api_base.as_deref()),
+            ClientConfig::BedrockConfig(c) => (BedrockClient::name(c), None),
+            ClientConfig::MockConfig(c) => return MockClient::name(c) == model.client_name,
+            ClientConfig::Unknown => return false,
+        };
+        name == model.client_name && api_base.map(is_local_url).unwrap_or_default()
+    })
+}
+
+/// Ask the servers of the clients discovering their models, only the one of `client_name` if
+/// given, so listing the models stays lazy
+fn discover_models(config: &Config, client_name: Option<&str>) {
+    for client in &config.clients {
+        if let ClientConfig::OllamaConfig(c) = client {
+            if client_name
+                .map(|v| v == OllamaClient::name(c))
+                .unwrap_or(true)
+            {
+                ollama::discover_models(c, config.offline);
+            }
+        }
+    }
+}
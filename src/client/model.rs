@@ -1,12 +1,32 @@
 use super::message::{Message, MessageContent};
+use super::ApiError;
 
 use crate::utils::count_tokens;
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use serde::{Deserialize, Deserializer};
 
 pub type TokensCountFactors = (usize, usize); // (per-messages, bias)
 
+/// The OpenAI models taking `reasoning_effort`
+const REASONING_MODEL_PREFIXES: [&str; 3] = ["o1", "o3", "o4"];
+
+/// The Claude models taking `thinking.budget_tokens`
+const THINKING_MODEL_PREFIXES: [&str; 3] = ["claude-3-7-", "claude-sonnet-4", "claude-opus-4"];
+
+/// Models retired by their providers, with the model to use instead
+const DEPRECATED_MODELS: [(&str, &str); 9] = [
+    ("openai:gpt-3.5-turbo-0301", "openai:gpt-3.5-turbo"),
+    ("openai:gpt-3.5-turbo-0613", "openai:gpt-3.5-turbo"),
+    ("openai:gpt-3.5-turbo-16k-0613", "openai:gpt-3.5-turbo"),
+    ("openai:gpt-4-0314", "openai:gpt-4-turbo-preview"),
+    ("openai:gpt-4-32k-0314", "openai:gpt-4-turbo-preview"),
+    ("openai:text-davinci-003", "openai:gpt-3.5-turbo"),
+    ("claude:claude-1", "claude:claude-2.1"),
+    ("claude:claude-2", "claude:claude-2.1"),
+    ("claude:claude-instant-1", "claude:claude-instant-1.2"),
+];
+
 #[derive(Debug, Clone)]
 pub struct Model {
     pub client_name: String,
@@ -15,6 +35,13 @@ pub struct Model {
     pub extra_fields: Option<serde_json::Map<String, serde_json::Value>>,
     pub tokens_count_factors: TokensCountFactors,
     pub capabilities: ModelCapabilities,
+    pub no_stream: bool,
+    /// Price in USD per 1M input tokens
+    pub input_price: Option<f64>,
+    /// Price in USD per 1M output tokens, the `input_price` is used when unset
+    pub output_price: Option<f64>,
+    /// The default `reasoning_effort` of the model
+    pub reasoning_effort: Option<String>,
 }
 
 impl Default for Model {
@@ -32,6 +59,10 @@ impl Model {
             max_input_tokens: None,
             tokens_count_factors: Default::default(),
             capabilities: ModelCapabilities::Text,
+            no_stream: false,
+            input_price: None,
+            output_price: None,
+            reasoning_effort: None,
         }
     }
 
@@ -66,6 +97,39 @@ impl Model {
         model
     }
 
+    /// The replacement of a model retired by its provider
+    pub fn deprecation(id: &str) -> Option<&'static str> {
+        DEPRECATED_MODELS
+            .iter()
+            .find(|(old, _)| *old == id)
+            .map(|(_, new)| *new)
+    }
+
+    /// Suggest a model to use instead of an unavailable one, from the deprecations or the
+    /// model of the same client whose name shares the longest prefix
+    pub fn replacement(models: &[Self], id: &str) -> Option<String> {
+        if let Some(new) = Self::deprecation(id) {
+            return Some(new.to_string());
+        }
+        let (client_name, name) = id.split_once(':')?;
+        let mut best: Option<(usize, &Self)> = None;
+        for model in models {
+            if model.client_name != client_name || model.name == name {
+                continue;
+            }
+            let len = model
+                .name
+                .chars()
+                .zip(name.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > 0 && best.map(|(v, _)| len > v).unwrap_or(true) {
+                best = Some((len, model));
+            }
+        }
+        best.map(|(_, model)| model.id())
+    }
+
     pub fn id(&self) -> String {
         format!("{}:{}", self.client_name, self.name)
     }
@@ -75,6 +139,26 @@ impl Model {
         self
     }
 
+    pub fn set_no_stream(mut self, no_stream: bool) -> Self {
+        self.no_stream = no_stream;
+        self
+    }
+
+    pub fn set_input_price(mut self, input_price: Option<f64>) -> Self {
+        self.input_price = input_price;
+        self
+    }
+
+    pub fn set_output_price(mut self, output_price: Option<f64>) -> Self {
+        self.output_price = output_price;
+        self
+    }
+
+    pub fn set_reasoning_effort(mut self, reasoning_effort: Option<String>) -> Self {
+        self.reasoning_effort = reasoning_effort;
+        self
+    }
+
     pub fn set_extra_fields(
         mut self,
         extra_fields: Option<serde_json::Map<String, serde_json::Value>>,
@@ -127,12 +211,50 @@ impl Model {
         let total_tokens = self.total_tokens(messages) + bias;
         if let Some(max_input_tokens) = self.max_input_tokens {
             if total_tokens >= max_input_tokens {
-                bail!("Exceed max input tokens limit")
+                let message = format!(
+                    "The input consumes {total_tokens} tokens, exceeding the max input tokens limit ({max_input_tokens}) of '{}', remove at least {} tokens",
+                    self.id(),
+                    total_tokens + 1 - max_input_tokens
+                );
+                return Err(ApiError::new(None, Some("context_length_exceeded"), message).into());
             }
         }
         Ok(())
     }
 
+    /// Estimate the cost in USD of sending the given number of input tokens
+    pub fn input_cost(&self, tokens: usize) -> Option<f64> {
+        self.input_price
+            .map(|price| price * tokens as f64 / 1_000_000.0)
+    }
+
+    /// Estimate the cost in USD of a request, with the output tokens at the `output_price`
+    pub fn cost(&self, input_tokens: usize, output_tokens: usize) -> Option<f64> {
+        let input_price = self.input_price?;
+        let output_price = self.output_price.unwrap_or(input_price);
+        Some(
+            (input_price * input_tokens as f64 + output_price * output_tokens as f64) / 1_000_000.0,
+        )
+    }
+
+    /// Whether the model takes the OpenAI `reasoning_effort`, declared with the `reasoning`
+    /// capability or inferred from the names of the o-series models
+    pub fn supports_reasoning_effort(&self) -> bool {
+        self.capabilities.contains(ModelCapabilities::Reasoning)
+            || REASONING_MODEL_PREFIXES
+                .iter()
+                .any(|v| self.name.starts_with(v))
+    }
+
+    /// Whether the model takes the Anthropic extended thinking, declared with the `thinking`
+    /// capability or inferred from the names of the Claude models
+    pub fn supports_thinking(&self) -> bool {
+        self.capabilities.contains(ModelCapabilities::Thinking)
+            || THINKING_MODEL_PREFIXES
+                .iter()
+                .any(|v| self.name.starts_with(v))
+    }
+
     pub fn merge_extra_fields(&self, body: &mut serde_json::Value) {
         if let (Some(body), Some(extra_fields)) = (body.as_object_mut(), &self.extra_fields) {
             for (k, v) in extra_fields {
@@ -152,6 +274,13 @@ pub struct ModelConfig {
     #[serde(deserialize_with = "deserialize_capabilities")]
     #[serde(default = "default_capabilities")]
     pub capabilities: ModelCapabilities,
+    #[serde(default)]
+    pub no_stream: bool,
+    pub input_price: Option<f64>,
+    pub output_price: Option<f64>,
+    pub reasoning_effort: Option<String>,
+    /// The deployment serving the model, only for azure-openai, defaults to the name
+    pub deployment: Option<String>,
 }
 
 bitflags::bitflags! {
@@ -159,6 +288,8 @@ bitflags::bitflags! {
     pub struct ModelCapabilities: u32 {
         const Text = 0b00000001;
         const Vision = 0b00000010;
+        const Reasoning = 0b00000100;
+        const Thinking = 0b00001000;
     }
 }
 
@@ -172,6 +303,12 @@ impl From<&str> for ModelCapabilities {
         if value.contains("vision") {
             output |= ModelCapabilities::Vision;
         }
+        if value.contains("reasoning") {
+            output |= ModelCapabilities::Reasoning;
+        }
+        if value.contains("thinking") {
+            output |= ModelCapabilities::Thinking;
+        }
         output
     }
 }
@@ -187,3 +324,46 @@ where
 fn default_capabilities() -> ModelCapabilities {
     ModelCapabilities::Text
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost() {
+        let model = Model::new("openai", "gpt-4o").set_input_price(Some(5.0));
+        assert_eq!(model.cost(1_000_000, 1_000_000), Some(10.0));
+        let model = model.set_output_price(Some(15.0));
+        assert_eq!(model.cost(1_000_000, 1_000_000), Some(20.0));
+        assert_eq!(Model::new("openai", "gpt-4o").cost(100, 100), None);
+    }
+
+    #[test]
+    fn test_replacement() {
+        let models = vec![
+            Model::new("openai", "gpt-3.5-turbo"),
+            Model::new("openai", "gpt-4-turbo-preview"),
+            Model::new("claude", "claude-2.1"),
+        ];
+        assert_eq!(
+            Model::replacement(&models, "openai:gpt-4-0314"),
+            Some("openai:gpt-4-turbo-preview".into())
+        );
+        assert_eq!(
+            Model::replacement(&models, "openai:gpt-4-vision-preview"),
+            Some("openai:gpt-4-turbo-preview".into())
+        );
+        assert_eq!(Model::replacement(&models, "claude:foo"), None);
+    }
+
+    #[test]
+    fn test_supports_reasoning() {
+        assert!(Model::new("openai", "o3-mini").supports_reasoning_effort());
+        assert!(!Model::new("openai", "gpt-4-turbo-preview").supports_reasoning_effort());
+        assert!(Model::new("localai", "qwq")
+            .set_capabilities("text,reasoning".into())
+            .supports_reasoning_effort());
+        assert!(Model::new("claude", "claude-3-7-sonnet-latest").supports_thinking());
+        assert!(!Model::new("claude", "claude-3-opus-20240229").supports_thinking());
+    }
+}
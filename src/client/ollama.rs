@@ -1,25 +1,39 @@
 use super::{
-    message::*, patch_system_message, Client, ExtraConfig, Model, ModelConfig, OllamaClient,
-    PromptType, SendData, TokensCountFactors,
+    message::*, parse_stream_frame, patch_system_message, ApiError, Client, ExtraConfig, Model,
+    ModelConfig, OllamaClient, PromptType, SendData, TokensCountFactors,
 };
 
-use crate::{render::ReplyHandler, utils::PromptKind};
+use crate::{
+    render::ReplyHandler,
+    utils::{init_tokio_runtime, is_local_url, PromptKind},
+};
 
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
 use reqwest::{Client as ReqwestClient, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
 
 const TOKENS_COUNT_FACTORS: TokensCountFactors = (5, 2);
 
+lazy_static! {
+    /// The models pulled on each server by `api_base`, asked once per run by `discover_models`
+    static ref DISCOVERED_MODELS: Mutex<HashMap<String, Vec<ModelConfig>>> = Default::default();
+}
+
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct OllamaConfig {
     pub name: Option<String>,
     pub api_base: String,
     pub api_key: Option<String>,
     pub chat_endpoint: Option<String>,
+    /// Defaults to the models pulled on the server
+    #[serde(default)]
     pub models: Vec<ModelConfig>,
     pub extra: Option<ExtraConfig>,
 }
@@ -62,12 +76,19 @@ impl OllamaClient {
     pub fn list_models(local_config: &OllamaConfig) -> Vec<Model> {
         let client_name = Self::name(local_config);
 
-        local_config
-            .models
+        let models = if local_config.models.is_empty() {
+            discovered_models(local_config)
+        } else {
+            local_config.models.clone()
+        };
+        models
             .iter()
             .map(|v| {
                 Model::new(client_name, &v.name)
                     .set_capabilities(v.capabilities)
+                    .set_no_stream(v.no_stream)
+                    .set_input_price(v.input_price)
+                    .set_output_price(v.output_price)
                     .set_max_input_tokens(v.max_input_tokens)
                     .set_extra_fields(v.extra_fields.clone())
                     .set_tokens_count_factors(TOKENS_COUNT_FACTORS)
@@ -97,12 +118,63 @@ impl OllamaClient {
     }
 }
 
+/// Ask the server for the models pulled on it, unless models are configured, it was asked
+/// already, or it is not on this machine in offline mode
+pub fn discover_models(local_config: &OllamaConfig, offline: bool) {
+    if !local_config.models.is_empty() || (offline && !is_local_url(&local_config.api_base)) {
+        return;
+    }
+    DISCOVERED_MODELS
+        .lock()
+        .entry(local_config.api_base.clone())
+        .or_insert_with(|| match fetch_models(local_config) {
+            Ok(models) => models,
+            Err(err) => {
+                debug!("Failed to list the Ollama models: {err}");
+                vec![]
+            }
+        });
+}
+
+/// The models found by `discover_models`
+fn discovered_models(local_config: &OllamaConfig) -> Vec<ModelConfig> {
+    DISCOVERED_MODELS
+        .lock()
+        .get(&local_config.api_base)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// The models pulled on the server, from `/api/tags`
+fn fetch_models(local_config: &OllamaConfig) -> Result<Vec<ModelConfig>> {
+    init_tokio_runtime()?.block_on(async {
+        let client = ReqwestClient::builder()
+            .timeout(Duration::from_secs(3))
+            .build()?;
+        let mut builder = client.get(format!("{}/api/tags", local_config.api_base));
+        if let Some(api_key) = &local_config.api_key {
+            builder = builder.header("Authorization", api_key)
+        }
+        let data: Value = builder.send().await?.json().await?;
+        let models = data["models"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
+        let models = models
+            .iter()
+            .filter_map(|v| v["name"].as_str())
+            .filter_map(|name| serde_json::from_value(json!({ "name": name })).ok())
+            .collect();
+        Ok(models)
+    })
+}
+
 async fn send_message(builder: RequestBuilder) -> Result<String> {
     let res = builder.send().await?;
     let status = res.status();
     if status != 200 {
         let text = res.text().await?;
-        bail!("{status}, {text}");
+        let message = format!("{status}, {text}");
+        return Err(ApiError::new(Some(status.as_u16()), None, message).into());
     }
     let data: Value = res.json().await?;
     let output = data["message"]["content"]
@@ -116,15 +188,16 @@ async fn send_message_streaming(builder: RequestBuilder, handler: &mut ReplyHand
     let status = res.status();
     if status != 200 {
         let text = res.text().await?;
-        bail!("{status}, {text}");
+        let message = format!("{status}, {text}");
+        return Err(ApiError::new(Some(status.as_u16()), None, message).into());
     } else {
         let mut stream = res.bytes_stream();
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
             if chunk.is_empty() {
-              continue;
+                continue;
             }
-            let data: Value = serde_json::from_slice(&chunk)?;
+            let data = parse_stream_frame(std::str::from_utf8(&chunk)?)?;
             if data["done"].is_boolean() {
                 if let Some(text) = data["message"]["content"].as_str() {
                     handler.text(text)?;
@@ -142,6 +215,7 @@ fn build_body(data: SendData, model: String) -> Result<Value> {
         mut messages,
         temperature,
         stream,
+        ..
     } = data;
 
     patch_system_message(&mut messages);
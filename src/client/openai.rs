@@ -1,4 +1,7 @@
-use super::{ExtraConfig, Model, OpenAIClient, PromptType, SendData, TokensCountFactors};
+use super::{
+    content_filter_error, parse_stream_frame, ApiError, ExtraConfig, Model, OpenAIClient,
+    PromptType, SendData, TokensCountFactors,
+};
 
 use crate::{render::ReplyHandler, utils::PromptKind};
 
@@ -12,14 +15,15 @@ use serde_json::{json, Value};
 
 const API_BASE: &str = "https://api.openai.com/v1";
 
-const MODELS: [(&str, usize, &str); 5] = [
+// (name, max_input_tokens, input_price, capabilities), see https://openai.com/pricing
+const MODELS: [(&str, usize, f64, &str); 5] = [
     // https://platform.openai.com/docs/models/gpt-3-5-turbo
-    ("gpt-3.5-turbo", 16385, "text"),
-    ("gpt-3.5-turbo-1106", 16385, "text"),
+    ("gpt-3.5-turbo", 16385, 0.5, "text"),
+    ("gpt-3.5-turbo-1106", 16385, 1.0, "text"),
     // https://platform.openai.com/docs/models/gpt-4-and-gpt-4-turbo
-    ("gpt-4-turbo-preview", 128000, "text"),
-    ("gpt-4-vision-preview", 128000, "text,vision"),
-    ("gpt-4-1106-preview", 128000, "text"),
+    ("gpt-4-turbo-preview", 128000, 10.0, "text"),
+    ("gpt-4-vision-preview", 128000, 10.0, "text,vision"),
+    ("gpt-4-1106-preview", 128000, 10.0, "text"),
 ];
 
 pub const OPENAI_TOKENS_COUNT_FACTORS: TokensCountFactors = (5, 2);
@@ -30,6 +34,7 @@ pub struct OpenAIConfig {
     pub api_key: Option<String>,
     pub api_base: Option<String>,
     pub organization_id: Option<String>,
+    pub project_id: Option<String>,
     pub extra: Option<ExtraConfig>,
 }
 
@@ -38,6 +43,8 @@ openai_compatible_client!(OpenAIClient);
 impl OpenAIClient {
     config_get_fn!(api_key, get_api_key);
     config_get_fn!(api_base, get_api_base);
+    config_get_fn!(organization_id, get_organization_id);
+    config_get_fn!(project_id, get_project_id);
 
     pub const PROMPTS: [PromptType<'static>; 1] =
         [("api_key", "API Key:", true, PromptKind::String)];
@@ -46,10 +53,11 @@ impl OpenAIClient {
         let client_name = Self::name(local_config);
         MODELS
             .into_iter()
-            .map(|(name, max_input_tokens, capabilities)| {
+            .map(|(name, max_input_tokens, input_price, capabilities)| {
                 Model::new(client_name, name)
                     .set_capabilities(capabilities.into())
                     .set_max_input_tokens(Some(max_input_tokens))
+                    .set_input_price(Some(input_price))
                     .set_tokens_count_factors(OPENAI_TOKENS_COUNT_FACTORS)
             })
             .collect()
@@ -67,19 +75,24 @@ impl OpenAIClient {
 
         let mut builder = client.post(url).bearer_auth(api_key).json(&body);
 
-        if let Some(organization_id) = &self.config.organization_id {
+        if let Ok(organization_id) = self.get_organization_id() {
             builder = builder.header("OpenAI-Organization", organization_id);
         }
 
+        if let Ok(project_id) = self.get_project_id() {
+            builder = builder.header("OpenAI-Project", project_id);
+        }
+
         Ok(builder)
     }
 }
 
 pub async fn openai_send_message(builder: RequestBuilder) -> Result<String> {
-    let data: Value = builder.send().await?.json().await?;
-    if let Some(err_msg) = data["error"]["message"].as_str() {
-        bail!("{err_msg}");
-    }
+    let res = builder.send().await?;
+    let status = res.status().as_u16();
+    let data: Value = res.json().await?;
+    openai_check_content_filter(&data)?;
+    openai_check_error(status, &data)?;
 
     let output = data["choices"][0]["message"]["content"]
         .as_str()
@@ -88,6 +101,29 @@ pub async fn openai_send_message(builder: RequestBuilder) -> Result<String> {
     Ok(output.to_string())
 }
 
+pub async fn openai_send_message_candidates(builder: RequestBuilder) -> Result<Vec<String>> {
+    let res = builder.send().await?;
+    let status = res.status().as_u16();
+    let data: Value = res.json().await?;
+    openai_check_content_filter(&data)?;
+    openai_check_error(status, &data)?;
+
+    let outputs: Vec<String> = data["choices"]
+        .as_array()
+        .map(|choices| {
+            choices
+                .iter()
+                .filter_map(|v| v["message"]["content"].as_str().map(|v| v.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if outputs.is_empty() {
+        bail!("Invalid response data: {data}");
+    }
+
+    Ok(outputs)
+}
+
 pub async fn openai_send_message_streaming(
     builder: RequestBuilder,
     handler: &mut ReplyHandler,
@@ -100,22 +136,24 @@ pub async fn openai_send_message_streaming(
                 if message.data == "[DONE]" {
                     break;
                 }
-                let data: Value = serde_json::from_str(&message.data)?;
+                let data = parse_stream_frame(&message.data)?;
                 if let Some(text) = data["choices"][0]["delta"]["content"].as_str() {
                     handler.text(text)?;
                 }
+                openai_check_content_filter(&data)?;
             }
             Err(err) => {
                 match err {
-                    EventSourceError::InvalidStatusCode(_, res) => {
+                    EventSourceError::InvalidStatusCode(status, res) => {
+                        let status = status.as_u16();
                         let data: Value = res.json().await?;
-                        if let Some(err_msg) = data["error"]["message"].as_str() {
-                            bail!("{err_msg}");
-                        } else if let Some(err_msg) = data["message"].as_str() {
-                            bail!("{err_msg}");
-                        } else {
-                            bail!("Request failed, {data}");
-                        } 
+                        openai_check_content_filter(&data)?;
+                        openai_check_error(status, &data)?;
+                        let message = match data["message"].as_str() {
+                            Some(err_msg) => err_msg.to_string(),
+                            None => format!("Request failed, {data}"),
+                        };
+                        return Err(ApiError::new(Some(status), None, message).into());
                     }
                     EventSourceError::StreamEnded => {}
                     _ => {
@@ -130,11 +168,46 @@ pub async fn openai_send_message_streaming(
     Ok(())
 }
 
+/// The error in the body, with its `code`, or its `type` when the code is null
+fn openai_check_error(status: u16, data: &Value) -> Result<()> {
+    if let Some(err_msg) = data["error"]["message"].as_str() {
+        let code = data["error"]["code"]
+            .as_str()
+            .or_else(|| data["error"]["type"].as_str());
+        return Err(ApiError::new(Some(status), code, err_msg).into());
+    }
+    Ok(())
+}
+
+/// Detect replies cut by the content filter and requests rejected by it (Azure OpenAI)
+fn openai_check_content_filter(data: &Value) -> Result<()> {
+    if data["error"]["code"].as_str() == Some("content_filter") {
+        let categories: Vec<String> = data["error"]["innererror"]["content_filter_result"]
+            .as_object()
+            .map(|results| {
+                results
+                    .iter()
+                    .filter(|(_, v)| v["filtered"].as_bool() == Some(true))
+                    .map(|(k, _)| k.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        return Err(content_filter_error(&categories));
+    }
+    if data["choices"][0]["finish_reason"].as_str() == Some("content_filter") {
+        return Err(content_filter_error(&[]));
+    }
+    Ok(())
+}
+
 pub fn openai_build_body(data: SendData, model: String) -> Value {
     let SendData {
         messages,
         temperature,
         stream,
+        n,
+        reasoning_effort,
+        ..
     } = data;
 
     let mut body = json!({
@@ -147,11 +220,19 @@ pub fn openai_build_body(data: SendData, model: String) -> Value {
         body["max_tokens"] = json!(4096);
     }
 
-    if let Some(v) = temperature {
+    if let Some(v) = reasoning_effort {
+        // The reasoning models only accept the default temperature
+        body["reasoning_effort"] = v.into();
+    } else if let Some(v) = temperature {
         body["temperature"] = v.into();
     }
     if stream {
         body["stream"] = true.into();
     }
+    if let Some(n) = n {
+        if n > 1 {
+            body["n"] = n.into();
+        }
+    }
     body
 }
@@ -1,4 +1,7 @@
-use super::{message::*, Client, ExtraConfig, Model, PromptType, QianwenClient, SendData};
+use super::{
+    message::*, parse_stream_frame, ApiError, Client, ExtraConfig, Model, PromptType,
+    QianwenClient, SendData,
+};
 
 use crate::{
     render::ReplyHandler,
@@ -145,7 +148,7 @@ async fn send_message_streaming(
         match event {
             Ok(Event::Open) => {}
             Ok(Event::Message(message)) => {
-                let data: Value = serde_json::from_str(&message.data)?;
+                let data = parse_stream_frame(&message.data)?;
                 check_error(&data)?;
                 if is_vl {
                     let text =
@@ -176,7 +179,8 @@ async fn send_message_streaming(
 
 fn check_error(data: &Value) -> Result<()> {
     if let (Some(code), Some(message)) = (data["code"].as_str(), data["message"].as_str()) {
-        bail!("{code}: {message}");
+        let message = format!("{code}: {message}");
+        return Err(ApiError::new(None, Some(code), message).into());
     }
     Ok(())
 }
@@ -186,6 +190,7 @@ fn build_body(data: SendData, model: String, is_vl: bool) -> Result<(Value, bool
         messages,
         temperature,
         stream,
+        ..
     } = data;
 
     let mut has_upload = false;
@@ -0,0 +1,125 @@
+use super::ExtraConfig;
+
+use crate::utils::{clear_status, set_status};
+
+use is_terminal::IsTerminal;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// The requests sent in the last minute and their input tokens, by client
+    static ref WINDOWS: Mutex<HashMap<String, VecDeque<(Instant, usize)>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Wait until sending a request of this many tokens stays within the `rpm` and `tpm` limits of
+/// the client, counting down in the status line
+pub async fn acquire(client_name: &str, extra: &Option<ExtraConfig>, tokens: usize) {
+    let (rpm, tpm) = match extra {
+        Some(extra) if extra.rpm.is_some() || extra.tpm.is_some() => (extra.rpm, extra.tpm),
+        _ => return,
+    };
+    let mut waited = false;
+    loop {
+        let wait = {
+            let mut windows = WINDOWS.lock();
+            let entries = windows.entry(client_name.to_string()).or_default();
+            let now = Instant::now();
+            while entries
+                .front()
+                .map(|(time, _)| now.duration_since(*time) >= WINDOW)
+                .unwrap_or_default()
+            {
+                entries.pop_front();
+            }
+            let wait = wait_time(entries, now, rpm, tpm, tokens);
+            if wait.is_zero() {
+                entries.push_back((now, tokens));
+            }
+            wait
+        };
+        if wait.is_zero() {
+            break;
+        }
+        let status = format!(
+            "Waiting {}s for the rate limit of '{client_name}'",
+            wait.as_secs() + 1
+        );
+        if !set_status(&status) && std::io::stderr().is_terminal() {
+            eprint!("\r\x1b[2K{status}");
+            waited = true;
+        }
+        sleep(wait.min(Duration::from_secs(1))).await;
+    }
+    clear_status();
+    if waited {
+        eprint!("\r\x1b[2K");
+    }
+}
+
+/// How long to wait before the request fits in the limits, given the requests of the last minute
+fn wait_time(
+    entries: &VecDeque<(Instant, usize)>,
+    now: Instant,
+    rpm: Option<usize>,
+    tpm: Option<usize>,
+    tokens: usize,
+) -> Duration {
+    let until = |index: usize| {
+        let (time, _) = entries[index];
+        (time + WINDOW).saturating_duration_since(now)
+    };
+    let mut wait = Duration::ZERO;
+    if let Some(rpm) = rpm.filter(|v| *v > 0) {
+        if entries.len() >= rpm {
+            wait = wait.max(until(entries.len() - rpm));
+        }
+    }
+    if let Some(tpm) = tpm {
+        let mut used: usize = entries.iter().map(|(_, v)| v).sum();
+        let mut index = 0;
+        while used + tokens > tpm && index < entries.len() {
+            used -= entries[index].1;
+            wait = wait.max(until(index));
+            index += 1;
+        }
+    }
+    wait
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_time() {
+        let now = Instant::now();
+        let entries: VecDeque<_> = vec![
+            (now - Duration::from_secs(50), 1000),
+            (now - Duration::from_secs(30), 2000),
+        ]
+        .into();
+        assert_eq!(wait_time(&entries, now, None, None, 500), Duration::ZERO);
+        assert_eq!(
+            wait_time(&entries, now, Some(2), None, 500),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            wait_time(&entries, now, Some(3), Some(3500), 500),
+            Duration::ZERO
+        );
+        assert_eq!(
+            wait_time(&entries, now, None, Some(3000), 500),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            wait_time(&entries, now, None, Some(2000), 500),
+            Duration::from_secs(30)
+        );
+    }
+}
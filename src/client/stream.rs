@@ -0,0 +1,59 @@
+use super::init_client_for_input;
+
+use crate::config::{GlobalConfig, Input};
+use crate::render::{ReplyEvent, ReplyHandler};
+use crate::utils::{create_abort_signal, AbortSignal};
+
+use anyhow::{anyhow, Result};
+use crossbeam::channel::{unbounded, Receiver};
+use std::thread::{spawn, JoinHandle};
+
+/// A reply streamed on a background thread, iterate over it to receive the text as it arrives
+pub struct ReplyStream {
+    rx: Receiver<ReplyEvent>,
+    abort: AbortSignal,
+    handle: JoinHandle<Result<String>>,
+}
+
+impl ReplyStream {
+    pub fn start(config: &GlobalConfig, input: Input) -> Self {
+        Self::start_with_abort(config, input, create_abort_signal())
+    }
+
+    /// Start the reply with a signal shared with the caller, e.g. to stop it at a deadline
+    pub fn start_with_abort(config: &GlobalConfig, input: Input, abort: AbortSignal) -> Self {
+        let config = config.clone();
+        let (tx, rx) = unbounded();
+        let mut handler = ReplyHandler::new(tx, abort.clone());
+        let handle = spawn(move || {
+            let client = init_client_for_input(&config, &input)?;
+            client.send_message_streaming(&input, &mut handler)?;
+            Ok(handler.get_buffer().to_string())
+        });
+        Self { rx, abort, handle }
+    }
+
+    /// Stop receiving the reply, for the library consumers
+    #[allow(dead_code)]
+    pub fn abort(&self) {
+        self.abort.set_ctrlc();
+    }
+
+    /// Wait for the reply to complete, returns the whole text or the error of the request
+    pub fn finish(self) -> Result<String> {
+        self.handle
+            .join()
+            .map_err(|_| anyhow!("The streaming thread panicked"))?
+    }
+}
+
+impl Iterator for ReplyStream {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.recv().ok()? {
+            ReplyEvent::Text(text) => Some(text),
+            ReplyEvent::Done => None,
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use super::{
-    message::*, patch_system_message, Client, ExtraConfig, Model, PromptType, SendData,
-    TokensCountFactors, VertexAIClient,
+    content_filter_error, message::*, parse_stream_frame, patch_system_message, ApiError, Client,
+    ExtraConfig, Model, PromptType, SendData, TokensCountFactors, VertexAIClient,
 };
 
 use crate::{render::ReplyHandler, utils::PromptKind};
@@ -32,6 +32,8 @@ pub struct VertexAIConfig {
     pub name: Option<String>,
     pub api_base: Option<String>,
     pub adc_file: Option<String>,
+    /// Replaces the default `BLOCK_ONLY_HIGH` threshold of every harm category
+    pub safety_settings: Option<Value>,
     pub extra: Option<ExtraConfig>,
 }
 
@@ -84,7 +86,11 @@ impl VertexAIClient {
             false => "generateContent",
         };
 
-        let body = build_body(data, self.model.name.clone())?;
+        let body = build_body(
+            data,
+            self.model.name.clone(),
+            self.config.safety_settings.clone(),
+        )?;
 
         let model = self.model.name.clone();
 
@@ -118,8 +124,9 @@ pub(crate) async fn send_message(builder: RequestBuilder) -> Result<String> {
     let status = res.status();
     let data: Value = res.json().await?;
     if status != 200 {
-        check_error(&data)?;
+        check_error(status.as_u16(), &data)?;
     }
+    check_blocked(&data)?;
     let output = data["candidates"][0]["content"]["parts"][0]["text"]
         .as_str()
         .ok_or_else(|| anyhow!("Invalid response data: {data}"))?;
@@ -131,9 +138,10 @@ pub(crate) async fn send_message_streaming(
     handler: &mut ReplyHandler,
 ) -> Result<()> {
     let res = builder.send().await?;
-    if res.status() != 200 {
+    let status = res.status();
+    if status != 200 {
         let data: Value = res.json().await?;
-        check_error(&data)?;
+        check_error(status.as_u16(), &data)?;
     } else {
         let mut buffer = vec![];
         let mut cursor = 0;
@@ -170,7 +178,8 @@ pub(crate) async fn send_message_streaming(
                         balances.pop();
                         if balances.is_empty() {
                             let value: String = buffer[start..=i].iter().collect();
-                            let value: Value = serde_json::from_str(&value)?;
+                            let value = parse_stream_frame(&value)?;
+                            check_blocked(&value)?;
                             if let Some(text) =
                                 value["candidates"][0]["content"]["parts"][0]["text"].as_str()
                             {
@@ -192,8 +201,40 @@ pub(crate) async fn send_message_streaming(
     Ok(())
 }
 
-fn check_error(data: &Value) -> Result<()> {
-    if let Some((Some(status), Some(message))) = data[0]["error"].as_object().map(|v| {
+/// Surface the safety categories when the prompt or the reply is blocked
+fn check_blocked(data: &Value) -> Result<()> {
+    let blocked_categories = |ratings: &Value| -> Vec<String> {
+        ratings
+            .as_array()
+            .map(|ratings| {
+                ratings
+                    .iter()
+                    .filter(|v| {
+                        v["blocked"].as_bool() == Some(true)
+                            || matches!(v["probability"].as_str(), Some("MEDIUM" | "HIGH"))
+                    })
+                    .filter_map(|v| v["category"].as_str().map(|v| v.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    if data["promptFeedback"]["blockReason"].is_string() {
+        let categories = blocked_categories(&data["promptFeedback"]["safetyRatings"]);
+        return Err(content_filter_error(&categories));
+    }
+    if data["candidates"][0]["finishReason"].as_str() == Some("SAFETY") {
+        let categories = blocked_categories(&data["candidates"][0]["safetyRatings"]);
+        return Err(content_filter_error(&categories));
+    }
+    Ok(())
+}
+
+/// The error of a failed request, in the body as is or as the only item of a stream
+fn check_error(status_code: u16, data: &Value) -> Result<()> {
+    let error = data[0]["error"]
+        .as_object()
+        .or_else(|| data["error"].as_object());
+    if let Some((Some(status), Some(message))) = error.map(|v| {
         (
             v.get("status").and_then(|v| v.as_str()),
             v.get("message").and_then(|v| v.as_str()),
@@ -202,13 +243,19 @@ fn check_error(data: &Value) -> Result<()> {
         if status == "UNAUTHENTICATED" {
             unsafe { ACCESS_TOKEN = (String::new(), 0) }
         }
-        bail!("{status}: {message}")
+        let message = format!("{status}: {message}");
+        Err(ApiError::new(Some(status_code), Some(status), message).into())
     } else {
-        bail!("Error {}", data);
+        let message = format!("Error {data}");
+        Err(ApiError::new(Some(status_code), None, message).into())
     }
 }
 
-pub(crate) fn build_body(data: SendData, _model: String) -> Result<Value> {
+pub(crate) fn build_body(
+    data: SendData,
+    _model: String,
+    safety_settings: Option<Value>,
+) -> Result<Value> {
     let SendData {
         mut messages,
         temperature,
@@ -258,14 +305,18 @@ pub(crate) fn build_body(data: SendData, _model: String) -> Result<Value> {
         );
     }
 
-    let mut body = json!({
-        "contents": contents,
-        "safetySettings":[
+    let safety_settings = safety_settings.unwrap_or_else(|| {
+        json!([
             {"category":"HARM_CATEGORY_HARASSMENT","threshold":"BLOCK_ONLY_HIGH"},
             {"category":"HARM_CATEGORY_HATE_SPEECH","threshold":"BLOCK_ONLY_HIGH"},
             {"category":"HARM_CATEGORY_SEXUALLY_EXPLICIT","threshold":"BLOCK_ONLY_HIGH"},
             {"category":"HARM_CATEGORY_DANGEROUS_CONTENT","threshold":"BLOCK_ONLY_HIGH"}
-        ]
+        ])
+    });
+
+    let mut body = json!({
+        "contents": contents,
+        "safetySettings": safety_settings,
     });
 
     if let Some(temperature) = temperature {
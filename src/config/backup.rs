@@ -0,0 +1,195 @@
+use super::{ensure_parent_exists, Config, CLIENTS_FIELD, CONFIG_FILE_NAME, DRAFTS_DIR_NAME};
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use inquire::Confirm;
+use std::fs::{self, read_dir, symlink_metadata};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tar::{EntryType, Header};
+
+/// Pack the config directory, with the sessions, roles, templates, prompts, plugins and role
+/// packs in it, into a gzipped tarball
+pub fn backup(path: &str, exclude_keys: bool) -> Result<()> {
+    let (data, num_files) = pack(&Config::config_dir()?, exclude_keys)?;
+    fs::write(path, data).with_context(|| format!("Failed to write backup to {path}"))?;
+    println!("✨ Backed up {num_files} files to {path}");
+    Ok(())
+}
+
+pub fn restore(path: &str) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("Failed to load backup {path}"))?;
+    let files = unpack(&data)?;
+    let dir = Config::config_dir()?;
+    if files.iter().any(|(name, _)| dir.join(name).exists()) {
+        let ans = Confirm::new("Restoring will overwrite existing files, continue?")
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            return Ok(());
+        }
+    }
+    write_files(&dir, &files)?;
+    println!("✨ Restored {} files from {path}", files.len());
+    Ok(())
+}
+
+/// The tarball of the files in the directory and their number. Links and unsent drafts are
+/// left out, and so are the API keys of the config with `exclude_keys`.
+fn pack(dir: &Path, exclude_keys: bool) -> Result<(Vec<u8>, usize)> {
+    let mut files = vec![];
+    collect_files(dir, Path::new(""), &mut files);
+    let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    for name in &files {
+        let path = dir.join(name);
+        let mut content =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        if exclude_keys && name == Path::new(CONFIG_FILE_NAME) {
+            content = strip_keys(&String::from_utf8_lossy(&content))?.into_bytes();
+        }
+        let mut header = Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o600);
+        header.set_entry_type(EntryType::Regular);
+        builder
+            .append_data(&mut header, name, content.as_slice())
+            .with_context(|| format!("Failed to back up {}", path.display()))?;
+    }
+    let data = builder
+        .into_inner()
+        .and_then(|v| v.finish())
+        .with_context(|| "Failed to write the backup")?;
+    Ok((data, files.len()))
+}
+
+/// The files of the tarball by their path relative to the config directory, refusing anything
+/// that could be written outside of it
+fn unpack(data: &[u8]) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    let mut files = vec![];
+    for entry in archive
+        .entries()
+        .with_context(|| "Invalid backup archive")?
+    {
+        let mut entry = entry.with_context(|| "Invalid backup archive")?;
+        let name = entry.path()?.into_owned();
+        if !name.components().all(|v| matches!(v, Component::Normal(_))) {
+            bail!("Invalid backup entry '{}'", name.display());
+        }
+        match entry.header().entry_type() {
+            EntryType::Regular => {}
+            EntryType::Directory => continue,
+            _ => bail!("Invalid backup entry '{}'", name.display()),
+        }
+        let mut content = vec![];
+        entry
+            .read_to_end(&mut content)
+            .with_context(|| format!("Failed to read '{}' of the backup", name.display()))?;
+        files.push((name, content));
+    }
+    Ok(files)
+}
+
+fn write_files(dir: &Path, files: &[(PathBuf, Vec<u8>)]) -> Result<()> {
+    for (name, content) in files {
+        let target = dir.join(name);
+        ensure_parent_exists(&target)?;
+        fs::write(&target, content)
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+        #[cfg(unix)]
+        {
+            if name == Path::new(CONFIG_FILE_NAME) {
+                use std::os::unix::prelude::PermissionsExt;
+                let perms = std::fs::Permissions::from_mode(0o600);
+                std::fs::set_permissions(&target, perms)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn collect_files(dir: &Path, prefix: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<_> = match read_dir(dir.join(prefix)) {
+        Ok(rd) => rd.flatten().collect(),
+        Err(_) => return,
+    };
+    entries.sort_by_key(|v| v.file_name());
+    for entry in entries {
+        let name = prefix.join(entry.file_name());
+        if name == Path::new(DRAFTS_DIR_NAME) {
+            continue;
+        }
+        let file_type = match symlink_metadata(entry.path()) {
+            Ok(v) => v.file_type(),
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            collect_files(dir, &name, files);
+        } else if file_type.is_file() {
+            files.push(name);
+        }
+    }
+}
+
+fn strip_keys(content: &str) -> Result<String> {
+    let mut value: serde_yaml::Value =
+        serde_yaml::from_str(content).with_context(|| "Invalid config")?;
+    if let Some(clients) = value
+        .get_mut(CLIENTS_FIELD)
+        .and_then(|v| v.as_sequence_mut())
+    {
+        for client in clients {
+            if let Some(client) = client.as_mapping_mut() {
+                let keys: Vec<serde_yaml::Value> = client
+                    .keys()
+                    .filter(|k| k.as_str().map(is_secret_field).unwrap_or_default())
+                    .cloned()
+                    .collect();
+                for key in keys {
+                    client.remove(&key);
+                }
+            }
+        }
+    }
+    serde_yaml::to_string(&value).with_context(|| "Failed to serde config")
+}
+
+fn is_secret_field(name: &str) -> bool {
+    name.contains("key") || name.contains("secret") || name.contains("token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let src = tempfile::tempdir().unwrap();
+        let src = src.path();
+        let config = "model: openai:gpt-4o\nclients:\n- type: openai\n  api_key: sk-xxx\n";
+        for (name, content) in [
+            (CONFIG_FILE_NAME, config),
+            ("sessions/work.yaml", "messages: []\n"),
+            ("templates/bug.tpl", "{{title}}"),
+            ("prompts/review.md", "Review this"),
+            ("drafts/pid-1.txt", "unsent"),
+        ] {
+            let path = src.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let (data, num_files) = pack(src, true).unwrap();
+        assert_eq!(num_files, 4);
+
+        let dst = tempfile::tempdir().unwrap();
+        let dst = dst.path();
+        write_files(dst, &unpack(&data).unwrap()).unwrap();
+        let read = |name: &str| fs::read_to_string(dst.join(name)).unwrap();
+        assert_eq!(read("templates/bug.tpl"), "{{title}}");
+        assert_eq!(read("prompts/review.md"), "Review this");
+        assert_eq!(read("sessions/work.yaml"), "messages: []\n");
+        assert!(!read(CONFIG_FILE_NAME).contains("sk-xxx"));
+        assert!(read(CONFIG_FILE_NAME).contains("gpt-4o"));
+        assert!(!dst.join("drafts").exists());
+    }
+}
@@ -0,0 +1,213 @@
+use super::{GlobalConfig, Input, OutputConstraints, Role};
+
+use crate::client::{
+    init_client, init_client_with_model, list_models, record_reply, replay_reply, Client, Model,
+    SendData,
+};
+use crate::utils::{init_tokio_runtime, sanitize_output};
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::read_to_string;
+
+/// One line of the `--batch` file, the other fields override the model, role and temperature
+/// of the run for this item
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchItem {
+    pub id: Value,
+    pub text: String,
+    pub model: Option<String>,
+    pub role: Option<String>,
+    pub temperature: Option<f64>,
+}
+
+/// The reply to a batch item, printed as a JSON line as soon as it arrives
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+pub fn load_batch(path: &str) -> Result<Vec<BatchItem>> {
+    let content =
+        read_to_string(path).with_context(|| format!("Failed to load batch at {path}"))?;
+    parse_batch(&content)
+}
+
+fn parse_batch(content: &str) -> Result<Vec<BatchItem>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid batch item at line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Send the items concurrently, at most `concurrency` at a time and within the `rpm` and `tpm`
+/// limits of each client, handing over the results in the order they finish
+pub fn run_batch(
+    config: &GlobalConfig,
+    items: Vec<BatchItem>,
+    concurrency: usize,
+    mut on_result: impl FnMut(BatchResult),
+) -> Result<()> {
+    let jobs: Vec<_> = items
+        .into_iter()
+        .map(|item| (item.id.clone(), BatchJob::new(config, &item)))
+        .collect();
+    init_tokio_runtime()?.block_on(async {
+        let mut results = stream::iter(jobs.iter().map(|(id, job)| async move {
+            let ret = match job {
+                Ok(job) => job.send(config).await,
+                Err(err) => Err(anyhow!("{err:#}")),
+            };
+            let model = job.as_ref().ok().map(|v| v.client.model().id());
+            match ret {
+                Ok(output) => BatchResult {
+                    id: id.clone(),
+                    model,
+                    output: Some(output),
+                    error: None,
+                },
+                Err(err) => BatchResult {
+                    id: id.clone(),
+                    model,
+                    output: None,
+                    error: Some(format!("{err:#}")),
+                },
+            }
+        }))
+        .buffer_unordered(concurrency.max(1));
+        while let Some(result) = results.next().await {
+            on_result(result);
+        }
+    });
+    Ok(())
+}
+
+/// An item ready to send, its overrides applied to the requests rather than to a copy of the
+/// config, so the items share the cassette of the run
+struct BatchJob {
+    client: Box<dyn Client>,
+    input: Input,
+    role: Option<Role>,
+    temperature: Option<f64>,
+    constraints: OutputConstraints,
+}
+
+impl BatchJob {
+    fn new(config: &GlobalConfig, item: &BatchItem) -> Result<Self> {
+        let client = match &item.model {
+            Some(id) => {
+                let model = Model::find(&list_models(&config.read()), id)
+                    .ok_or_else(|| anyhow!("Invalid model '{id}'"))?;
+                init_client_with_model(config, &model)?
+            }
+            None => init_client(config)?,
+        };
+        let config = config.read();
+        let role = match &item.role {
+            Some(name) => Some(config.retrieve_role(name)?),
+            None => config.role.clone(),
+        };
+        let temperature = match (&item.role, &role) {
+            (Some(_), Some(role)) => role.temperature,
+            _ => config.get_temperature(),
+        };
+        let constraints = config.output_constraints_with_role(role.as_ref());
+        Ok(Self {
+            client,
+            input: Input::from_str(&item.text),
+            role,
+            temperature: item.temperature.or(temperature),
+            constraints,
+        })
+    }
+
+    /// Send the input, resending it while the reply violates the output constraints
+    async fn send(&self, config: &GlobalConfig) -> Result<String> {
+        if config.read().dry_run {
+            let data = self.prepare_send_data(config, &self.input)?;
+            return Ok(serde_yaml::to_string(&data.messages)?);
+        }
+        let mut output = self.send_input(config, &self.input).await?;
+        let mut retry = 0;
+        while let Some(input) = self
+            .constraints
+            .retry_input(&self.input, &output, &mut retry)?
+        {
+            output = self.send_input(config, &input).await?;
+        }
+        Ok(output)
+    }
+
+    async fn send_input(&self, config: &GlobalConfig, input: &Input) -> Result<String> {
+        let model = self.client.model();
+        let data = self.prepare_send_data(config, input)?;
+        if let Some(output) = replay_reply(config, model, &data)? {
+            return Ok(output);
+        }
+        let http_client = self.client.build_client()?;
+        self.client.acquire_rate_limit(&data).await;
+        let output = self
+            .client
+            .send_message_inner(&http_client, data.clone())
+            .await
+            .with_context(|| "Failed to get answer")?;
+        let output = sanitize_output(&output);
+        record_reply(config, model, &data, &output)?;
+        Ok(output)
+    }
+
+    fn prepare_send_data(&self, config: &GlobalConfig, input: &Input) -> Result<SendData> {
+        let mut data = config.read().prepare_send_data_with_role(
+            input,
+            self.client.model(),
+            self.role.as_ref(),
+            false,
+        )?;
+        data.temperature = self.temperature;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch() {
+        let items = parse_batch(
+            r#"{"id":1,"text":"hello"}
+
+{"id":"b","text":"hi","model":"openai:gpt-4o","temperature":0.2}
+"#,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].id, Value::from(1));
+        assert_eq!(items[0].model, None);
+        assert_eq!(items[1].id, Value::from("b"));
+        assert_eq!(items[1].model.as_deref(), Some("openai:gpt-4o"));
+        assert_eq!(items[1].temperature, Some(0.2));
+
+        let err = parse_batch("{\"id\":1,\"text\":\"hello\"}\n\n{\"id\":2}\n").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid batch item at line 3");
+    }
+}
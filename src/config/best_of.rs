@@ -0,0 +1,92 @@
+use super::{GlobalConfig, Input};
+
+use crate::client::{init_client, init_client_for_input};
+
+use anyhow::{anyhow, bail, Result};
+
+/// The temperature to sample the answers with, unless a nonzero temperature is set
+const SAMPLE_TEMPERATURE: f64 = 1.0;
+
+const JUDGE_PROMPT: &str = "Below are several answers to the same question. Pick the answer that is most accurate and complete. Output only its number.";
+
+/// Sample `n` answers to the input and select the best one, by majority vote or by asking the model
+pub fn best_of(config: &GlobalConfig, input: &Input, n: usize) -> Result<String> {
+    if n < 2 {
+        bail!("--best-of needs at least 2 samples");
+    }
+    let client = init_client_for_input(config, input)?;
+    let temperature = config.read().temperature;
+    match temperature {
+        Some(value) if value > 0.0 => {}
+        Some(value) => {
+            eprintln!(
+                "The temperature {value} samples the same answer, using {SAMPLE_TEMPERATURE} for --best-of"
+            );
+            config.write().temperature = Some(SAMPLE_TEMPERATURE);
+        }
+        None => config.write().temperature = Some(SAMPLE_TEMPERATURE),
+    }
+    let ret = client.send_message_candidates(input.clone(), n);
+    config.write().temperature = temperature;
+    let outputs = ret?;
+    if outputs.is_empty() {
+        bail!("No answers were sampled");
+    }
+    if let Some(index) = majority_vote(&outputs) {
+        return Ok(outputs[index].clone());
+    }
+    let mut judge_text = format!("{JUDGE_PROMPT}\n\nQuestion:\n{}", input.render());
+    for (i, output) in outputs.iter().enumerate() {
+        judge_text.push_str(&format!("\n\nAnswer {}:\n{output}", i + 1));
+    }
+    let verdict = init_client(config)?.send_message(Input::from_str(&judge_text))?;
+    let index = parse_verdict(&verdict, outputs.len()).ok_or_else(|| {
+        anyhow!(
+            "Cannot tell which answer the judge picked: {}",
+            verdict.trim()
+        )
+    })?;
+    Ok(outputs[index].clone())
+}
+
+/// The index of an answer given by more than half of the samples, ignoring case and spacing
+fn majority_vote(outputs: &[String]) -> Option<usize> {
+    let normalize = |v: &str| {
+        v.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    let normalized: Vec<String> = outputs.iter().map(|v| normalize(v)).collect();
+    normalized.iter().enumerate().find_map(|(i, v)| {
+        let votes = normalized.iter().filter(|w| *w == v).count();
+        (votes * 2 > outputs.len()).then_some(i)
+    })
+}
+
+/// The first number in the reply of the judge, as an index
+fn parse_verdict(text: &str, len: usize) -> Option<usize> {
+    let number: String = text
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    let number: usize = number.parse().ok()?;
+    (1..=len).contains(&number).then(|| number - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let outputs = vec!["42".to_string(), "41".into(), " 42 ".into()];
+        assert_eq!(majority_vote(&outputs), Some(0));
+        let outputs = vec!["a".to_string(), "b".into(), "c".into(), "a".into()];
+        assert_eq!(majority_vote(&outputs), None);
+        assert_eq!(parse_verdict("Answer 3", 3), Some(2));
+        assert_eq!(parse_verdict("4", 3), None);
+        assert_eq!(parse_verdict("They are all good", 3), None);
+    }
+}
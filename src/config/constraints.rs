@@ -0,0 +1,143 @@
+use super::schema::{extract_json, validate};
+use super::Input;
+
+use anyhow::{bail, Context, Result};
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const DEFAULT_MAX_RETRIES: usize = 2;
+
+/// Constraints on the reply, the question is resent with feedback when the reply violates them
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct OutputConstraints {
+    /// Strings that must not appear in the reply
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub banned: Vec<String>,
+    /// Regexes that the reply must match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
+    /// How many times to retry before giving up, defaults to 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<usize>,
+    /// The JSON schema of `--schema` that the reply must match
+    #[serde(skip)]
+    pub schema: Option<Value>,
+}
+
+impl OutputConstraints {
+    pub fn is_empty(&self) -> bool {
+        self.banned.is_empty() && self.required.is_empty() && self.schema.is_none()
+    }
+
+    /// Combine the global constraints with the ones of a role, the role wins on `max_retries`
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            banned: [self.banned.clone(), other.banned.clone()].concat(),
+            required: [self.required.clone(), other.required.clone()].concat(),
+            max_retries: other.max_retries.or(self.max_retries),
+            schema: self.schema.clone(),
+        }
+    }
+
+    pub fn max_retries(&self) -> usize {
+        self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES)
+    }
+
+    /// Describe every constraint that the output violates
+    pub fn check(&self, output: &str) -> Result<Vec<String>> {
+        let mut violations = vec![];
+        for banned in &self.banned {
+            if output.contains(banned.as_str()) {
+                violations.push(format!("it must not contain '{banned}'"));
+            }
+        }
+        for required in &self.required {
+            let re = Regex::new(required)
+                .with_context(|| format!("Invalid required pattern '{required}'"))?;
+            if !re.is_match(output)? {
+                violations.push(format!("it must match the regex '{required}'"));
+            }
+        }
+        if let Some(schema) = &self.schema {
+            match serde_json::from_str::<Value>(extract_json(output)) {
+                Ok(value) => violations.extend(
+                    validate(schema, &value)
+                        .into_iter()
+                        .map(|v| format!("it must match the JSON schema ({v})")),
+                ),
+                Err(err) => violations.push(format!("it must be valid JSON ({err})")),
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Send the input, then resend it with corrective feedback while the reply violates the constraints
+    pub fn enforce<F>(&self, input: &Input, mut send: F) -> Result<String>
+    where
+        F: FnMut(&Input) -> Result<String>,
+    {
+        let mut output = send(input)?;
+        let mut retry = 0;
+        while let Some(input) = self.retry_input(input, &output, &mut retry)? {
+            output = send(&input)?;
+        }
+        Ok(output)
+    }
+
+    /// The input resending the question with feedback on the violations of the output, `None`
+    /// once the output passes and an error once the retries run out
+    pub fn retry_input(
+        &self,
+        input: &Input,
+        output: &str,
+        retry: &mut usize,
+    ) -> Result<Option<Input>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let violations = self.check(output)?;
+        if violations.is_empty() {
+            return Ok(None);
+        }
+        let violations = violations.join("; ");
+        let max_retries = self.max_retries();
+        *retry += 1;
+        if *retry > max_retries {
+            bail!("The reply still violates the output constraints after {max_retries} retries: {violations}");
+        }
+        eprintln!(
+            "The reply violates the output constraints ({violations}), retrying {retry}/{max_retries}"
+        );
+        let feedback = format!(
+            "Your previous reply was rejected because {violations}. The rejected reply was:\n\n{output}\n\nReply again, fixing these problems."
+        );
+        Ok(Some(input.with_feedback(&feedback)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enforce() {
+        let constraints = OutputConstraints {
+            banned: vec!["```".into()],
+            required: vec![r"^\{.*\}$".into()],
+            max_retries: None,
+            schema: None,
+        };
+        let input = Input::from_str("give me json");
+        let mut replies = vec!["{}", "```json\n{}\n```"];
+        let output = constraints
+            .enforce(&input, |_| Ok(replies.pop().unwrap().to_string()))
+            .unwrap();
+        assert_eq!(output, "{}");
+
+        let mut replies = vec!["a", "b", "c"];
+        assert!(constraints
+            .enforce(&input, |_| Ok(replies.pop().unwrap().to_string()))
+            .is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::client::{Message, MessageContent, MessageContentPart, MessageRole};
+
+use anyhow::{bail, Result};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+lazy_static! {
+    static ref PII_PATTERNS: [(Regex, &'static str); 4] = [
+        (
+            Regex::new(r"\b(sk|pk|ghp|gho|xoxb|xoxp)[-_][A-Za-z0-9_-]{16,}").unwrap(),
+            "[REDACTED_SECRET]"
+        ),
+        (
+            Regex::new(r"[\w.+-]+@[\w-]+(\.[\w-]+)+").unwrap(),
+            "[REDACTED_EMAIL]"
+        ),
+        (
+            Regex::new(r"\b\d{1,3}(\.\d{1,3}){3}\b").unwrap(),
+            "[REDACTED_IP]"
+        ),
+        (
+            // An optional country code, an area code, then two groups of digits
+            Regex::new(
+                r"(?<![\w.+-])(?:\+\d{1,3}[ .-]?)?(?:\(\d{2,4}\)[ .-]?|\d{2,4}[ .-])\d{3,4}[ .-]\d{3,4}\b(?![.-]\d)"
+            )
+            .unwrap(),
+            "[REDACTED_PHONE]"
+        ),
+    ];
+}
+
+/// Options of `.export finetune`
+#[derive(Debug, Clone, Default)]
+pub struct FinetuneOptions {
+    /// The roles of the messages to keep, all if empty
+    pub roles: Vec<MessageRole>,
+    /// Replace secrets, emails, IPs and phone numbers by placeholders
+    pub redact: bool,
+}
+
+impl FinetuneOptions {
+    pub fn parse_roles(value: &str) -> Result<Vec<MessageRole>> {
+        value
+            .split(',')
+            .map(|v| match v.trim() {
+                "system" => Ok(MessageRole::System),
+                "user" => Ok(MessageRole::User),
+                "assistant" => Ok(MessageRole::Assistant),
+                v => bail!("Invalid role '{v}', expect system, user or assistant"),
+            })
+            .collect()
+    }
+}
+
+/// A conversation in the chat fine-tuning JSONL format, none if it has no reply
+pub fn finetune_record(messages: &[Message], options: &FinetuneOptions) -> Option<Value> {
+    let messages: Vec<Value> = messages
+        .iter()
+        .filter(|v| options.roles.is_empty() || options.roles.contains(&v.role))
+        .map(|v| {
+            let mut content = match &v.content {
+                MessageContent::Text(text) => text.clone(),
+                MessageContent::Array(list) => list
+                    .iter()
+                    .filter_map(|v| match v {
+                        MessageContentPart::Text { text } => Some(text.as_str()),
+                        MessageContentPart::ImageUrl { .. } => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            if options.redact {
+                content = redact_pii(&content);
+            }
+            json!({ "role": v.role, "content": content })
+        })
+        .collect();
+    if !messages.iter().any(|v| v["role"] == "assistant") {
+        return None;
+    }
+    Some(json!({ "messages": messages }))
+}
+
+pub fn redact_pii(text: &str) -> String {
+    let mut text = text.to_string();
+    for (re, placeholder) in PII_PATTERNS.iter() {
+        text = re.replace_all(&text, *placeholder).to_string();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_pii() {
+        assert_eq!(
+            redact_pii("mail bob@example.com from 10.0.0.1, call +1 (555) 123-4567"),
+            "mail [REDACTED_EMAIL] from [REDACTED_IP], call [REDACTED_PHONE]"
+        );
+        assert_eq!(
+            redact_pii("key sk-abcdefghijklmnopqrstuvwx in v1.2.3"),
+            "key [REDACTED_SECRET] in v1.2.3"
+        );
+        for text in [
+            "released on 2024-05-08 at 12:30:45",
+            "upgrade from v1.2.3 to 10.20.3",
+            "build 20240508.1 at 1715160000",
+        ] {
+            assert_eq!(redact_pii(text), text);
+        }
+        assert_eq!(
+            redact_pii("555-123-4567 or +44 20 7946 0958"),
+            "[REDACTED_PHONE] or [REDACTED_PHONE]"
+        );
+    }
+}
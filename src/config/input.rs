@@ -1,23 +1,31 @@
 use crate::client::{ImageUrl, MessageContent, MessageContentPart, ModelCapabilities};
-use crate::utils::sha256sum;
+use crate::utils::{fetch_url, scan_untrusted, sha256sum, wrap_untrusted};
 
 use anyhow::{bail, Context, Result};
 use base64::{self, engine::general_purpose::STANDARD, Engine};
 use fancy_regex::Regex;
+use is_terminal::IsTerminal;
 use lazy_static::lazy_static;
 use mime_guess::from_path;
 use std::{
     collections::HashMap,
     fs::{self, File},
-    io::Read,
+    io::{stdin, Read},
     path::{Path, PathBuf},
 };
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const IMAGE_EXTS: [&str; 5] = ["png", "jpeg", "jpg", "webp", "gif"];
 
+/// The file name reading the attachment from piped stdin, e.g. `-f -` on the command line
+pub const STDIN_FILE: &str = "-";
+
 lazy_static! {
     static ref URL_RE: Regex = Regex::new(r"^[A-Za-z0-9_-]{2,}:/").unwrap();
+    static ref FILE_MENTION_RE: Regex = Regex::new(r"(?<!\S)@(\S+)").unwrap();
+    static ref SYSTEM_DIRECTIVE_RE: Regex =
+        Regex::new(r"(?s)^\s*::sys\s+(.+?)\s*::\s*(.*)$").unwrap();
+    static ref LIST_MARKER_RE: Regex = Regex::new(r"^\s*(?:\d+[.)]|[-*+])\s+").unwrap();
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +33,8 @@ pub struct Input {
     text: String,
     medias: Vec<String>,
     data_urls: HashMap<String, String>,
+    /// The system prompt of `::sys <prompt> :: <text>`, only for this message
+    system: Option<String>,
 }
 
 impl Input {
@@ -33,14 +43,39 @@ impl Input {
             text: text.to_string(),
             medias: Default::default(),
             data_urls: Default::default(),
+            system: None,
         }
     }
 
     pub fn new(text: &str, files: Vec<String>) -> Result<Self> {
-        let mut texts = vec![text.to_string()];
+        let (system, text) = parse_system_directive(text);
+        let mut texts = vec![expand_file_mentions(text)?];
         let mut medias = vec![];
         let mut data_urls = HashMap::new();
         for file_item in files.into_iter() {
+            if file_item == STDIN_FILE {
+                if stdin().is_terminal() {
+                    bail!("'{STDIN_FILE}' reads the attachment from stdin, which is not piped");
+                }
+                let mut buffer = Vec::new();
+                stdin()
+                    .read_to_end(&mut buffer)
+                    .with_context(|| "Unable to read stdin")?;
+                match sniff_image_mime(&buffer) {
+                    Some(mime_type) => {
+                        let data_url =
+                            format!("data:{};base64,{}", mime_type, STANDARD.encode(buffer));
+                        data_urls.insert(sha256sum(&data_url), "<stdin>".into());
+                        medias.push(data_url)
+                    }
+                    None => {
+                        let text = String::from_utf8(buffer)
+                            .with_context(|| "Unable to use stdin, expect an image or text")?;
+                        texts.push(text);
+                    }
+                }
+                continue;
+            }
             match resolve_path(&file_item) {
                 Some(file_path) => {
                     let file_path = fs::canonicalize(file_path)
@@ -61,6 +96,10 @@ impl Input {
                 None => {
                     if is_image_ext(Path::new(&file_item)) {
                         medias.push(file_item)
+                    } else if file_item.starts_with("http://") || file_item.starts_with("https://")
+                    {
+                        let text = fetch_url(&file_item)?;
+                        texts.push(wrap_untrusted(&file_item, &text));
                     } else {
                         bail!("Unable to use file '{file_item}");
                     }
@@ -72,9 +111,31 @@ impl Input {
             text: texts.join("\n"),
             medias,
             data_urls,
+            system,
         })
     }
 
+    pub fn system(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    /// The same input with feedback on a rejected reply appended
+    pub fn with_feedback(&self, feedback: &str) -> Self {
+        let mut input = self.clone();
+        input.text = format!("{}\n\n{feedback}", self.text);
+        input
+    }
+
+    /// The independent questions asked by the text, empty unless there are several of them
+    pub fn questions(&self) -> Vec<String> {
+        split_questions(&self.text)
+    }
+
+    /// Instruction-like lines found in the ingested web pages and documents
+    pub fn untrusted_findings(&self) -> Vec<String> {
+        scan_untrusted(&self.text)
+    }
+
     pub fn data_urls(&self) -> HashMap<String, String> {
         self.data_urls.clone()
     }
@@ -166,7 +227,84 @@ pub fn resolve_data_url(data_urls: &HashMap<String, String>, data_url: String) -
     }
 }
 
-fn resolve_path(file: &str) -> Option<PathBuf> {
+/// Split `::sys <prompt> :: <text>` into the ad-hoc system prompt and the text
+fn parse_system_directive(text: &str) -> (Option<String>, &str) {
+    if let Ok(Some(captures)) = SYSTEM_DIRECTIVE_RE.captures(text) {
+        if let (Some(system), Some(rest)) = (captures.get(1), captures.get(2)) {
+            return (Some(system.as_str().to_string()), rest.as_str());
+        }
+    }
+    (None, text)
+}
+
+/// Find the questions of a text, one per list item or sentence ending with `?`. Short
+/// follow-ups like `Why?` lean on the previous question so they don't count, and neither does
+/// a text with code blocks.
+fn split_questions(text: &str) -> Vec<String> {
+    if text.contains("```") {
+        return vec![];
+    }
+    let mut questions = vec![];
+    for line in text.lines() {
+        let line = match LIST_MARKER_RE.find(line) {
+            Ok(Some(m)) => &line[m.end()..],
+            _ => line,
+        };
+        let mut start = 0;
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        for (i, (index, ch)) in chars.iter().enumerate() {
+            let at_boundary = chars
+                .get(i + 1)
+                .map(|(_, v)| v.is_whitespace())
+                .unwrap_or(true);
+            if !at_boundary || !matches!(ch, '?' | '.' | '!') {
+                continue;
+            }
+            let end = index + ch.len_utf8();
+            let sentence = line[start..end].trim();
+            if *ch == '?' && sentence.split_whitespace().count() >= 3 {
+                questions.push(sentence.to_string());
+            }
+            start = end;
+        }
+    }
+    if questions.len() < 2 {
+        return vec![];
+    }
+    questions
+}
+
+/// Expand `@<path>` tokens into fenced file contents
+pub fn expand_file_mentions(text: &str) -> Result<String> {
+    let mut output = String::new();
+    let mut last = 0;
+    for captures in FILE_MENTION_RE.captures_iter(text) {
+        let captures = captures?;
+        if let (Some(mention), Some(file)) = (captures.get(0), captures.get(1)) {
+            let file_path = match resolve_path(file.as_str()) {
+                Some(file_path) if file_path.is_file() && !is_image_ext(&file_path) => file_path,
+                _ => continue,
+            };
+            let content = fs::read_to_string(&file_path)
+                .with_context(|| format!("Unable to read file '{}'", file.as_str()))?;
+            let lang = file_path
+                .extension()
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default();
+            output.push_str(&text[last..mention.start()]);
+            output.push_str(&format!(
+                "{}:\n```{lang}\n{}\n```",
+                file.as_str(),
+                content.trim_end()
+            ));
+            last = mention.end();
+        }
+    }
+    output.push_str(&text[last..]);
+    Ok(output)
+}
+
+pub fn resolve_path(file: &str) -> Option<PathBuf> {
     if let Ok(true) = URL_RE.is_match(file) {
         return None;
     }
@@ -178,7 +316,7 @@ fn resolve_path(file: &str) -> Option<PathBuf> {
     Some(path)
 }
 
-fn is_image_ext(path: &Path) -> bool {
+pub fn is_image_ext(path: &Path) -> bool {
     path.extension()
         .map(|v| {
             IMAGE_EXTS
@@ -188,6 +326,21 @@ fn is_image_ext(path: &Path) -> bool {
         .unwrap_or_default()
 }
 
+/// Detect the image type from the magic bytes, for data without a file name
+fn sniff_image_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
 fn read_media_to_data_url<P: AsRef<Path>>(image_path: P) -> Result<String> {
     let mime_type = from_path(&image_path).first_or_octet_stream().to_string();
 
@@ -200,3 +353,43 @@ fn read_media_to_data_url<P: AsRef<Path>>(image_path: P) -> Result<String> {
 
     Ok(data_url)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_image_mime() {
+        assert_eq!(
+            sniff_image_mime(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("image/png")
+        );
+        assert_eq!(
+            sniff_image_mime(&[0xff, 0xd8, 0xff, 0xe0]),
+            Some("image/jpeg")
+        );
+        assert_eq!(sniff_image_mime(b"GIF89a\x01\0"), Some("image/gif"));
+        assert_eq!(
+            sniff_image_mime(b"RIFF\x24\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_image_mime(b"fn main() {}"), None);
+    }
+
+    #[test]
+    fn test_split_questions() {
+        assert_eq!(
+            split_questions("What is Node.js? How do I install it on Debian? Thanks."),
+            vec!["What is Node.js?", "How do I install it on Debian?"]
+        );
+        assert_eq!(
+            split_questions("1. Is Rust memory safe?\n2. What is a lifetime?"),
+            vec!["Is Rust memory safe?", "What is a lifetime?"]
+        );
+        assert!(split_questions("What is a monad? Why?").is_empty());
+        assert!(
+            split_questions("Explain this code.\n```\nfoo()?\n```\nWhat does ? do here?")
+                .is_empty()
+        );
+    }
+}
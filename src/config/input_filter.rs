@@ -0,0 +1,139 @@
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref ANSI_RE: Regex =
+        Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(\x07|\x1b\\)").unwrap();
+}
+
+/// Filters on piped or pasted inputs, reducing the tokens wasted on the noise of logs
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InputFilters {
+    /// Only filter inputs with at least this many lines
+    pub min_lines: usize,
+    /// Remove ANSI escape sequences, e.g. colors
+    pub strip_ansi: bool,
+    /// Replace consecutive identical lines by one line and a count
+    pub collapse_repeats: bool,
+    /// Keep only the last lines
+    pub max_lines: Option<usize>,
+    /// Keep only the last kilobytes
+    pub max_kb: Option<usize>,
+}
+
+impl Default for InputFilters {
+    fn default() -> Self {
+        Self {
+            min_lines: 50,
+            strip_ansi: false,
+            collapse_repeats: false,
+            max_lines: None,
+            max_kb: None,
+        }
+    }
+}
+
+impl InputFilters {
+    /// Filter the text if it is long enough, otherwise return it unchanged
+    pub fn apply(&self, text: &str) -> String {
+        if text.lines().count() < self.min_lines {
+            return text.to_string();
+        }
+        let mut text = if self.strip_ansi {
+            ANSI_RE.replace_all(text, "").to_string()
+        } else {
+            text.to_string()
+        };
+        if self.collapse_repeats {
+            text = collapse_repeats(&text);
+        }
+        if let Some(max_lines) = self.max_lines {
+            text = keep_last_lines(&text, max_lines);
+        }
+        if let Some(max_kb) = self.max_kb {
+            text = keep_last_bytes(&text, max_kb * 1024);
+        }
+        text
+    }
+}
+
+fn collapse_repeats(text: &str) -> String {
+    let mut output: Vec<String> = vec![];
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        let mut count = 1;
+        while lines.peek() == Some(&line) {
+            lines.next();
+            count += 1;
+        }
+        output.push(line.to_string());
+        if count > 1 {
+            output.push(format!("[previous line repeated {} times]", count - 1));
+        }
+    }
+    output.join("\n")
+}
+
+fn keep_last_lines(text: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= max_lines {
+        return text.to_string();
+    }
+    let skipped = lines.len() - max_lines;
+    format!(
+        "[{skipped} earlier lines truncated]\n{}",
+        lines[skipped..].join("\n")
+    )
+}
+
+fn keep_last_bytes(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut start = text.len() - max_bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let tail = &text[start..];
+    let tail = match tail.find('\n') {
+        Some(i) => &tail[i + 1..],
+        None => tail,
+    };
+    format!(
+        "[{} earlier bytes truncated]\n{tail}",
+        text.len() - tail.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let text = "\x1b[31mstart\x1b[0m\nretry\nretry\nretry\nfailed\ndone";
+        let filters = InputFilters {
+            min_lines: 3,
+            ..Default::default()
+        };
+        assert_eq!(filters.apply(text), text);
+        let filters = InputFilters {
+            min_lines: 3,
+            strip_ansi: true,
+            collapse_repeats: true,
+            max_lines: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            filters.apply(text),
+            "[1 earlier lines truncated]\nretry\n[previous line repeated 2 times]\nfailed\ndone"
+        );
+        assert_eq!(filters.apply("a\nb"), "a\nb");
+        assert_eq!(
+            keep_last_bytes("line1\nline2\nline3", 8),
+            "[12 earlier bytes truncated]\nline3"
+        );
+    }
+}
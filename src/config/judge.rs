@@ -0,0 +1,79 @@
+use super::{GlobalConfig, Input};
+
+use crate::client::init_client;
+use crate::utils::extract_block;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+const JUDGE_PROMPT: &str = r#"You are a strict grader. Score the text against every criterion of the rubric.
+Reply with only a JSON object of this shape:
+{"scores": [{"criterion": "<criterion>", "score": <number>, "max": <number>, "reason": "<one sentence>"}]}"#;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CriterionScore {
+    pub criterion: String,
+    pub score: f64,
+    pub max: f64,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Scores of a text against a rubric
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JudgeReport {
+    pub model: String,
+    pub scores: Vec<CriterionScore>,
+    pub total: f64,
+    pub max_total: f64,
+}
+
+/// Ask the current model to score the text against the rubric
+pub fn judge(config: &GlobalConfig, rubric: &str, text: &str) -> Result<JudgeReport> {
+    let input = Input::from_str(&format!(
+        "{JUDGE_PROMPT}\n\nRubric:\n{rubric}\n\nText:\n{text}"
+    ));
+    let client = init_client(config)?;
+    let output = client.send_message(input)?;
+    let mut report = parse_report(&output)?;
+    report.model = client.model().id();
+    Ok(report)
+}
+
+fn parse_report(output: &str) -> Result<JudgeReport> {
+    #[derive(Deserialize)]
+    struct Reply {
+        scores: Vec<CriterionScore>,
+    }
+    let reply: Reply = serde_json::from_str(&extract_block(output))
+        .with_context(|| format!("Invalid reply of the judge: {output}"))?;
+    if reply.scores.is_empty() {
+        bail!("The judge returned no scores");
+    }
+    let total = reply.scores.iter().map(|v| v.score).sum();
+    let max_total = reply.scores.iter().map(|v| v.max).sum();
+    Ok(JudgeReport {
+        model: String::new(),
+        scores: reply.scores,
+        total,
+        max_total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_report() {
+        let output = r#"```json
+{"scores": [{"criterion": "Accuracy", "score": 4, "max": 5, "reason": "One slip"},
+{"criterion": "Clarity", "score": 3, "max": 5}]}
+```"#;
+        let report = parse_report(output).unwrap();
+        assert_eq!(report.scores.len(), 2);
+        assert_eq!(report.total, 7.0);
+        assert_eq!(report.max_total, 10.0);
+        assert!(parse_report("looks good").is_err());
+    }
+}
@@ -1,24 +1,68 @@
-mod input;
+pub(crate) mod backup;
+pub(crate) mod batch;
+pub(crate) mod best_of;
+mod constraints;
+mod export;
+pub(crate) mod input;
+mod input_filter;
+pub(crate) mod judge;
+mod model_cache;
+mod model_guard;
+mod output_normalization;
+mod packing;
+pub(crate) mod pipeline;
+mod plugin;
+pub(crate) mod prompt_library;
+mod retention;
 mod role;
+pub(crate) mod role_pack;
+pub(crate) mod schema;
 mod session;
-
+mod snippet;
+mod template;
+mod usage;
+mod workspace;
+
+pub use self::constraints::OutputConstraints;
+use self::export::finetune_record;
+pub use self::export::FinetuneOptions;
 pub use self::input::Input;
-use self::role::Role;
-use self::session::{Session, TEMP_SESSION_NAME};
+use self::input::{is_image_ext, resolve_path};
+pub use self::input_filter::InputFilters;
+use self::model_cache::{CachedCapabilities, ModelCache};
+pub use self::model_guard::ModelGuard;
+pub use self::output_normalization::OutputNormalization;
+pub use self::packing::PackStrategy;
+use self::packing::{pack, Attachment};
+pub use self::pipeline::PipelineStage;
+pub use self::plugin::Plugin;
+pub use self::prompt_library::SavedPrompt;
+pub use self::retention::SessionRetention;
+pub use self::role::Role;
+use self::schema::load_schema;
+use self::session::{dir_session_name, is_auto_session, Session, TEMP_SESSION_NAME};
+use self::snippet::Snippet;
+use self::template::ConversationTemplate;
+pub use self::usage::UsageAlert;
+use self::usage::{Usage, UsageAlerts};
+use self::workspace::{build_context, expand_pattern};
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, ExtraConfig, Message,
-    Model, OpenAIClient, SendData,
+    create_client_config, list_client_models, list_client_types, list_models, Cassette,
+    ClientConfig, ExtraConfig, Message, MessageContent, MessageRole, Model, OpenAIClient, SendData,
+};
+use crate::render::{create_renderer, RenderOptions, RendererKind};
+use crate::utils::{
+    count_tokens, env_context, get_env_name, git_toplevel, light_theme_from_colorfgbg, now,
+    render_prompt, CopyTarget,
 };
-use crate::render::{MarkdownRender, RenderOptions};
-use crate::utils::{get_env_name, light_theme_from_colorfgbg, now, render_prompt, set_text};
 
 use anyhow::{anyhow, bail, Context, Result};
 use inquire::{Confirm, Select, Text};
 use is_terminal::IsTerminal;
 use parking_lot::RwLock;
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::{
     env,
     fs::{create_dir_all, read_dir, read_to_string, remove_file, File, OpenOptions},
@@ -35,11 +79,29 @@ const LIGHT_THEME: &[u8] = include_bytes!("../../assets/monokai-extended-light.t
 
 const CONFIG_FILE_NAME: &str = "config.yaml";
 const ROLES_FILE_NAME: &str = "roles.yaml";
+const SNIPPETS_FILE_NAME: &str = "snippets.yaml";
 const MESSAGES_FILE_NAME: &str = "messages.md";
 const SESSIONS_DIR_NAME: &str = "sessions";
+const TEMPLATES_DIR_NAME: &str = "templates";
+const PROMPTS_DIR_NAME: &str = "prompts";
+const PLUGINS_DIR_NAME: &str = "plugins";
+const ROLE_PACKS_DIR_NAME: &str = "role-packs";
+const USAGE_FILE_NAME: &str = "usage.yaml";
+const MODEL_CACHE_FILE_NAME: &str = "models-cache.yaml";
+const DRAFTS_DIR_NAME: &str = "drafts";
+
+/// The token budget of the `.context` files when the model has no known context window
+const DEFAULT_CONTEXT_BUDGET: usize = 8000;
+
+const REASONING_EFFORTS: [&str; 3] = ["low", "medium", "high"];
+/// The smallest `thinking.budget_tokens` accepted by Anthropic
+const MIN_THINKING_BUDGET: usize = 1024;
 
 const CLIENTS_FIELD: &str = "clients";
 
+/// The `.set` keys whose value is free text, taking the rest of the line or opened in the editor
+pub const TEXT_SET_KEYS: [&str; 3] = ["instructions", "default_system_prompt", "answer_language"];
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct Config {
@@ -51,8 +113,10 @@ pub struct Config {
     pub default_temperature: Option<f64>,
     /// Dry-run flag
     pub dry_run: bool,
-    /// Whether to save the message
+    /// Whether to save the messages, sessions and usage to disk
     pub save: bool,
+    /// Whether to keep the reply cut short by Ctrl-C (always, ask, never)
+    pub keep_partial_reply: KeepPartialReply,
     /// Whether to disable highlight
     pub highlight: bool,
     /// Whether to use a light theme
@@ -61,8 +125,84 @@ pub struct Config {
     pub wrap: Option<String>,
     /// Whether wrap code block
     pub wrap_code: bool,
+    /// How to render the replies (auto, markdown, minimal, plain, machine)
+    pub renderer: RendererKind,
     /// Automatically copy the last output to the clipboard
     pub auto_copy: bool,
+    /// How many copied replies to keep for `.copy list` and `.copy restore`
+    pub copy_history_size: usize,
+    /// Always write the newest raw reply to this file, for editor plugins and other tools
+    pub last_reply_file: Option<String>,
+    /// Whether to stream the reply
+    pub stream: bool,
+    /// Show the throughput while a reply streams, and the first-token latency after it
+    pub stream_stats: bool,
+    /// Fold replies longer than this many lines in the REPL, `.last full` shows them whole
+    pub fold_output: Option<usize>,
+    /// Number of candidate replies to request in the REPL
+    pub candidates: usize,
+    /// The OpenAI `reasoning_effort` (low, medium, high), sent to the models supporting it
+    pub reasoning_effort: Option<String>,
+    /// The Anthropic `thinking.budget_tokens`, sent to the models supporting it
+    pub thinking_budget: Option<usize>,
+    /// Switch to this model when the input exceeds the context window of the current model
+    pub fallback_model: Option<String>,
+    /// The model of `aichat judge`, defaults to the current model
+    pub judge_model: Option<String>,
+    /// The model proposing the follow-up questions of `.ask-followups`, defaults to the current model
+    pub followups_model: Option<String>,
+    /// Ask for confirmation before a session consumes more tokens than this value
+    pub session_token_budget: Option<usize>,
+    /// Ask for confirmation before the tokens consumed today exceed this value
+    pub daily_token_budget: Option<usize>,
+    /// Ask for confirmation before the estimated spend of today, in USD, exceeds this value
+    pub daily_cost_budget: Option<f64>,
+    /// Warn when the usage of the week or of the month crosses these thresholds
+    pub usage_alerts: UsageAlerts,
+    /// Ask for confirmation before submitting a REPL input longer than this many tokens
+    pub paste_token_limit: Option<usize>,
+    /// Prepend the OS, shell, cwd, git branch, date and locale to the system prompt
+    pub env_context: bool,
+    /// Answer the questions of an input asking several of them in separate sections
+    pub split_questions: bool,
+    /// Resume the session of the git repository or of the directory when the REPL starts
+    pub auto_session_per_dir: bool,
+    /// Always answer in this language, whatever the language of the question
+    pub answer_language: Option<String>,
+    /// The system prompt of the chat without a role
+    pub default_system_prompt: Option<String>,
+    /// The URL of the role packs installed by name, `{name}` is replaced by the name
+    pub role_pack_registry: Option<String>,
+    /// How many saved sessions `--prune-sessions` keeps, and for how long
+    pub session_retention: SessionRetention,
+    /// Drop the attachments beyond the input budget of the model, keeping the most recent, the smallest or the most relevant ones
+    pub context_packing: Option<PackStrategy>,
+    /// Strip ANSI escapes, collapse repeated lines and truncate long piped or pasted inputs
+    pub input_filters: InputFilters,
+    /// Banned strings and required regexes on the reply, violations trigger a retry
+    pub output_constraints: OutputConstraints,
+    /// Trailing newline, line endings and chatter stripping of the `--code` and piped outputs
+    pub output_normalization: OutputNormalization,
+    /// Named chains of models, each stage refines the output of the previous one
+    pub pipelines: HashMap<String, Vec<PipelineStage>>,
+    /// Ask for confirmation before sending large or any messages to these models, by model id
+    pub model_guards: HashMap<String, ModelGuard>,
+    /// Ask for confirmation before sending fetched web pages that contain instruction-like content
+    pub scan_untrusted: bool,
+    /// Store a one-paragraph summary in a named session when leaving it
+    pub summarize_on_exit: bool,
+    /// Suggest the execute or code role when a question looks like one, and no role is active
+    pub suggest_role: bool,
+    /// Ask for confirmation before resending the last input of the session
+    pub confirm_duplicate: bool,
+    /// Switch sessions whose model was retired by the provider to its replacement
+    pub auto_migrate_models: bool,
+    /// Allow `aichat upgrade` to check GitHub for new releases
+    pub check_updates: bool,
+    /// Disable network features, only models served on this machine are usable
+    pub offline: bool,
+    /// Low-bandwidth mode for slow or metered connections
+    pub lite: bool,
     /// REPL keybindings. (emacs, vi)
     pub keybindings: Keybindings,
     /// Set a default role or session (role:<name>, session:<name>)
@@ -71,6 +211,8 @@ pub struct Config {
     pub compress_threshold: usize,
     /// The prompt for summarizing session messages
     pub summarize_prompt: String,
+    /// The prompt for summarizing a file or a web page
+    pub summarize_text_prompt: String,
     // The prompt for the summary of the session
     pub summary_prompt: String,
     /// REPL left prompt
@@ -82,18 +224,53 @@ pub struct Config {
     /// Predefined roles
     #[serde(skip)]
     pub roles: Vec<Role>,
+    /// Predefined snippets
+    #[serde(skip)]
+    pub snippets: Vec<Snippet>,
+    /// Plugins in the plugins directory, exposed as REPL commands
+    #[serde(skip)]
+    pub plugins: Vec<Plugin>,
     /// Current selected role
     #[serde(skip)]
     pub role: Option<Role>,
     /// Current session
     #[serde(skip)]
     pub session: Option<Session>,
+    /// Sessions kept open in the background, see `.session switch`
+    #[serde(skip)]
+    pub background_sessions: Vec<SessionState>,
     #[serde(skip)]
     pub model: Model,
     #[serde(skip)]
     pub last_message: Option<(Input, String)>,
     #[serde(skip)]
+    pub last_candidates: Option<(Input, Vec<String>)>,
+    /// The text and files of the last question, kept even if it failed
+    #[serde(skip)]
+    pub last_input: Option<(String, Vec<String>)>,
+    #[serde(skip)]
     pub temperature: Option<f64>,
+    /// Treat every plain input in the REPL as a shell command request
+    #[serde(skip)]
+    pub shell_mode: bool,
+    /// The cassette of `--record` or `--replay`
+    #[serde(skip)]
+    pub cassette: Option<Cassette>,
+    /// The JSON schema of `--schema` that the reply must match
+    #[serde(skip)]
+    pub output_schema: Option<serde_json::Value>,
+    /// Whether the REPL is running, some options only apply to it
+    #[serde(skip)]
+    pub in_repl: bool,
+    /// Set by `--no-save`, keeps `save` off for the whole run
+    #[serde(skip)]
+    pub no_save: bool,
+    /// What was learned about the models, loaded once from `models-cache.yaml`
+    #[serde(skip)]
+    pub model_cache: ModelCache,
+    /// The copied texts, the latest first
+    #[serde(skip)]
+    pub copy_history: VecDeque<String>,
 }
 
 impl Default for Config {
@@ -102,27 +279,79 @@ impl Default for Config {
             model_id: None,
             default_temperature: None,
             save: true,
+            keep_partial_reply: Default::default(),
             highlight: true,
             dry_run: false,
             light_theme: false,
             wrap: None,
             wrap_code: false,
+            renderer: Default::default(),
             auto_copy: false,
+            copy_history_size: 10,
+            last_reply_file: None,
+            stream: true,
+            stream_stats: false,
+            fold_output: None,
+            candidates: 1,
+            reasoning_effort: None,
+            thinking_budget: None,
+            fallback_model: None,
+            judge_model: None,
+            followups_model: None,
+            session_token_budget: None,
+            daily_token_budget: None,
+            daily_cost_budget: None,
+            usage_alerts: Default::default(),
+            paste_token_limit: None,
+            env_context: false,
+            split_questions: false,
+            auto_session_per_dir: false,
+            answer_language: None,
+            default_system_prompt: None,
+            scan_untrusted: false,
+            summarize_on_exit: false,
+            suggest_role: false,
+            confirm_duplicate: false,
+            auto_migrate_models: false,
+            check_updates: true,
+            offline: false,
+            lite: false,
+            role_pack_registry: None,
+            session_retention: Default::default(),
+            context_packing: None,
+            input_filters: Default::default(),
+            output_normalization: Default::default(),
+            output_constraints: Default::default(),
+            pipelines: Default::default(),
+            model_guards: Default::default(),
             keybindings: Default::default(),
             prelude: String::new(),
             compress_threshold: 2000,
             summarize_prompt: "Summarize the discussion briefly in 200 words or less to use as a prompt for future context.".to_string(),
+            summarize_text_prompt: "Summarize the following content briefly, keeping the key points:".into(),
             summary_prompt: "This is a summary of the chat history as a recap: ".into(),
-            left_prompt: "{color.green}{?session {session}{?role /}}{role}{color.cyan}{?session )}{!session >}{color.reset} ".to_string(),
+            left_prompt: "{?shell {color.yellow}$ }{color.green}{?session {session}{?role /}}{role}{color.cyan}{?session )}{!session >}{color.reset} ".to_string(),
             right_prompt: "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}"
                 .to_string(),
             clients: vec![ClientConfig::default()],
             roles: vec![],
+            snippets: vec![],
+            plugins: vec![],
             role: None,
             session: None,
+            background_sessions: vec![],
             model: Default::default(),
             last_message: None,
+            last_candidates: None,
+            last_input: None,
             temperature: None,
+            shell_mode: false,
+            cassette: None,
+            output_schema: None,
+            in_repl: false,
+            no_save: false,
+            model_cache: ModelCache::default(),
+            copy_history: VecDeque::new(),
         }
     }
 }
@@ -157,6 +386,8 @@ impl Config {
         config.temperature = config.default_temperature;
 
         config.load_roles()?;
+        config.load_snippets()?;
+        config.load_model_cache();
 
         config.setup_model()?;
         config.setup_highlight();
@@ -219,6 +450,18 @@ impl Config {
         Ok(path)
     }
 
+    /// Save a reply cut short by Ctrl-C, marking it as truncated in the session
+    pub fn save_partial_message(&mut self, input: Input, output: &str) -> Result<()> {
+        self.save_message(input, output)?;
+        if self.dry_run {
+            return Ok(());
+        }
+        if let Some(session) = self.session.as_mut() {
+            session.mark_last_truncated();
+        }
+        Ok(())
+    }
+
     pub fn save_message(&mut self, input: Input, output: &str) -> Result<()> {
         self.last_message = Some((input.clone(), output.to_string()));
 
@@ -226,6 +469,16 @@ impl Config {
             return Ok(());
         }
 
+        if let Err(err) = self.write_last_reply(output) {
+            warn!("{err:?}");
+        }
+
+        if !self.no_save {
+            if let Err(err) = self.record_usage(&input, output) {
+                warn!("{err:?}");
+            }
+        }
+
         if let Some(session) = self.session.as_mut() {
             session.add_message(&input, output)?;
             return Ok(());
@@ -235,7 +488,7 @@ impl Config {
             return Ok(());
         }
         let mut file = self.open_message_file()?;
-        if output.is_empty() || !self.save {
+        if output.is_empty() {
             return Ok(());
         }
         let timestamp = now();
@@ -256,12 +509,321 @@ impl Config {
             .with_context(|| "Failed to save message")
     }
 
-    pub fn maybe_copy(&self, text: &str) {
+    /// Overwrite `last_reply_file` with the raw reply
+    fn write_last_reply(&self, output: &str) -> Result<()> {
+        let path = match &self.last_reply_file {
+            Some(path) if !self.no_save && !output.is_empty() => path,
+            _ => return Ok(()),
+        };
+        let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(path),
+        };
+        if let Some(parent) = path.parent().filter(|v| !v.as_os_str().is_empty()) {
+            create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(&path, output)
+            .with_context(|| format!("Failed to write the last reply to {}", path.display()))
+    }
+
+    pub fn guard_budget(&self, input: &Input) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let messages = self.build_messages(input)?;
+        let tokens = self.model.total_tokens(&messages);
+        self.guard_model(tokens)?;
+        let mut exceeded = vec![];
+        if let (Some(budget), Some(session)) = (self.session_token_budget, &self.session) {
+            let total = session.tokens() + tokens;
+            if total > budget {
+                exceeded.push(format!("session token budget ({total}/{budget})"));
+            }
+        }
+        if self.daily_token_budget.is_some() || self.daily_cost_budget.is_some() {
+            let usage = Usage::load(&Self::usage_file()?)?;
+            if let Some(budget) = self.daily_token_budget {
+                let total = usage.today() + tokens;
+                if total > budget {
+                    exceeded.push(format!("daily token budget ({total}/{budget})"));
+                }
+            }
+            if let Some(budget) = self.daily_cost_budget {
+                let total = usage.today_cost() + self.model.input_cost(tokens).unwrap_or_default();
+                if total > budget {
+                    exceeded.push(format!("daily cost budget (${total:.2}/${budget:.2})"));
+                }
+            }
+        }
+        if exceeded.is_empty() {
+            return Ok(());
+        }
+        let message = format!("This message exceeds the {}", exceeded.join(" and "));
+        if !std::io::stdin().is_terminal() {
+            bail!("{message}");
+        }
+        let ans = Confirm::new(&format!("{message}, continue?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            bail!("{message}");
+        }
+        Ok(())
+    }
+
+    /// Guard against sending a large context to an expensive model by accident
+    fn guard_model(&self, tokens: usize) -> Result<()> {
+        let model_id = self.model.id();
+        match self.model_guards.get(&model_id) {
+            Some(guard) if guard.needs_confirm(tokens) => {}
+            _ => return Ok(()),
+        }
+        let cost = match self.model.input_cost(tokens) {
+            Some(cost) => format!(" and will cost ~${cost:.2}"),
+            None => String::new(),
+        };
+        let message = format!("This message has {tokens} tokens{cost} on '{model_id}'");
+        if !std::io::stdin().is_terminal() {
+            bail!("{message}");
+        }
+        let ans = Confirm::new(&format!("{message}, continue?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            bail!("{message}");
+        }
+        Ok(())
+    }
+
+    pub fn pipeline(&self, name: &str) -> Result<Vec<PipelineStage>> {
+        self.pipelines
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown pipeline '{name}'"))
+    }
+
+    /// The global output constraints combined with the ones of the current role
+    pub fn output_constraints(&self) -> OutputConstraints {
+        self.output_constraints_with_role(self.role.as_ref())
+    }
+
+    /// The global output constraints combined with the ones of `role`
+    pub fn output_constraints_with_role(&self, role: Option<&Role>) -> OutputConstraints {
+        let mut constraints = match role.and_then(|v| v.constraints.as_ref()) {
+            Some(constraints) => self.output_constraints.merge(constraints),
+            None => self.output_constraints.clone(),
+        };
+        constraints.schema = self.output_schema.clone();
+        constraints
+    }
+
+    /// Require the replies to be JSON matching the schema file
+    pub fn set_output_schema(&mut self, path: &str) -> Result<()> {
+        self.output_schema = Some(load_schema(Path::new(path))?);
+        Ok(())
+    }
+
+    /// Tell the model the JSON schema that the reply must match
+    pub fn with_output_schema(&self, input: Input) -> Input {
+        match &self.output_schema {
+            Some(schema) => input.with_feedback(&format!(
+                "Reply only with a JSON value matching this JSON schema, without any explanation:\n{schema}"
+            )),
+            None => input,
+        }
+    }
+
+    /// Guard against prompt injection in the ingested web pages and documents
+    pub fn guard_untrusted(&self, input: &Input) -> Result<()> {
+        if !self.scan_untrusted {
+            return Ok(());
+        }
+        let findings = input.untrusted_findings();
+        if findings.is_empty() {
+            return Ok(());
+        }
+        eprintln!("Found instruction-like content in the ingested content:");
+        for finding in &findings {
+            eprintln!("  {finding}");
+        }
+        let message = "The ingested content may contain a prompt injection";
+        if !std::io::stdin().is_terminal() {
+            bail!("{message}");
+        }
+        let ans = Confirm::new(&format!("{message}, send it anyway?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            bail!("{message}");
+        }
+        Ok(())
+    }
+
+    /// Guard against submitting the last input of the session again by accident
+    pub fn guard_duplicate(&self, input: &Input) -> Result<()> {
+        if !self.confirm_duplicate {
+            return Ok(());
+        }
+        match &self.session {
+            Some(session) if session.is_last_input(input) => {}
+            _ => return Ok(()),
+        }
+        let ans =
+            Confirm::new("The input is the same as the last one of the session, send it again?")
+                .with_default(false)
+                .prompt()?;
+        if !ans {
+            bail!("Discarded the duplicate input");
+        }
+        Ok(())
+    }
+
+    /// Drop the attached files that do not fit the input budget of the model, by `context_packing`
+    pub fn pack_files(&self, text: &str, files: Vec<String>) -> Result<Vec<String>> {
+        for file in &files {
+            if (file.starts_with("http://") || file.starts_with("https://"))
+                && !is_image_ext(Path::new(file))
+            {
+                self.guard_fetch(file)?;
+            }
+        }
+        let (strategy, max_input_tokens) = match (self.context_packing, self.model.max_input_tokens)
+        {
+            (Some(strategy), Some(max_input_tokens)) => (strategy, max_input_tokens),
+            _ => return Ok(files),
+        };
+        let mut attachments = vec![];
+        for file in &files {
+            let path = match resolve_path(file) {
+                Some(path) if path.is_file() && !is_image_ext(&path) => path,
+                _ => continue,
+            };
+            let text = match read_to_string(&path) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            let modified = path
+                .metadata()
+                .and_then(|v| v.modified())
+                .unwrap_or(std::time::SystemTime::UNIX_EPOCH);
+            attachments.push(Attachment {
+                name: file.clone(),
+                tokens: count_tokens(&text),
+                text,
+                modified,
+            });
+        }
+        let messages = self.build_messages(&Input::from_str(text))?;
+        let budget = max_input_tokens.saturating_sub(self.model.total_tokens(&messages));
+        if attachments.iter().map(|v| v.tokens).sum::<usize>() <= budget {
+            return Ok(files);
+        }
+        let (_, dropped) = pack(&attachments, budget, strategy, text);
+        eprintln!(
+            "Dropped attachments to fit the input budget of '{}' ({budget} tokens): {}",
+            self.model.id(),
+            dropped.join(", ")
+        );
+        Ok(files.into_iter().filter(|v| !dropped.contains(v)).collect())
+    }
+
+    /// Apply the input filters to a piped or pasted text
+    pub fn filter_input(&self, text: &str) -> String {
+        let filtered = self.input_filters.apply(text);
+        let (lines, filtered_lines) = (text.lines().count(), filtered.lines().count());
+        if filtered_lines < lines {
+            eprintln!("Filtered the input from {lines} to {filtered_lines} lines.");
+        }
+        filtered
+    }
+
+    /// Guard against submitting a large paste by accident
+    pub fn guard_paste(&self, text: &str) -> Result<()> {
+        let limit = match self.paste_token_limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let tokens = count_tokens(text);
+        if tokens <= limit {
+            return Ok(());
+        }
+        let ans = Confirm::new(&format!(
+            "The input has {tokens} tokens, more than the paste token limit ({limit}), submit it?"
+        ))
+        .with_default(false)
+        .prompt()?;
+        if !ans {
+            bail!("Discarded the input of {tokens} tokens");
+        }
+        Ok(())
+    }
+
+    pub fn maybe_copy(&mut self, text: &str) {
         if self.auto_copy {
-            let _ = set_text(text);
+            let _ = self.copy_text(text);
         }
     }
 
+    /// Copy the text to the clipboard and keep it in the copy history
+    pub fn copy_text(&mut self, text: &str) -> Result<()> {
+        self.copy_text_to(&CopyTarget::Clipboard, text)
+    }
+
+    /// Copy the text to the clipboard, the primary selection, the tmux buffer or a file, and keep
+    /// it in the copy history
+    pub fn copy_text_to(&mut self, target: &CopyTarget, text: &str) -> Result<()> {
+        if text.is_empty() {
+            bail!("Empty text")
+        }
+        target.set_text(text)?;
+        self.copy_history.retain(|v| v != text);
+        self.copy_history.push_front(text.to_string());
+        self.copy_history.truncate(self.copy_history_size.max(1));
+        Ok(())
+    }
+
+    /// Copy the n-th text of the copy history again
+    pub fn restore_copy(&mut self, target: &CopyTarget, n: usize) -> Result<()> {
+        let text = self
+            .copy_history
+            .get(n.wrapping_sub(1))
+            .cloned()
+            .ok_or_else(|| anyhow!("No copied text {n}, see '.copy list'"))?;
+        self.copy_text_to(target, &text)
+    }
+
+    pub fn list_copy_history(&self) -> String {
+        if self.copy_history.is_empty() {
+            return "No copied texts".into();
+        }
+        self.copy_history
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let line: String = text
+                    .trim()
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(60)
+                    .collect();
+                format!("{:>3}. {line} ({} chars)", i + 1, text.chars().count())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn should_stream(&self, model: &Model) -> bool {
+        self.stream && !self.lite && !model.no_stream
+    }
+
+    /// The number of lines to fold the replies at, only in the REPL
+    pub fn fold_lines(&self) -> Option<usize> {
+        self.fold_output.filter(|_| self.in_repl)
+    }
+
     pub fn config_file() -> Result<PathBuf> {
         Self::local_path(CONFIG_FILE_NAME)
     }
@@ -274,10 +836,30 @@ impl Config {
         )
     }
 
+    pub fn snippets_file() -> Result<PathBuf> {
+        let env_name = get_env_name("snippets_file");
+        env::var(env_name).map_or_else(
+            |_| Self::local_path(SNIPPETS_FILE_NAME),
+            |value| Ok(PathBuf::from(value)),
+        )
+    }
+
     pub fn messages_file() -> Result<PathBuf> {
         Self::local_path(MESSAGES_FILE_NAME)
     }
 
+    pub fn usage_file() -> Result<PathBuf> {
+        Self::local_path(USAGE_FILE_NAME)
+    }
+
+    pub fn model_cache_file() -> Result<PathBuf> {
+        Self::local_path(MODEL_CACHE_FILE_NAME)
+    }
+
+    pub fn drafts_dir() -> Result<PathBuf> {
+        Self::local_path(DRAFTS_DIR_NAME)
+    }
+
     pub fn sessions_dir() -> Result<PathBuf> {
         Self::local_path(SESSIONS_DIR_NAME)
     }
@@ -288,6 +870,91 @@ impl Config {
         Ok(path)
     }
 
+    pub fn templates_dir() -> Result<PathBuf> {
+        let env_name = get_env_name("templates_dir");
+        env::var(env_name).map_or_else(
+            |_| Self::local_path(TEMPLATES_DIR_NAME),
+            |value| Ok(PathBuf::from(value)),
+        )
+    }
+
+    pub fn template_file(name: &str) -> Result<PathBuf> {
+        let mut path = Self::templates_dir()?;
+        path.push(format!("{name}.yaml"));
+        Ok(path)
+    }
+
+    pub fn prompts_dir() -> Result<PathBuf> {
+        let env_name = get_env_name("prompts_dir");
+        env::var(env_name).map_or_else(
+            |_| Self::local_path(PROMPTS_DIR_NAME),
+            |value| Ok(PathBuf::from(value)),
+        )
+    }
+
+    pub fn prompt_file(name: &str) -> Result<PathBuf> {
+        let mut path = Self::prompts_dir()?;
+        path.push(format!("{name}.md"));
+        Ok(path)
+    }
+
+    pub fn role_packs_dir() -> Result<PathBuf> {
+        Self::local_path(ROLE_PACKS_DIR_NAME)
+    }
+
+    pub fn plugins_dir() -> Result<PathBuf> {
+        let env_name = get_env_name("plugins_dir");
+        env::var(env_name).map_or_else(
+            |_| Self::local_path(PLUGINS_DIR_NAME),
+            |value| Ok(PathBuf::from(value)),
+        )
+    }
+
+    pub fn load_plugins(&mut self) -> Result<()> {
+        self.plugins = plugin::load_plugins(&Self::plugins_dir()?, self.highlight);
+        Ok(())
+    }
+
+    /// Lint the roles, including the prompts that exceed the context window of the current model
+    pub fn lint_roles(&self) -> Vec<String> {
+        let mut issues = vec![];
+        for role in &self.roles {
+            let mut role_issues = role.lint();
+            if let Some(max_input_tokens) = self.model.max_input_tokens {
+                let tokens = count_tokens(&role.prompt);
+                if tokens >= max_input_tokens {
+                    role_issues.push(format!(
+                        "the prompt consumes {tokens} tokens, exceeding the max input tokens ({max_input_tokens}) of '{}'",
+                        self.model.id()
+                    ));
+                }
+            }
+            issues.extend(
+                role_issues
+                    .into_iter()
+                    .map(|v| format!("{}: {v}", role.name)),
+            );
+        }
+        issues
+    }
+
+    pub fn find_plugin(&self, cmd: &str) -> Option<Plugin> {
+        let name = cmd.strip_prefix('.')?;
+        self.plugins.iter().find(|v| v.name == name).cloned()
+    }
+
+    /// The request for a plugin command, with the context of the REPL
+    pub fn plugin_request(&self, args: Option<&str>) -> serde_json::Value {
+        serde_json::json!({
+            "type": "command",
+            "args": args.unwrap_or_default(),
+            "model": self.model.id(),
+            "role": self.role.as_ref().map(|v| v.name.clone()),
+            "session": self.session.as_ref().map(|v| v.name().to_string()),
+            "last_reply": self.last_reply(),
+        })
+    }
+
     pub fn set_role(&mut self, name: &str) -> Result<()> {
         let role = self.retrieve_role(name)?;
         self.set_role_obj(role)
@@ -307,10 +974,14 @@ impl Config {
         self.set_role_obj(role)
     }
 
-    pub fn set_code_role(&mut self) -> Result<()> {
-        let role = self
+    /// Use the code role, asking for code in `lang` if given
+    pub fn set_code_role(&mut self, lang: Option<&str>) -> Result<()> {
+        let mut role = self
             .retrieve_role(Role::CODE)
             .unwrap_or_else(|_| Role::for_code());
+        if let Some(lang) = lang {
+            role.prompt = format!("{}\nWrite the code in {lang}.", role.prompt);
+        }
         self.set_role_obj(role)
     }
 
@@ -361,6 +1032,121 @@ impl Config {
         }
     }
 
+    /// Add a path or a glob to the files sent with every request of the session, returning
+    /// the matching files
+    pub fn add_context(&mut self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => bail!("The context can only be set in a session"),
+        };
+        let pattern = env::current_dir()?.join(pattern).display().to_string();
+        let files = expand_pattern(&pattern)?;
+        if files.is_empty() {
+            bail!("No file matches '{pattern}'");
+        }
+        session.add_context(pattern);
+        Ok(files)
+    }
+
+    pub fn clear_context(&mut self) -> Result<()> {
+        match self.session.as_mut() {
+            Some(session) => session.clear_context(),
+            None => bail!("Not in a session"),
+        }
+        Ok(())
+    }
+
+    /// The context patterns of the session with their numbers of files and tokens
+    pub fn list_context(&self) -> Result<String> {
+        let session = match self.session.as_ref() {
+            Some(session) => session,
+            None => bail!("Not in a session"),
+        };
+        if session.context().is_empty() {
+            return Ok("No context, add files with '.context add <path|glob>'".into());
+        }
+        let mut lines = vec![];
+        for pattern in session.context() {
+            let files = expand_pattern(pattern).unwrap_or_default();
+            let (text, _) = build_context(&files, usize::MAX);
+            lines.push(format!(
+                "{pattern} ({} files, {} tokens)",
+                files.len(),
+                count_tokens(&text)
+            ));
+        }
+        if let Some(notice) = self.context_left_out() {
+            lines.push(notice);
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Which context files do not fit the token budget and are left out of the requests
+    pub fn context_left_out(&self) -> Option<String> {
+        let (_, skipped) = build_context(&self.context_files()?, self.context_budget());
+        if skipped.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "Left out over the budget of {} tokens: {}",
+            self.context_budget(),
+            skipped.join(", ")
+        ))
+    }
+
+    fn context_files(&self) -> Option<Vec<PathBuf>> {
+        let session = self.session.as_ref()?;
+        if session.context().is_empty() {
+            return None;
+        }
+        let mut files = vec![];
+        for pattern in session.context() {
+            for file in expand_pattern(pattern).unwrap_or_default() {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        Some(files)
+    }
+
+    /// Half of the context window of the model
+    fn context_budget(&self) -> usize {
+        self.model
+            .max_input_tokens
+            .map(|v| v / 2)
+            .unwrap_or(DEFAULT_CONTEXT_BUDGET)
+    }
+
+    /// The current content of the context files of the session, within the context budget
+    fn workspace_context(&self) -> Option<String> {
+        let files = self.context_files()?;
+        let (text, skipped) = build_context(&files, self.context_budget());
+        let mut output = format!("The current content of the project files:\n\n{text}");
+        if !skipped.is_empty() {
+            output.push_str(&format!(
+                "\n\n(Left out over the token budget: {})",
+                skipped.join(", ")
+            ));
+        }
+        Some(output)
+    }
+
+    /// Set the instructions of the current session, the value is free text
+    pub fn set_instructions(&mut self, value: &str) -> Result<()> {
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => bail!("The instructions can only be set in a session"),
+        };
+        let value = if value == "null" {
+            None
+        } else {
+            Some(value.to_string())
+        };
+        session.set_instructions(value);
+        Ok(())
+    }
+
     pub fn set_compress_threshold(&mut self, value: usize) {
         self.compress_threshold = value;
         if let Some(session) = self.session.as_mut() {
@@ -379,17 +1165,93 @@ impl Config {
     }
 
     pub fn build_messages(&self, input: &Input) -> Result<Vec<Message>> {
-        let messages = if let Some(session) = self.session.as_ref() {
+        self.build_messages_with_role(input, self.role.as_ref())
+    }
+
+    /// Build the messages with `role` in place of the current role, a session keeps its own
+    pub fn build_messages_with_role(
+        &self,
+        input: &Input,
+        role: Option<&Role>,
+    ) -> Result<Vec<Message>> {
+        let mut messages = if let Some(session) = self.session.as_ref() {
             session.build_emssages(input)
-        } else if let Some(role) = self.role.as_ref() {
+        } else if let Some(role) = role {
             role.build_messages(input)
         } else {
             let message = Message::new(input);
             vec![message]
         };
+        let has_role = role.is_some()
+            || self
+                .session
+                .as_ref()
+                .map(|v| v.role.is_some())
+                .unwrap_or_default();
+        if let Some(prompt) = self.default_system_prompt.as_ref().filter(|_| !has_role) {
+            add_system_prompt(&mut messages, prompt, true);
+        }
+        if let Some(instructions) = self.session.as_ref().and_then(|v| v.instructions()) {
+            add_system_prompt(&mut messages, instructions, false);
+        }
+        if let Some(system) = input.system() {
+            add_system_prompt(&mut messages, system, false);
+        }
+        if self.split_questions {
+            let questions = input.questions();
+            if !questions.is_empty() {
+                let list: Vec<String> = questions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| format!("{}. {v}", i + 1))
+                    .collect();
+                add_system_prompt(
+                    &mut messages,
+                    &format!("The message asks {} separate questions:\n{}\nAnswer each one in its own section headed by the question, in this order, without mixing the answers.", questions.len(), list.join("\n")),
+                    false,
+                );
+            }
+        }
+        if let Some(language) = &self.answer_language {
+            add_system_prompt(
+                &mut messages,
+                &format!("Always answer in {language}, whatever the language of the question, unless asked for another language."),
+                false,
+            );
+        }
+        if let Some(context) = self.workspace_context() {
+            add_system_prompt(&mut messages, &context, true);
+        }
+        if self.env_context {
+            add_system_prompt(&mut messages, &env_context(), true);
+        }
         Ok(messages)
     }
 
+    /// Characters, words, tokens and the estimated cost of sending the text
+    pub fn input_stats(&self, text: &str) -> Result<String> {
+        let input = Input::from_str(text);
+        let messages = self.build_messages(&input)?;
+        let total_tokens = self.model.total_tokens(&messages);
+        let cost = match self.model.input_cost(total_tokens) {
+            Some(cost) => format!("${cost:.6}"),
+            None => format!("- (no input_price for '{}')", self.model.id()),
+        };
+        let items = [
+            ("characters", text.chars().count().to_string()),
+            ("words", text.split_whitespace().count().to_string()),
+            ("tokens", count_tokens(text).to_string()),
+            ("total_tokens", total_tokens.to_string()),
+            ("cost", cost),
+        ];
+        let output = items
+            .iter()
+            .map(|(name, value)| format!("{name:<20}{value}"))
+            .collect::<Vec<String>>()
+            .join("\n");
+        Ok(output)
+    }
+
     pub fn set_wrap(&mut self, value: &str) -> Result<()> {
         if value == "no" {
             self.wrap = None;
@@ -405,11 +1267,12 @@ impl Config {
     }
 
     pub fn set_model(&mut self, value: &str) -> Result<()> {
-        let models = list_models(self);
+        let models = list_client_models(self, value);
         let model = Model::find(&models, value);
         match model {
             None => bail!("Invalid model '{}'", value),
             Some(model) => {
+                let model = self.adapt_model(&models, model);
                 if let Some(session) = self.session.as_mut() {
                     session.set_model(model.clone())?;
                 }
@@ -419,8 +1282,82 @@ impl Config {
         }
     }
 
+    /// Apply what was learned about the model, or guess from its name what a model missing from
+    /// the config supports
+    fn adapt_model(&self, models: &[Model], model: Model) -> Model {
+        if let Some(capabilities) = self.model_cache.get(&model.id()) {
+            return capabilities.apply(model);
+        }
+        if models.iter().any(|v| v.id() == model.id()) {
+            return model;
+        }
+        CachedCapabilities::lookup(&model).apply(model)
+    }
+
+    /// Stop streaming a model that rejected a streamed request, now and, unless nothing may be
+    /// saved, in later runs
+    pub fn remember_no_stream(&mut self, model: &Model) -> Result<()> {
+        if self.model.id() == model.id() {
+            self.model.no_stream = true;
+        }
+        let mut capabilities = self
+            .model_cache
+            .get(&model.id())
+            .unwrap_or_else(|| CachedCapabilities::lookup(model));
+        capabilities.stream = false;
+        self.model_cache.set(&model.id(), capabilities);
+        if self.no_save {
+            return Ok(());
+        }
+        let path = Self::model_cache_file()?;
+        ensure_parent_exists(&path)?;
+        self.model_cache.save(&path)
+    }
+
+    /// Fetching web pages is a network feature, unavailable in offline mode
+    pub fn guard_fetch(&self, url: &str) -> Result<()> {
+        if self.offline {
+            bail!("Unable to fetch {url} in offline mode");
+        }
+        Ok(())
+    }
+
+    /// Find a model by its id, its name or its client
+    pub fn resolve_model(&self, value: &str) -> Option<Model> {
+        let models = list_models(self);
+        models
+            .iter()
+            .find(|v| v.id() == value || v.name == value)
+            .cloned()
+            .or_else(|| Model::find(&models, value))
+    }
+
+    /// Suggest a model to use instead of the current one, when the provider cannot find it
+    pub fn suggest_model(&self) -> Option<String> {
+        Model::replacement(&list_models(self), &self.model.id())
+    }
+
+    fn migrate_session_model(&mut self, model: &str) -> Result<()> {
+        let new_model = match Model::deprecation(model) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        if self.auto_migrate_models {
+            self.set_model(new_model)?;
+            eprintln!("Migrated the session from the retired model '{model}' to '{new_model}'");
+        } else {
+            eprintln!(
+                "The model '{model}' of the session was retired, run '.model {new_model}' to switch"
+            );
+        }
+        Ok(())
+    }
+
     pub fn sys_info(&self) -> Result<String> {
         let display_path = |path: &Path| path.display().to_string();
+        let usage_today = Usage::load(&Self::usage_file()?)
+            .map(|v| v.today_summary())
+            .unwrap_or_else(|_| String::from("-"));
         let temperature = self
             .temperature
             .map_or_else(|| String::from("-"), |v| v.to_string());
@@ -438,18 +1375,89 @@ impl Config {
             ("temperature", temperature),
             ("dry_run", self.dry_run.to_string()),
             ("save", self.save.to_string()),
+            (
+                "keep_partial_reply",
+                self.keep_partial_reply.stringify().into(),
+            ),
             ("highlight", self.highlight.to_string()),
             ("light_theme", self.light_theme.to_string()),
             ("wrap", wrap),
             ("wrap_code", self.wrap_code.to_string()),
+            ("renderer", self.renderer.stringify().into()),
             ("auto_copy", self.auto_copy.to_string()),
+            ("copy_history_size", self.copy_history_size.to_string()),
+            (
+                "last_reply_file",
+                format_option_value(&self.last_reply_file),
+            ),
+            ("stream", self.stream.to_string()),
+            ("stream_stats", self.stream_stats.to_string()),
+            ("fold_output", format_option_value(&self.fold_output)),
+            ("candidates", self.candidates.to_string()),
+            (
+                "reasoning_effort",
+                format_option_value(&self.reasoning_effort),
+            ),
+            (
+                "thinking_budget",
+                format_option_value(&self.thinking_budget),
+            ),
+            ("fallback_model", format_option_value(&self.fallback_model)),
+            ("judge_model", format_option_value(&self.judge_model)),
+            (
+                "followups_model",
+                format_option_value(&self.followups_model),
+            ),
+            (
+                "session_token_budget",
+                format_option_value(&self.session_token_budget),
+            ),
+            (
+                "daily_token_budget",
+                format_option_value(&self.daily_token_budget),
+            ),
+            (
+                "daily_cost_budget",
+                format_option_value(&self.daily_cost_budget),
+            ),
+            ("usage_today", usage_today),
+            (
+                "paste_token_limit",
+                format_option_value(&self.paste_token_limit),
+            ),
+            ("env_context", self.env_context.to_string()),
+            ("split_questions", self.split_questions.to_string()),
+            (
+                "auto_session_per_dir",
+                self.auto_session_per_dir.to_string(),
+            ),
+            (
+                "answer_language",
+                format_option_value(&self.answer_language),
+            ),
+            (
+                "default_system_prompt",
+                format_option_value(&self.default_system_prompt),
+            ),
+            ("scan_untrusted", self.scan_untrusted.to_string()),
+            ("summarize_on_exit", self.summarize_on_exit.to_string()),
+            ("suggest_role", self.suggest_role.to_string()),
+            ("confirm_duplicate", self.confirm_duplicate.to_string()),
+            ("auto_migrate_models", self.auto_migrate_models.to_string()),
+            ("check_updates", self.check_updates.to_string()),
+            ("offline", self.offline.to_string()),
+            ("lite", self.lite.to_string()),
             ("keybindings", self.keybindings.stringify().into()),
             ("prelude", prelude),
             ("compress_threshold", self.compress_threshold.to_string()),
             ("config_file", display_path(&Self::config_file()?)),
             ("roles_file", display_path(&Self::roles_file()?)),
+            ("snippets_file", display_path(&Self::snippets_file()?)),
             ("messages_file", display_path(&Self::messages_file()?)),
             ("sessions_dir", display_path(&Self::sessions_dir()?)),
+            ("templates_dir", display_path(&Self::templates_dir()?)),
+            ("prompts_dir", display_path(&Self::prompts_dir()?)),
+            ("plugins_dir", display_path(&Self::plugins_dir()?)),
         ];
         let output = items
             .iter()
@@ -467,11 +1475,18 @@ impl Config {
         }
     }
 
+    pub fn session_stats(&self) -> Result<String> {
+        match &self.session {
+            Some(session) => Ok(session.stats()),
+            None => bail!("No session"),
+        }
+    }
+
     pub fn session_info(&self) -> Result<String> {
         if let Some(session) = &self.session {
             let render_options = self.get_render_options()?;
-            let mut markdown_render = MarkdownRender::init(render_options)?;
-            session.info(&mut markdown_render)
+            let mut render = create_renderer(render_options)?;
+            session.info(render.as_mut())
         } else {
             bail!("No session")
         }
@@ -487,6 +1502,24 @@ impl Config {
         }
     }
 
+    pub fn set_candidates(&mut self, input: Input, outputs: Vec<String>) {
+        self.last_candidates = Some((input, outputs));
+    }
+
+    pub fn pick_candidate(&mut self, index: usize) -> Result<(Input, String)> {
+        let (input, mut outputs) = self
+            .last_candidates
+            .take()
+            .ok_or_else(|| anyhow!("No candidates to pick"))?;
+        if index == 0 || index > outputs.len() {
+            let len = outputs.len();
+            self.last_candidates = Some((input, outputs));
+            bail!("Invalid candidate, choose one of 1..={len}");
+        }
+        let output = outputs.swap_remove(index - 1);
+        Ok((input, output))
+    }
+
     pub fn last_reply(&self) -> &str {
         self.last_message
             .as_ref()
@@ -504,9 +1537,40 @@ impl Config {
                     "temperature ",
                     "compress_threshold",
                     "save ",
+                    "keep_partial_reply ",
                     "highlight ",
+                    "renderer ",
                     "dry_run ",
                     "auto_copy ",
+                    "copy_history_size ",
+                    "last_reply_file ",
+                    "stream ",
+                    "stream_stats ",
+                    "fold_output ",
+                    "candidates ",
+                    "reasoning_effort ",
+                    "thinking_budget ",
+                    "fallback_model ",
+                    "judge_model ",
+                    "followups_model ",
+                    "session_token_budget ",
+                    "daily_token_budget ",
+                    "daily_cost_budget ",
+                    "paste_token_limit ",
+                    "env_context ",
+                    "split_questions ",
+                    "auto_session_per_dir ",
+                    "answer_language ",
+                    "default_system_prompt ",
+                    "instructions ",
+                    "scan_untrusted ",
+                    "summarize_on_exit ",
+                    "suggest_role ",
+                    "confirm_duplicate ",
+                    "auto_migrate_models ",
+                    "check_updates ",
+                    "offline ",
+                    "lite ",
                 ]
                 .into_iter()
                 .map(|v| v.to_string())
@@ -519,8 +1583,36 @@ impl Config {
             let values = match args[0] {
                 "save" => to_vec(!self.save),
                 "highlight" => to_vec(!self.highlight),
+                "reasoning_effort" => REASONING_EFFORTS.iter().map(|v| v.to_string()).collect(),
+                "keep_partial_reply" => ["always", "ask", "never"]
+                    .into_iter()
+                    .map(|v| v.to_string())
+                    .collect(),
+                "renderer" => ["auto", "markdown", "minimal", "plain", "machine"]
+                    .into_iter()
+                    .map(|v| v.to_string())
+                    .collect(),
                 "dry_run" => to_vec(!self.dry_run),
+                "switch" if cmd == ".session" => self
+                    .background_sessions
+                    .iter()
+                    .map(|v| v.name().to_string())
+                    .chain(self.list_sessions())
+                    .collect(),
                 "auto_copy" => to_vec(!self.auto_copy),
+                "stream" => to_vec(!self.stream),
+                "stream_stats" => to_vec(!self.stream_stats),
+                "env_context" => to_vec(!self.env_context),
+                "split_questions" => to_vec(!self.split_questions),
+                "auto_session_per_dir" => to_vec(!self.auto_session_per_dir),
+                "scan_untrusted" => to_vec(!self.scan_untrusted),
+                "summarize_on_exit" => to_vec(!self.summarize_on_exit),
+                "suggest_role" => to_vec(!self.suggest_role),
+                "confirm_duplicate" => to_vec(!self.confirm_duplicate),
+                "auto_migrate_models" => to_vec(!self.auto_migrate_models),
+                "check_updates" => to_vec(!self.check_updates),
+                "offline" => to_vec(!self.offline),
+                "lite" => to_vec(!self.lite),
                 _ => vec![],
             };
             (values, args[1])
@@ -533,7 +1625,67 @@ impl Config {
             .collect()
     }
 
+    /// The current value of a free-text `.set` key
+    pub fn text_setting(&self, key: &str) -> Option<String> {
+        match key {
+            "instructions" => self
+                .session
+                .as_ref()
+                .and_then(|v| v.instructions())
+                .map(|v| v.to_string()),
+            "default_system_prompt" => self.default_system_prompt.clone(),
+            "answer_language" => self.answer_language.clone(),
+            _ => None,
+        }
+    }
+
+    fn update_text(&mut self, key: &str, value: &str) -> Result<()> {
+        let text = if value == "null" {
+            None
+        } else {
+            Some(value.to_string())
+        };
+        match key {
+            "instructions" => self.set_instructions(value)?,
+            "default_system_prompt" => self.default_system_prompt = text,
+            "answer_language" => self.answer_language = text,
+            _ => bail!("Unknown key '{key}'"),
+        }
+        Ok(())
+    }
+
+    /// Write `<key> <value>` to the config file, keeping the other lines and the comment of the key
+    pub fn save_setting(&self, data: &str) -> Result<()> {
+        let (key, value) = data
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("Usage: .set --save <key> <value>"))?;
+        if self.no_save {
+            bail!("Cannot write the config file, the run was started with --no-save");
+        }
+        let config_path = Self::config_file()?;
+        let text = read_to_string(&config_path)
+            .with_context(|| format!("Failed to load config at {}", config_path.display()))?;
+        let value = match value.trim() {
+            value if TEXT_SET_KEYS.contains(&key) && value != "null" => {
+                serde_json::to_string(value).with_context(|| "Invalid value")?
+            }
+            value => value.to_string(),
+        };
+        let text = set_yaml_key(&text, key, &value);
+        serde_yaml::from_str::<serde_yaml::Value>(&text)
+            .with_context(|| format!("Invalid value '{value}' for '{key}'"))?;
+        std::fs::write(&config_path, text)
+            .with_context(|| format!("Failed to write to config at {}", config_path.display()))?;
+        Ok(())
+    }
+
     pub fn update(&mut self, data: &str) -> Result<()> {
+        if let Some((key, value)) = data.trim().split_once(char::is_whitespace) {
+            if TEXT_SET_KEYS.contains(&key) {
+                return self.update_text(key, value.trim());
+            }
+        }
         let parts: Vec<&str> = data.split_whitespace().collect();
         if parts.len() != 2 {
             bail!("Usage: .set <key> <value>. If value is null, unset key.");
@@ -557,12 +1709,21 @@ impl Config {
             }
             "save" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
+                if value && self.no_save {
+                    bail!("Cannot turn on saving, the run was started with --no-save");
+                }
                 self.save = value;
             }
             "highlight" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 self.highlight = value;
             }
+            "renderer" => {
+                self.renderer = RendererKind::parse(value)?;
+            }
+            "keep_partial_reply" => {
+                self.keep_partial_reply = KeepPartialReply::parse(value)?;
+            }
             "dry_run" => {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 self.dry_run = value;
@@ -571,6 +1732,110 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 self.auto_copy = value;
             }
+            "copy_history_size" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.copy_history_size = value;
+                self.copy_history.truncate(value.max(1));
+            }
+            "stream" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.stream = value;
+            }
+            "stream_stats" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.stream_stats = value;
+            }
+            "fold_output" => {
+                self.fold_output = parse_option_value(value)?;
+            }
+            "reasoning_effort" => {
+                if !unset && !REASONING_EFFORTS.contains(&value) {
+                    bail!("Invalid reasoning_effort '{value}', expect low, medium or high");
+                }
+                self.reasoning_effort = if unset { None } else { Some(value.to_string()) };
+            }
+            "thinking_budget" => {
+                let value: Option<usize> = parse_option_value(value)?;
+                if let Some(value) = value.filter(|v| *v < MIN_THINKING_BUDGET) {
+                    bail!("Invalid thinking_budget {value}, expect at least {MIN_THINKING_BUDGET}");
+                }
+                self.thinking_budget = value;
+            }
+            "fallback_model" => {
+                self.fallback_model = parse_model_option(self, value)?;
+            }
+            "judge_model" => {
+                self.judge_model = parse_model_option(self, value)?;
+            }
+            "followups_model" => {
+                self.followups_model = parse_model_option(self, value)?;
+            }
+            "session_token_budget" => {
+                self.session_token_budget = parse_option_value(value)?;
+            }
+            "daily_token_budget" => {
+                self.daily_token_budget = parse_option_value(value)?;
+            }
+            "daily_cost_budget" => {
+                self.daily_cost_budget = parse_option_value(value)?;
+            }
+            "scan_untrusted" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.scan_untrusted = value;
+            }
+            "suggest_role" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.suggest_role = value;
+            }
+            "summarize_on_exit" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.summarize_on_exit = value;
+            }
+            "confirm_duplicate" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.confirm_duplicate = value;
+            }
+            "auto_migrate_models" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.auto_migrate_models = value;
+            }
+            "check_updates" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.check_updates = value;
+            }
+            "offline" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.offline = value;
+            }
+            "lite" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.lite = value;
+            }
+            "env_context" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.env_context = value;
+            }
+            "split_questions" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.split_questions = value;
+            }
+            "auto_session_per_dir" => {
+                let value = value.parse().with_context(|| "Invalid value")?;
+                self.auto_session_per_dir = value;
+            }
+            "last_reply_file" => {
+                self.last_reply_file = if unset { None } else { Some(value.to_string()) };
+            }
+            "paste_token_limit" => {
+                self.paste_token_limit = parse_option_value(value)?;
+            }
+            "candidates" => {
+                let value: usize = value.parse().with_context(|| "Invalid value")?;
+                if value == 0 {
+                    bail!("Invalid value")
+                }
+                self.candidates = value;
+            }
             _ => bail!("Unknown key `{key}`"),
         }
         Ok(())
@@ -605,6 +1870,7 @@ impl Config {
                     self.temperature = session.temperature();
                     self.session = Some(session);
                     self.set_model(&model)?;
+                    self.migrate_session_model(&model)?;
                 }
             }
         }
@@ -625,11 +1891,85 @@ impl Config {
         Ok(())
     }
 
+    /// Start or resume the session named after the git repository, or the current directory
+    /// outside of a repository
+    pub fn start_dir_session(&mut self) -> Result<()> {
+        let dir = match git_toplevel() {
+            Some(v) => v,
+            None => env::current_dir().with_context(|| "Failed to get the current directory")?,
+        };
+        self.start_session(Some(&dir_session_name(&dir)))
+    }
+
+    pub fn start_session_from_template(&mut self, name: &str) -> Result<()> {
+        let template = ConversationTemplate::load(name, &Self::template_file(name)?)?;
+        if let Some(role) = &template.role {
+            self.set_role(role)?;
+        }
+        let variables = template.prompt_variables()?;
+        let messages = template.build_messages(self.role.as_ref(), &variables)?;
+        self.start_session(None)?;
+        if let Some(session) = self.session.as_mut() {
+            session.seed_messages(messages);
+        }
+        Ok(())
+    }
+
+    /// Move the current session to the background and switch to another one, starting it if needed
+    pub fn switch_session(&mut self, name: &str) -> Result<()> {
+        if self.session.as_ref().map(|v| v.name() == name) == Some(true) {
+            return Ok(());
+        }
+        if let Some(session) = self.session.take() {
+            self.background_sessions.push(SessionState {
+                session,
+                role: self.role.take(),
+                model: self.model.clone(),
+                temperature: self.temperature,
+            });
+        }
+        self.last_message = None;
+        match self
+            .background_sessions
+            .iter()
+            .position(|v| v.name() == name)
+        {
+            Some(index) => {
+                let state = self.background_sessions.remove(index);
+                self.session = Some(state.session);
+                self.role = state.role;
+                self.model = state.model;
+                self.temperature = state.temperature;
+            }
+            None => {
+                self.role = None;
+                self.temperature = self.default_temperature;
+                self.start_session(Some(name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// End the current session and all the background ones
+    pub fn end_all_sessions(&mut self) -> Result<()> {
+        self.end_session()?;
+        while let Some(state) = self.background_sessions.pop() {
+            self.session = Some(state.session);
+            self.end_session()?;
+        }
+        Ok(())
+    }
+
     pub fn end_session(&mut self) -> Result<()> {
         if let Some(mut session) = self.session.take() {
             self.last_message = None;
             self.temperature = self.default_temperature;
-            if session.dirty {
+            if session.dirty && self.no_save {
+                println!(
+                    "Saving is off, the session '{}' is not saved.",
+                    session.name()
+                );
+            } else if session.dirty {
                 let ans = Confirm::new("Save session?").with_default(false).prompt()?;
                 if !ans {
                     return Ok(());
@@ -681,6 +2021,166 @@ impl Config {
         }
     }
 
+    /// The saved prompts of the prompt library, sorted by name
+    pub fn list_prompts(&self) -> Vec<SavedPrompt> {
+        let prompts_dir = match Self::prompts_dir() {
+            Ok(dir) => dir,
+            Err(_) => return vec![],
+        };
+        let mut prompts = vec![];
+        if let Ok(rd) = read_dir(prompts_dir) {
+            for entry in rd.flatten() {
+                let file_name = entry.file_name();
+                if let Some(name) = file_name.to_string_lossy().strip_suffix(".md") {
+                    if let Ok(prompt) = SavedPrompt::load(name, &entry.path()) {
+                        prompts.push(prompt);
+                    }
+                }
+            }
+        }
+        prompts.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        prompts
+    }
+
+    /// Copy a markdown file, or the markdown files of a directory, into the prompt library,
+    /// returning the names of the imported prompts
+    pub fn import_prompts(&self, path: &str) -> Result<Vec<String>> {
+        let path = Path::new(path);
+        let files = if path.is_dir() {
+            read_dir(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .flatten()
+                .map(|v| v.path())
+                .filter(|v| v.extension().map(|v| v == "md").unwrap_or_default())
+                .collect()
+        } else {
+            vec![path.to_path_buf()]
+        };
+        let prompts_dir = Self::prompts_dir()?;
+        create_dir_all(&prompts_dir)
+            .with_context(|| format!("Failed to create prompts_dir '{}'", prompts_dir.display()))?;
+        let mut names = vec![];
+        for file in files {
+            let name = match file.file_stem() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => bail!("Invalid prompt file {}", file.display()),
+            };
+            std::fs::copy(&file, Self::prompt_file(&name)?)
+                .with_context(|| format!("Failed to import prompt {}", file.display()))?;
+            names.push(name);
+        }
+        names.sort_unstable();
+        Ok(names)
+    }
+
+    /// Write every prompt of the library as `<name>.md` in the directory
+    pub fn export_prompts(&self, dir: &str) -> Result<usize> {
+        let dir = Path::new(dir);
+        create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let prompts = self.list_prompts();
+        for prompt in &prompts {
+            let path = dir.join(format!("{}.md", prompt.name));
+            std::fs::write(&path, format!("{}\n", prompt.content))
+                .with_context(|| format!("Failed to write to {}", path.display()))?;
+        }
+        Ok(prompts.len())
+    }
+
+    /// Archive or delete the temp and project directory sessions beyond the retention, returning
+    /// their names. The sessions named by the user are left alone.
+    pub fn prune_sessions(&self) -> Result<Vec<String>> {
+        if self.no_save {
+            bail!("Cannot prune the sessions with --no-save");
+        }
+        let retention = &self.session_retention;
+        if retention.max_count.is_none() && retention.max_age_days.is_none() {
+            bail!("No session_retention is configured");
+        }
+        let sessions_dir = Self::sessions_dir()?;
+        let sessions = self
+            .list_sessions()
+            .into_iter()
+            .filter(|name| is_auto_session(name))
+            .filter_map(|name| {
+                let modified = Self::session_file(&name)
+                    .and_then(|path| Ok(path.metadata()?.modified()?))
+                    .ok()?;
+                Some((name, modified))
+            })
+            .collect();
+        let names = retention.select(sessions, std::time::SystemTime::now());
+        let archive_dir = sessions_dir.join("archive");
+        let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S");
+        for name in &names {
+            let path = Self::session_file(name)?;
+            if retention.archive {
+                create_dir_all(&archive_dir)?;
+                std::fs::rename(&path, archive_dir.join(format!("{name}-{timestamp}.yaml")))
+                    .with_context(|| format!("Failed to archive session '{name}'"))?;
+            } else {
+                remove_file(&path).with_context(|| format!("Failed to delete session '{name}'"))?;
+            }
+        }
+        Ok(names)
+    }
+
+    /// Write the sessions, or the current one, as chat fine-tuning JSONL, returning the number of
+    /// conversations written
+    pub fn export_finetune(
+        &self,
+        path: &str,
+        names: &[String],
+        options: &FinetuneOptions,
+    ) -> Result<usize> {
+        let sessions = if names.is_empty() {
+            match &self.session {
+                Some(session) => vec![session.clone()],
+                None => bail!("No session to export, name the sessions or start one"),
+            }
+        } else {
+            names
+                .iter()
+                .map(|name| Session::load(name, &Self::session_file(name)?))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let mut lines = vec![];
+        for session in &sessions {
+            if let Some(record) = finetune_record(session.messages(), options) {
+                lines.push(record.to_string());
+            }
+        }
+        let mut content = lines.join("\n");
+        content.push('\n');
+        std::fs::write(path, content).with_context(|| format!("Failed to write to {path}"))?;
+        Ok(lines.len())
+    }
+
+    /// The summary stored in a saved session file, if any
+    pub fn read_session_summary(path: &Path) -> Option<String> {
+        let text = read_to_string(path).ok()?;
+        let value = serde_yaml::from_str::<serde_yaml::Value>(&text).ok()?;
+        value["summary"].as_str().map(|v| v.to_string())
+    }
+
+    /// The builtin role suggested for the question, when `suggest_role` is on and no role is active
+    pub fn suggest_role(&self, text: &str) -> Option<&'static str> {
+        if !self.suggest_role || self.role.is_some() || self.session.is_some() {
+            return None;
+        }
+        Role::suggest_builtin(text)
+    }
+
+    /// Whether the current session should get a summary before it is left
+    pub fn need_session_summary(&self) -> bool {
+        self.summarize_on_exit
+            && !self.no_save
+            && self
+                .session
+                .as_ref()
+                .map(|v| v.dirty && !v.is_temp() && !v.is_empty())
+                .unwrap_or_default()
+    }
+
     pub fn should_compress_session(&mut self) -> bool {
         if let Some(session) = self.session.as_mut() {
             if session.need_compress(self.compress_threshold) {
@@ -711,7 +2211,7 @@ impl Config {
     }
 
     pub fn get_render_options(&self) -> Result<RenderOptions> {
-        let theme = if self.highlight {
+        let theme = if self.highlight && !self.lite {
             let theme_mode = if self.light_theme { "light" } else { "dark" };
             let theme_filename = format!("{theme_mode}.tmTheme");
             let theme_path = Self::local_path(&theme_filename)?;
@@ -735,7 +2235,20 @@ impl Config {
         } else {
             None
         };
-        Ok(RenderOptions::new(theme, wrap, self.wrap_code))
+        Ok(RenderOptions::new(
+            theme,
+            wrap,
+            self.wrap_code,
+            self.render_kind(),
+        ))
+    }
+
+    /// The lite mode skips the markdown renderer, which redraws and highlights the reply
+    fn render_kind(&self) -> RendererKind {
+        match self.renderer {
+            RendererKind::Auto | RendererKind::Markdown if self.lite => RendererKind::Minimal,
+            kind => kind,
+        }
     }
 
     pub fn render_prompt_left(&self) -> String {
@@ -748,16 +2261,65 @@ impl Config {
         render_prompt(&self.right_prompt, &variables)
     }
 
-    pub fn prepare_send_data(&self, input: &Input, stream: bool) -> Result<SendData> {
-        let messages = self.build_messages(input)?;
-        self.model.max_input_tokens_limit(&messages)?;
+    pub fn prepare_send_data(
+        &self,
+        input: &Input,
+        model: &Model,
+        stream: bool,
+    ) -> Result<SendData> {
+        self.prepare_send_data_with_role(input, model, self.role.as_ref(), stream)
+    }
+
+    pub fn prepare_send_data_with_role(
+        &self,
+        input: &Input,
+        model: &Model,
+        role: Option<&Role>,
+        stream: bool,
+    ) -> Result<SendData> {
+        let messages = self.build_messages_with_role(input, role)?;
+        if let Err(err) = model.max_input_tokens_limit(&messages) {
+            let hint = if self.session.is_some() {
+                "Try '.compress session' or '.clear messages' to shrink the session, or shorten the input."
+            } else {
+                "Try shortening the input or switching to a model with a larger context window."
+            };
+            bail!("{err}. {hint}");
+        }
+        let reasoning_effort = self
+            .reasoning_effort
+            .clone()
+            .or_else(|| model.reasoning_effort.clone())
+            .filter(|_| model.supports_reasoning_effort());
+        let thinking_budget = self.thinking_budget.filter(|_| model.supports_thinking());
         Ok(SendData {
             messages,
             temperature: self.get_temperature(),
             stream,
+            n: None,
+            reasoning_effort,
+            thinking_budget,
         })
     }
 
+    pub fn fallback_model(&self, input: &Input) -> Result<Option<Model>> {
+        let fallback_model = match &self.fallback_model {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let messages = self.build_messages(input)?;
+        if self.model.max_input_tokens_limit(&messages).is_ok() {
+            return Ok(None);
+        }
+        let models = list_models(self);
+        let model = Model::find(&models, fallback_model)
+            .ok_or_else(|| anyhow!("Invalid fallback model '{fallback_model}'"))?;
+        if model.max_input_tokens_limit(&messages).is_err() {
+            return Ok(None);
+        }
+        Ok(Some(model))
+    }
+
     pub fn maybe_print_send_tokens(&self, input: &Input) {
         if self.dry_run {
             if let Ok(messages) = self.build_messages(input) {
@@ -795,6 +2357,9 @@ impl Config {
         if self.auto_copy {
             output.insert("auto_copy", "true".to_string());
         }
+        if self.shell_mode {
+            output.insert("shell", "true".to_string());
+        }
         if let Some(role) = &self.role {
             output.insert("role", role.name.clone());
         }
@@ -831,6 +2396,29 @@ impl Config {
         output
     }
 
+    fn record_usage(&self, input: &Input, output: &str) -> Result<()> {
+        let messages = self.build_messages(input)?;
+        let input_tokens = self.model.total_tokens(&messages);
+        let output_tokens = count_tokens(output);
+        let path = Self::usage_file()?;
+        ensure_parent_exists(&path)?;
+        let mut usage = Usage::load(&path)?;
+        usage.add(
+            input_tokens + output_tokens,
+            self.model.cost(input_tokens, output_tokens),
+        );
+        usage.save(&path)
+    }
+
+    /// The usage of the week and of the month against `usage_alerts`
+    pub fn check_usage_alerts(&self) -> Result<Vec<UsageAlert>> {
+        if self.usage_alerts.is_empty() {
+            return Ok(vec![]);
+        }
+        let usage = Usage::load(&Self::usage_file()?)?;
+        Ok(self.usage_alerts.check(&usage))
+    }
+
     fn open_message_file(&self) -> Result<File> {
         let path = Self::messages_file()?;
         ensure_parent_exists(&path)?;
@@ -861,14 +2449,38 @@ impl Config {
 
     fn load_roles(&mut self) -> Result<()> {
         let path = Self::roles_file()?;
+        if path.exists() {
+            let content = read_to_string(&path)
+                .with_context(|| format!("Failed to load roles at {}", path.display()))?;
+            let roles: Vec<Role> =
+                serde_yaml::from_str(&content).with_context(|| "Invalid roles config")?;
+            self.roles = roles;
+        }
+        self.roles.extend(role_pack::load_roles()?);
+        Ok(())
+    }
+
+    fn load_model_cache(&mut self) {
+        let path = match Self::model_cache_file() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        match ModelCache::load(&path) {
+            Ok(cache) => self.model_cache = cache,
+            Err(err) => debug!("{err:#}"),
+        }
+    }
+
+    fn load_snippets(&mut self) -> Result<()> {
+        let path = Self::snippets_file()?;
         if !path.exists() {
             return Ok(());
         }
         let content = read_to_string(&path)
-            .with_context(|| format!("Failed to load roles at {}", path.display()))?;
-        let roles: Vec<Role> =
-            serde_yaml::from_str(&content).with_context(|| "Invalid roles config")?;
-        self.roles = roles;
+            .with_context(|| format!("Failed to load snippets at {}", path.display()))?;
+        let snippets: Vec<Snippet> =
+            serde_yaml::from_str(&content).with_context(|| "Invalid snippets config")?;
+        self.snippets = snippets;
         Ok(())
     }
 
@@ -972,6 +2584,48 @@ impl Keybindings {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeepPartialReply {
+    Always,
+    #[default]
+    Ask,
+    Never,
+}
+
+impl KeepPartialReply {
+    pub fn stringify(&self) -> &str {
+        match self {
+            KeepPartialReply::Always => "always",
+            KeepPartialReply::Ask => "ask",
+            KeepPartialReply::Never => "never",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "always" => Ok(KeepPartialReply::Always),
+            "ask" => Ok(KeepPartialReply::Ask),
+            "never" => Ok(KeepPartialReply::Never),
+            _ => bail!("Invalid value '{value}', expect always, ask or never"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionState {
+    session: Session,
+    role: Option<Role>,
+    model: Model,
+    temperature: Option<f64>,
+}
+
+impl SessionState {
+    pub fn name(&self) -> &str {
+        self.session.name()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum State {
     Normal,
@@ -1073,6 +2727,80 @@ fn ensure_parent_exists(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Replace the value of a top-level key, keeping its trailing comment aligned, or append the key
+fn set_yaml_key(text: &str, key: &str, value: &str) -> String {
+    let prefix = format!("{key}:");
+    let mut found = false;
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            if found || !line.starts_with(&prefix) {
+                return line.to_string();
+            }
+            found = true;
+            let mut new_line = format!("{key}: {value}");
+            if let Some(index) = line.find(" #") {
+                let comment = line[index..].trim_start();
+                let column = index + line[index..].len() - comment.len();
+                let padding = column.saturating_sub(new_line.len()).max(1);
+                new_line.push_str(&" ".repeat(padding));
+                new_line.push_str(comment);
+            }
+            new_line
+        })
+        .collect();
+    if !found {
+        lines.push(format!("{key}: {value}"));
+    }
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+/// Add the text to the system prompt, before it if `prepend` and after it otherwise, adding a
+/// system message if there is none
+fn add_system_prompt(messages: &mut Vec<Message>, text: &str, prepend: bool) {
+    match messages.first_mut() {
+        Some(Message {
+            role: MessageRole::System,
+            content: MessageContent::Text(prompt),
+            ..
+        }) => {
+            *prompt = if prepend {
+                format!("{text}\n\n{prompt}")
+            } else {
+                format!("{prompt}\n\n{text}")
+            }
+        }
+        _ => messages.insert(0, Message::system(text)),
+    }
+}
+
+fn format_option_value<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| String::from("-"), |v| v.to_string())
+}
+
+fn parse_option_value<T: std::str::FromStr>(value: &str) -> Result<Option<T>> {
+    if value == "null" {
+        return Ok(None);
+    }
+    let value = value.parse().map_err(|_| anyhow!("Invalid value"))?;
+    Ok(Some(value))
+}
+
+/// The model id of an optional model key, checked against the models of the config
+fn parse_model_option(config: &Config, value: &str) -> Result<Option<String>> {
+    if value == "null" {
+        return Ok(None);
+    }
+    if Model::find(&list_models(config), value).is_none() {
+        bail!("Invalid model '{value}'")
+    }
+    Ok(Some(value.to_string()))
+}
+
 fn set_bool(target: &mut bool, value: &str) {
     match value {
         "1" | "true" => *target = true,
@@ -1102,3 +2830,22 @@ fn setup_logger() -> Result<()> {
 fn setup_logger() -> Result<()> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_yaml_key() {
+        let text =
+            "model: openai:gpt-3.5-turbo\ntemperature: 1.0          # The sampling temperature\n";
+        assert_eq!(
+            set_yaml_key(text, "temperature", "0.2"),
+            "model: openai:gpt-3.5-turbo\ntemperature: 0.2          # The sampling temperature\n"
+        );
+        assert_eq!(
+            set_yaml_key(text, "save", "false"),
+            format!("{text}save: false\n")
+        );
+    }
+}
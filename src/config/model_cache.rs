@@ -0,0 +1,128 @@
+use crate::client::{Model, ModelCapabilities};
+
+use anyhow::{Context, Result};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, read_to_string};
+use std::path::Path;
+
+lazy_static! {
+    /// The names of the models known to take images, whole words of the name or its start, so
+    /// e.g. `gpt-4-turbo-preview` and `claude-3-5-haiku` are left out
+    static ref VISION_MODEL_RE: Regex = Regex::new(concat!(
+        r"(^|[/:_-])(llava|bakllava|moondream|minicpm-v|pixtral|vision)([:_.-]|$)",
+        r"|(^|/)(gpt-4o|gpt-4-turbo(-2024-04-09)?$|claude-3-(opus|sonnet|haiku)",
+        r"|claude-3-[57]-sonnet|claude-(sonnet|opus)-4|gemini-(1\.5|2\.))",
+        r"|(^|[/-])qwen2?(\.5)?-vl",
+    ))
+    .unwrap();
+}
+
+/// What the models missing from the config support, keyed by model id
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ModelCache {
+    #[serde(default)]
+    models: BTreeMap<String, CachedCapabilities>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CachedCapabilities {
+    pub stream: bool,
+    pub vision: bool,
+}
+
+impl CachedCapabilities {
+    /// Guess from the name of the model, keeping what its client declares for other models
+    pub fn lookup(model: &Model) -> Self {
+        let name = model.name.to_lowercase();
+        let vision = model.capabilities.contains(ModelCapabilities::Vision)
+            || VISION_MODEL_RE.is_match(&name).unwrap_or_default();
+        Self {
+            stream: !model.no_stream,
+            vision,
+        }
+    }
+
+    pub fn apply(&self, mut model: Model) -> Model {
+        model.no_stream = !self.stream;
+        model
+            .capabilities
+            .set(ModelCapabilities::Vision, self.vision);
+        model
+    }
+}
+
+impl ModelCache {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to load model cache at {}", path.display()))?;
+        let cache: Self =
+            serde_yaml::from_str(&content).with_context(|| "Invalid model cache data")?;
+        Ok(cache)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content =
+            serde_yaml::to_string(&self).with_context(|| "Failed to serde model cache")?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write model cache to {}", path.display()))
+    }
+
+    pub fn get(&self, id: &str) -> Option<CachedCapabilities> {
+        self.models.get(id).cloned()
+    }
+
+    pub fn set(&mut self, id: &str, capabilities: CachedCapabilities) {
+        self.models.insert(id.to_string(), capabilities);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let model = Model::new("ollama", "llava:13b");
+        let capabilities = CachedCapabilities::lookup(&model);
+        assert_eq!(
+            capabilities,
+            CachedCapabilities {
+                stream: true,
+                vision: true
+            }
+        );
+        let model = capabilities.apply(model);
+        assert!(model.capabilities.contains(ModelCapabilities::Vision));
+        for name in [
+            "llama3.2-vision",
+            "gpt-4o-mini",
+            "claude-3-5-sonnet-latest",
+            "qwen2.5-vl:7b",
+        ] {
+            assert!(
+                CachedCapabilities::lookup(&Model::new("openai", name)).vision,
+                "{name}"
+            );
+        }
+        for name in [
+            "gpt-4-turbo-preview",
+            "claude-3-5-haiku-latest",
+            "television",
+        ] {
+            assert!(
+                !CachedCapabilities::lookup(&Model::new("openai", name)).vision,
+                "{name}"
+            );
+        }
+        let model = Model::new("ollama", "mistral").set_no_stream(true);
+        let model = CachedCapabilities::lookup(&model).apply(model);
+        assert!(!model.capabilities.contains(ModelCapabilities::Vision));
+        assert!(model.no_stream);
+    }
+}
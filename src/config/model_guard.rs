@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+/// When to ask for confirmation before sending a message to a model
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ModelGuard {
+    /// Confirm every message
+    pub confirm: bool,
+    /// Confirm the messages with more input tokens than this value
+    pub confirm_above_tokens: Option<usize>,
+}
+
+impl ModelGuard {
+    pub fn needs_confirm(&self, tokens: usize) -> bool {
+        self.confirm
+            || self
+                .confirm_above_tokens
+                .map(|limit| tokens > limit)
+                .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_confirm() {
+        let guard = ModelGuard {
+            confirm: false,
+            confirm_above_tokens: Some(1000),
+        };
+        assert!(!guard.needs_confirm(1000));
+        assert!(guard.needs_confirm(1001));
+        assert!(!ModelGuard::default().needs_confirm(100_000));
+        let guard = ModelGuard {
+            confirm: true,
+            ..Default::default()
+        };
+        assert!(guard.needs_confirm(1));
+    }
+}
@@ -0,0 +1,148 @@
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref LEADING_CHATTER_RE: Regex = Regex::new(
+        r"(?i)^(sure|certainly|of course|okay|ok|here(\s+is|'s|\s+are)|below\s+is|the\s+following)\b[^(){}\[\]=;]*$|^[a-z][^(){}\[\]=;:]*\s(code|commands?|script|output|result|text|version)\b[^(){}\[\]=;:]*:$"
+    )
+    .unwrap();
+    static ref TRAILING_CHATTER_RE: Regex = Regex::new(
+        r"(?i)^(this|these|the above|note|explanation|let me know|i hope|hope this|feel free|make sure)[\s:,]"
+    )
+    .unwrap();
+}
+
+/// Post-processing of the code printed by `--code`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutputNormalization {
+    /// End the output with exactly one newline
+    pub trailing_newline: bool,
+    /// Convert the line endings
+    pub line_endings: LineEndings,
+    /// Drop the explanatory first and last lines, e.g. "Here is the code:", off by default since
+    /// real lines such as "Note: ..." match too
+    pub strip_chatter: bool,
+}
+
+impl Default for OutputNormalization {
+    fn default() -> Self {
+        Self {
+            trailing_newline: true,
+            line_endings: LineEndings::Keep,
+            strip_chatter: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    Keep,
+    Lf,
+    Crlf,
+}
+
+impl OutputNormalization {
+    /// The newline between the lines of the output
+    pub fn newline(&self) -> &'static str {
+        match self.line_endings {
+            LineEndings::Crlf => "\r\n",
+            _ => "\n",
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = if self.strip_chatter {
+            strip_chatter(text)
+        } else {
+            text.to_string()
+        };
+        let newline = match self.line_endings {
+            LineEndings::Keep => None,
+            LineEndings::Lf => Some("\n"),
+            LineEndings::Crlf => Some("\r\n"),
+        };
+        if let Some(newline) = newline {
+            text = text.replace("\r\n", "\n");
+            if newline != "\n" {
+                text = text.replace('\n', newline);
+            }
+        }
+        if self.trailing_newline {
+            let newline = newline.unwrap_or(if text.contains("\r\n") { "\r\n" } else { "\n" });
+            text = format!("{}{newline}", text.trim_end_matches(['\r', '\n']));
+        }
+        text
+    }
+}
+
+/// Remove the first and last lines addressing the user rather than being part of the output,
+/// unless nothing else would remain
+fn strip_chatter(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let is_blank = |i: usize| lines[i].trim().is_empty();
+    let mut start = 0;
+    let mut end = lines.len();
+    while start < end && is_blank(start) {
+        start += 1;
+    }
+    while end > start && is_blank(end - 1) {
+        end -= 1;
+    }
+    if end - start < 2 {
+        return text.to_string();
+    }
+    if LEADING_CHATTER_RE
+        .is_match(lines[start].trim())
+        .unwrap_or_default()
+    {
+        start += 1;
+        while start < end && is_blank(start) {
+            start += 1;
+        }
+    }
+    if end - start >= 2
+        && TRAILING_CHATTER_RE
+            .is_match(lines[end - 1].trim())
+            .unwrap_or_default()
+    {
+        end -= 1;
+        while end > start && is_blank(end - 1) {
+            end -= 1;
+        }
+    }
+    let separator = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    lines[start..end].join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let normalization = OutputNormalization::default();
+        assert_eq!(
+            normalization.apply("Note: keep this line\nls -la\n\n"),
+            "Note: keep this line\nls -la\n"
+        );
+        let normalization = OutputNormalization {
+            strip_chatter: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalization
+                .apply("Here is the script:\n\nls -la\necho done\n\nThis lists the files.\n\n"),
+            "ls -la\necho done\n"
+        );
+        assert_eq!(normalization.apply("Sure!"), "Sure!\n");
+        let normalization = OutputNormalization {
+            trailing_newline: false,
+            line_endings: LineEndings::Crlf,
+            strip_chatter: false,
+        };
+        assert_eq!(normalization.apply("a\r\nb\nc\n"), "a\r\nb\r\nc\r\n");
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+/// How to choose the attachments to keep when they exceed the input budget of the model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackStrategy {
+    /// Keep the most recently modified files
+    Recency,
+    /// Keep the smallest files, to keep as many files as possible
+    Size,
+    /// Keep the files sharing the most words with the question
+    Relevance,
+}
+
+/// A text file attached to the input
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub text: String,
+    pub tokens: usize,
+    pub modified: SystemTime,
+}
+
+/// Rank the attachments and keep the best ones that fit the budget, in their original order.
+/// Returns the names of the kept and of the dropped attachments.
+pub fn pack(
+    attachments: &[Attachment],
+    budget: usize,
+    strategy: PackStrategy,
+    question: &str,
+) -> (Vec<String>, Vec<String>) {
+    let mut ranked: Vec<usize> = (0..attachments.len()).collect();
+    match strategy {
+        PackStrategy::Recency => {
+            ranked.sort_by(|&a, &b| attachments[b].modified.cmp(&attachments[a].modified))
+        }
+        PackStrategy::Size => ranked.sort_by_key(|&i| attachments[i].tokens),
+        PackStrategy::Relevance => {
+            let words = words_of(question);
+            let scores: Vec<usize> = attachments
+                .iter()
+                .map(|v| words_of(&v.text).intersection(&words).count())
+                .collect();
+            ranked.sort_by(|&a, &b| scores[b].cmp(&scores[a]));
+        }
+    }
+    let mut used = 0;
+    let mut keep = vec![false; attachments.len()];
+    for i in ranked {
+        if used + attachments[i].tokens <= budget {
+            used += attachments[i].tokens;
+            keep[i] = true;
+        }
+    }
+    let (kept, dropped): (Vec<_>, Vec<_>) =
+        attachments.iter().zip(keep).partition(|(_, keep)| *keep);
+    let names = |list: Vec<(&Attachment, bool)>| -> Vec<String> {
+        list.into_iter().map(|(v, _)| v.name.clone()).collect()
+    };
+    (names(kept), names(dropped))
+}
+
+fn words_of(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|v| v.chars().count() > 3)
+        .map(|v| v.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn attachment(name: &str, text: &str, tokens: usize, age: u64) -> Attachment {
+        Attachment {
+            name: name.into(),
+            text: text.into(),
+            tokens,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(1000 - age),
+        }
+    }
+
+    #[test]
+    fn test_pack() {
+        let attachments = vec![
+            attachment("a.rs", "fn parse_config() {}", 60, 10),
+            attachment("b.rs", "fn render() {}", 30, 0),
+            attachment("c.rs", "fn main() {}", 30, 20),
+        ];
+        let (kept, dropped) = pack(&attachments, 70, PackStrategy::Size, "");
+        assert_eq!(
+            (kept, dropped),
+            (vec!["b.rs".into(), "c.rs".into()], vec!["a.rs".into()])
+        );
+        let (kept, _) = pack(&attachments, 70, PackStrategy::Recency, "");
+        assert_eq!(kept, vec!["b.rs".to_string(), "c.rs".into()]);
+        let (kept, _) = pack(
+            &attachments,
+            70,
+            PackStrategy::Relevance,
+            "why does parse_config fail",
+        );
+        assert_eq!(kept, vec!["a.rs".to_string()]);
+    }
+}
@@ -0,0 +1,92 @@
+use super::{GlobalConfig, Input};
+
+use crate::client::{init_client, init_client_with_model, list_models, Model};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+const INPUT_PLACEHOLDER: &str = "__INPUT__";
+const QUESTION_PLACEHOLDER: &str = "__QUESTION__";
+
+/// A stage of a pipeline, its reply is the input of the next stage
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PipelineStage {
+    /// The model of the stage, defaults to the current model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// `__INPUT__` is replaced by the output of the previous stage, `__QUESTION__` by the question
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+}
+
+impl PipelineStage {
+    pub fn build_text(&self, question: &str, input: &str) -> String {
+        match &self.prompt {
+            Some(prompt) if prompt.contains(INPUT_PLACEHOLDER) => prompt
+                .replace(INPUT_PLACEHOLDER, input)
+                .replace(QUESTION_PLACEHOLDER, question),
+            Some(prompt) => format!(
+                "{}\n\n{input}",
+                prompt.replace(QUESTION_PLACEHOLDER, question)
+            ),
+            None => input.to_string(),
+        }
+    }
+}
+
+/// Run the stages in order, calling `on_stage` with the model and the output of each stage
+pub fn run_pipeline<F>(
+    config: &GlobalConfig,
+    stages: &[PipelineStage],
+    question: &str,
+    mut on_stage: F,
+) -> Result<String>
+where
+    F: FnMut(usize, &str, &str),
+{
+    if stages.is_empty() {
+        bail!("The pipeline has no stages");
+    }
+    let mut output = question.to_string();
+    for (i, stage) in stages.iter().enumerate() {
+        let input = Input::from_str(&stage.build_text(question, &output));
+        let client = match &stage.model {
+            Some(id) => {
+                let model = Model::find(&list_models(&config.read()), id)
+                    .ok_or_else(|| anyhow!("Invalid model '{id}' in stage {}", i + 1))?;
+                init_client_with_model(config, &model)?
+            }
+            None => init_client(config)?,
+        };
+        output = client
+            .send_message(input)
+            .with_context(|| format!("Failed at stage {}", i + 1))?;
+        on_stage(i + 1, &client.model().id(), &output);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_text() {
+        let stage = PipelineStage {
+            model: None,
+            prompt: Some("Improve the answer to '__QUESTION__':\n__INPUT__".into()),
+        };
+        assert_eq!(
+            stage.build_text("what is rust", "a language"),
+            "Improve the answer to 'what is rust':\na language"
+        );
+        let stage = PipelineStage {
+            model: None,
+            prompt: Some("Critique this draft.".into()),
+        };
+        assert_eq!(
+            stage.build_text("q", "draft"),
+            "Critique this draft.\n\ndraft"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::render::render_error;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::read_dir;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Version of the JSON-over-stdio protocol, sent with every request
+pub const PLUGIN_PROTOCOL_VERSION: u32 = 1;
+
+/// A plugin is killed if it does not describe itself in time, so a broken one cannot hang the REPL
+const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+const CALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// An executable in the plugins directory, exposed in the REPL as `.<name>`
+///
+/// aichat writes one JSON request to the stdin of the plugin and reads one JSON response from its stdout:
+/// - `{"version": 1, "type": "describe"}` asks for `{"description": "..."}`, sent when the plugin is loaded
+/// - `{"version": 1, "type": "command", "args": "...", ...}` asks for `{"output": "..."}` to print,
+///   `{"prompt": "..."}` to send to the model, or `{"error": "..."}`
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub name: String,
+    pub description: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PluginResponse {
+    pub description: Option<String>,
+    pub output: Option<String>,
+    pub prompt: Option<String>,
+    pub error: Option<String>,
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut plugin = Self {
+            name,
+            description: String::new(),
+            path: path.to_path_buf(),
+        };
+        let res = plugin.run(json!({ "type": "describe" }), DESCRIBE_TIMEOUT)?;
+        plugin.description = res.description.unwrap_or_default();
+        Ok(plugin)
+    }
+
+    pub fn call(&self, request: Value) -> Result<PluginResponse> {
+        self.run(request, CALL_TIMEOUT)
+    }
+
+    fn run(&self, mut request: Value, timeout: Duration) -> Result<PluginResponse> {
+        request["version"] = PLUGIN_PROTOCOL_VERSION.into();
+        let name = &self.name;
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run plugin '{name}'"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            writeln!(stdin, "{request}")?;
+        }
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("No output of plugin '{name}'"))?;
+        let reader = thread::spawn(move || {
+            let mut output = vec![];
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("Plugin '{name}' timed out after {}s", timeout.as_secs());
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let output = reader
+            .join()
+            .map_err(|_| anyhow!("Failed to read the output of plugin '{name}'"))??;
+        if !status.success() {
+            bail!("Plugin '{name}' exited with {status}");
+        }
+        parse_response(name, &output)
+    }
+}
+
+fn parse_response(name: &str, output: &[u8]) -> Result<PluginResponse> {
+    let res: PluginResponse = serde_json::from_slice(output)
+        .with_context(|| format!("Invalid response of plugin '{name}'"))?;
+    if let Some(error) = res.error {
+        bail!("Plugin '{name}' failed, {error}");
+    }
+    Ok(res)
+}
+
+/// Load the executables in the directory as plugins, skipping the ones that fail to describe themselves
+pub fn load_plugins(dir: &Path, highlight: bool) -> Vec<Plugin> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut plugins = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !is_executable(&path) {
+            continue;
+        }
+        match Plugin::load(&path) {
+            Ok(plugin) => plugins.push(plugin),
+            Err(err) => render_error(
+                err.context(format!("Skipped plugin at {}", path.display())),
+                highlight,
+            ),
+        }
+    }
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|v| v.is_file() && v.permissions().mode() & 0o111 != 0)
+        .unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let res = parse_response("echo", br#"{"output": "hi", "prompt": null}"#).unwrap();
+        assert_eq!(res.output.as_deref(), Some("hi"));
+        assert!(res.prompt.is_none());
+        let res = parse_response("echo", br#"{"description": "Echo the args"}"#).unwrap();
+        assert_eq!(res.description.as_deref(), Some("Echo the args"));
+        let err = parse_response("echo", br#"{"error": "no args"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "Plugin 'echo' failed, no args");
+        assert!(parse_response("echo", b"hi").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("slow");
+        std::fs::write(&path, "#!/bin/sh\nsleep 5\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let plugin = Plugin {
+            name: "slow".into(),
+            description: String::new(),
+            path,
+        };
+        let started = Instant::now();
+        let err = plugin
+            .run(json!({ "type": "describe" }), Duration::from_millis(200))
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// A reusable user message, saved as `prompts/<name>.md`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedPrompt {
+    pub name: String,
+    pub content: String,
+}
+
+impl SavedPrompt {
+    pub fn load(name: &str, path: &Path) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to load prompt {} at {}", name, path.display()))?;
+        Ok(Self {
+            name: name.to_string(),
+            content: content.trim_end().to_string(),
+        })
+    }
+
+    /// The first line of the content, to show next to the name
+    pub fn preview(&self) -> &str {
+        self.content.lines().next().unwrap_or_default()
+    }
+}
+
+/// The prompts matching the query, best first. A name matches when the query is a subsequence
+/// of it, the content matches when it contains the query.
+pub fn search<'a>(prompts: &'a [SavedPrompt], query: &str) -> Vec<&'a SavedPrompt> {
+    let query = query.to_lowercase();
+    let mut matches: Vec<(usize, &SavedPrompt)> = prompts
+        .iter()
+        .filter_map(|v| {
+            let score = fuzzy_score(&v.name.to_lowercase(), &query).or_else(|| {
+                v.content
+                    .to_lowercase()
+                    .contains(&query)
+                    .then_some(usize::MAX / 2)
+            })?;
+            Some((score, v))
+        })
+        .collect();
+    matches.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+    matches.into_iter().map(|(_, v)| v).collect()
+}
+
+/// The number of skipped characters when matching the query as a subsequence, lower is better
+fn fuzzy_score(text: &str, query: &str) -> Option<usize> {
+    let mut chars = text.chars();
+    let mut skipped = 0;
+    for q in query.chars() {
+        loop {
+            let c = chars.next()?;
+            if c == q {
+                break;
+            }
+            skipped += 1;
+        }
+    }
+    Some(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(name: &str, content: &str) -> SavedPrompt {
+        SavedPrompt {
+            name: name.into(),
+            content: content.into(),
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let prompts = vec![
+            prompt("code-review", "Review the following code"),
+            prompt("commit-message", "Write a commit message for the diff"),
+            prompt("summarize", "Summarize the text in three bullets"),
+        ];
+        let names = |query| -> Vec<&str> {
+            search(&prompts, query)
+                .into_iter()
+                .map(|v| v.name.as_str())
+                .collect()
+        };
+        assert_eq!(names("cr"), vec!["code-review"]);
+        assert_eq!(names("ce"), vec!["code-review", "commit-message"]);
+        assert_eq!(names("diff"), vec!["commit-message"]);
+        assert_eq!(names("").len(), 3);
+    }
+}
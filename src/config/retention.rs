@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::time::{Duration, SystemTime};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long saved sessions are kept by `--prune-sessions`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SessionRetention {
+    /// Keep at most this many sessions, the most recently modified ones
+    pub max_count: Option<usize>,
+    /// Prune sessions not modified for this many days
+    pub max_age_days: Option<u64>,
+    /// Move the pruned sessions to `sessions/archive` instead of deleting them
+    pub archive: bool,
+}
+
+impl Default for SessionRetention {
+    fn default() -> Self {
+        Self {
+            max_count: None,
+            max_age_days: None,
+            archive: true,
+        }
+    }
+}
+
+impl SessionRetention {
+    /// The names of the sessions to prune, given their names and modification times
+    pub fn select(&self, mut sessions: Vec<(String, SystemTime)>, now: SystemTime) -> Vec<String> {
+        sessions.sort_by_key(|v| Reverse(v.1));
+        sessions
+            .into_iter()
+            .enumerate()
+            .filter(|(i, (_, modified))| {
+                let too_many = self.max_count.map(|v| *i >= v).unwrap_or_default();
+                let too_old = self
+                    .max_age_days
+                    .and_then(|days| {
+                        now.duration_since(*modified)
+                            .ok()
+                            .map(|age| age > DAY * days as u32)
+                    })
+                    .unwrap_or_default();
+                too_many || too_old
+            })
+            .map(|(_, (name, _))| name)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let now = SystemTime::now();
+        let sessions = vec![
+            ("old".to_string(), now - DAY * 40),
+            ("new".into(), now),
+            ("recent".into(), now - DAY),
+        ];
+        let retention = SessionRetention {
+            max_count: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(retention.select(sessions.clone(), now), vec!["old"]);
+        let retention = SessionRetention {
+            max_age_days: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(retention.select(sessions, now), vec!["recent", "old"]);
+    }
+}
@@ -4,12 +4,26 @@ use crate::{
 };
 
 use anyhow::{Context, Result};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
-use super::Input;
+use super::{Input, OutputConstraints};
 
 const INPUT_PLACEHOLDER: &str = "__INPUT__";
 
+lazy_static! {
+    static ref PLACEHOLDER_RE: Regex = Regex::new(r"__[A-Za-z0-9]+__").unwrap();
+    static ref SHELL_QUESTION_RE: Regex = Regex::new(
+        r"(?i)^\s*(how (do|can|should) i|how to|what('s| is) the command|command to)\b.*\b(terminal|shell|command|bash|zsh|powershell|directory|folder|files?|process|port|permissions?|git|grep|chmod|ssh|tar|zip|disk|install)\b"
+    )
+    .unwrap();
+    static ref CODE_QUESTION_RE: Regex = Regex::new(
+        r"(?i)^\s*(write|generate|implement|create)\s+(me\s+)?(a|an|the)?\s*(\w+\s+)?(function|class|script|program|regex|snippet|method)\b"
+    )
+    .unwrap();
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Role {
     /// Role name
@@ -18,6 +32,9 @@ pub struct Role {
     pub prompt: String,
     /// What sampling temperature to use, between 0 and 2
     pub temperature: Option<f64>,
+    /// Constraints on the reply, added to the global `output_constraints`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraints: Option<OutputConstraints>,
 }
 
 impl Role {
@@ -25,6 +42,18 @@ impl Role {
     pub const DESCRIBE_COMMAND: &'static str = "__describe_command__";
     pub const CODE: &'static str = "__code__";
 
+    /// Guess the builtin role that suits the question, shell questions for `__execute__`,
+    /// code requests for `__code__`
+    pub fn suggest_builtin(text: &str) -> Option<&'static str> {
+        if SHELL_QUESTION_RE.is_match(text).unwrap_or_default() {
+            Some(Self::EXECUTE)
+        } else if CODE_QUESTION_RE.is_match(text).unwrap_or_default() {
+            Some(Self::CODE)
+        } else {
+            None
+        }
+    }
+
     pub fn for_execute() -> Self {
         let os = detect_os();
         let (shell, _, _) = detect_shell();
@@ -43,6 +72,7 @@ Provide only plain text without Markdown formatting.
 Do not provide markdown formatting such as ```"#
             ),
             temperature: None,
+            constraints: None,
         }
     }
 
@@ -55,6 +85,7 @@ Provide short responses in about 80 words.
 APPLY MARKDOWN formatting when possible."#
                 .into(),
             temperature: None,
+            constraints: None,
         }
     }
 
@@ -69,6 +100,7 @@ You are not allowed to ask for more details.
 For example if the prompt is "Hello world Python", you should return "print('Hello world')"."#
                 .into(),
             temperature: None,
+            constraints: None,
         }
     }
 
@@ -87,6 +119,53 @@ For example if the prompt is "Hello world Python", you should return "print('Hel
         self.prompt = complete_prompt_args(&self.prompt, &self.name);
     }
 
+    /// Find mistakes in the prompt: unknown placeholders, arguments not used or not declared
+    /// in the name, and unbalanced braces
+    pub fn lint(&self) -> Vec<String> {
+        let mut issues = vec![];
+        let num_args = self.name.split(':').count() - 1;
+        for placeholder in PLACEHOLDER_RE.find_iter(&self.prompt).flatten() {
+            let placeholder = placeholder.as_str();
+            if placeholder == INPUT_PLACEHOLDER {
+                continue;
+            }
+            let index = placeholder
+                .strip_prefix("__ARG")
+                .and_then(|v| v.strip_suffix("__"))
+                .and_then(|v| v.parse::<usize>().ok());
+            match index {
+                Some(index) if (1..=num_args).contains(&index) => {}
+                Some(_) => issues.push(format!(
+                    "'{placeholder}' has no matching ':<arg>' in the role name"
+                )),
+                None => issues.push(format!("unknown placeholder '{placeholder}'")),
+            }
+        }
+        for index in 1..=num_args {
+            if !self.prompt.contains(&format!("__ARG{index}__")) {
+                issues.push(format!(
+                    "argument {index} is not used, add '__ARG{index}__'"
+                ));
+            }
+        }
+        let mut depth = 0i32;
+        for c in self.prompt.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            if depth < 0 {
+                issues.push("unmatched '}'".into());
+                depth = 0;
+            }
+        }
+        if depth > 0 {
+            issues.push(format!("missing {depth} closing '}}'"));
+        }
+        issues
+    }
+
     pub fn match_name(&self, name: &str) -> bool {
         if self.name.contains(':') {
             let role_name_parts: Vec<&str> = self.name.split(':').collect();
@@ -114,16 +193,25 @@ For example if the prompt is "Hello world Python", you should return "print('Hel
             vec![Message {
                 role: MessageRole::User,
                 content,
+                pinned: false,
+                model: None,
+                truncated: false,
             }]
         } else {
             vec![
                 Message {
                     role: MessageRole::System,
                     content: MessageContent::Text(self.prompt.clone()),
+                    pinned: false,
+                    model: None,
+                    truncated: false,
                 },
                 Message {
                     role: MessageRole::User,
                     content,
+                    pinned: false,
+                    model: None,
+                    truncated: false,
                 },
             ]
         }
@@ -142,6 +230,37 @@ fn complete_prompt_args(prompt: &str, name: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_suggest_builtin() {
+        assert_eq!(
+            Role::suggest_builtin("how do I find large files in this folder"),
+            Some(Role::EXECUTE)
+        );
+        assert_eq!(
+            Role::suggest_builtin("write a python function to reverse a list"),
+            Some(Role::CODE)
+        );
+        assert_eq!(Role::suggest_builtin("why is the sky blue"), None);
+    }
+
+    #[test]
+    fn test_lint() {
+        let role = Role {
+            name: "convert:json".into(),
+            prompt: "convert __INPUT__ to __ARG2__ with {format".into(),
+            temperature: None,
+            constraints: None,
+        };
+        assert_eq!(
+            role.lint(),
+            vec![
+                "'__ARG2__' has no matching ':<arg>' in the role name",
+                "argument 1 is not used, add '__ARG1__'",
+                "missing 1 closing '}'",
+            ]
+        );
+    }
+
     #[test]
     fn test_merge_prompt_name() {
         assert_eq!(
@@ -0,0 +1,421 @@
+use super::{ensure_parent_exists, Config, Role};
+
+use crate::utils::fetch_bytes;
+
+use anyhow::{anyhow, bail, Context, Result};
+use fancy_regex::Regex;
+use flate2::read::GzDecoder;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, read_to_string};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::EntryType;
+use tempfile::NamedTempFile;
+
+/// The manifest of a pack, optional at the root of the tarball
+const MANIFEST_FILE_NAME: &str = "pack.yaml";
+const INDEX_FILE_NAME: &str = "index.yaml";
+
+lazy_static! {
+    static ref PACK_NAME_RE: Regex = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9_-]*$").unwrap();
+}
+
+/// An installed role pack, its roles are in `role-packs/<name>.yaml`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstalledPack {
+    pub source: String,
+    pub version: String,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct PackIndex {
+    #[serde(default)]
+    packs: BTreeMap<String, InstalledPack>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PackManifest {
+    name: Option<String>,
+    version: Option<String>,
+}
+
+impl PackIndex {
+    fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(INDEX_FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(&path)
+            .with_context(|| format!("Failed to load role packs at {}", path.display()))?;
+        serde_yaml::from_str(&content).with_context(|| "Invalid role packs index")
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(&self).with_context(|| "Failed to serde role packs")?;
+        write_file(&dir.join(INDEX_FILE_NAME), &content)
+    }
+
+    /// Refuse a pack whose name or roles are already taken by another pack
+    fn check_collisions(&self, name: &str, pack: &InstalledPack) -> Result<()> {
+        if let Some(installed) = self.packs.get(name) {
+            if installed.source != pack.source {
+                bail!(
+                    "The role pack '{name}' is already installed from {}, remove it first",
+                    installed.source
+                );
+            }
+        }
+        for (other, installed) in self.packs.iter().filter(|(v, _)| v.as_str() != name) {
+            if let Some(role) = pack.roles.iter().find(|v| installed.roles.contains(v)) {
+                bail!(
+                    "The role '{role}' of the role pack '{name}' is already in the role pack '{other}'"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Install a pack by its name in the registry, its URL or the path of a tarball. Returns the
+/// name of the pack and what was installed.
+pub fn install(source: &str, registry: Option<&str>) -> Result<(String, InstalledPack)> {
+    let (name, pack, roles) = fetch_pack(source, registry)?;
+    install_in(&Config::role_packs_dir()?, name, pack, &roles)
+}
+
+/// Fetch the packs again from their sources, returns the names with the old and new versions
+/// of the changed ones
+pub fn update(name: Option<&str>, registry: Option<&str>) -> Result<Vec<(String, String, String)>> {
+    let dir = Config::role_packs_dir()?;
+    let mut index = PackIndex::load(&dir)?;
+    let names: Vec<String> = match name {
+        Some(name) => {
+            if !index.packs.contains_key(name) {
+                bail!("The role pack '{name}' is not installed");
+            }
+            vec![name.to_string()]
+        }
+        None => index.packs.keys().cloned().collect(),
+    };
+    let mut updated = vec![];
+    for name in names {
+        let old = index.packs[&name].clone();
+        let (_, pack, roles) = fetch_pack(&old.source, registry)?;
+        if pack.version == old.version {
+            continue;
+        }
+        index.check_collisions(&name, &pack)?;
+        write_roles(&dir, &name, &roles)?;
+        updated.push((name.clone(), old.version, pack.version.clone()));
+        index.packs.insert(name, pack);
+    }
+    index.save(&dir)?;
+    Ok(updated)
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    remove_in(&Config::role_packs_dir()?, name)
+}
+
+pub fn list() -> Result<Vec<(String, InstalledPack)>> {
+    Ok(PackIndex::load(&Config::role_packs_dir()?)?
+        .packs
+        .into_iter()
+        .collect())
+}
+
+/// The roles of all installed packs, after the roles of `roles.yaml`. A broken pack is skipped
+/// with a warning, so it does not keep aichat from starting.
+pub fn load_roles() -> Result<Vec<Role>> {
+    let dir = Config::role_packs_dir()?;
+    let index = match PackIndex::load(&dir) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("Skipped the role packs, {err:#}");
+            return Ok(vec![]);
+        }
+    };
+    let mut roles = vec![];
+    for name in index.packs.keys() {
+        match load_pack_roles(&dir, name) {
+            Ok(pack_roles) => roles.extend(pack_roles),
+            Err(err) => eprintln!("Skipped the role pack '{name}', {err:#}"),
+        }
+    }
+    Ok(roles)
+}
+
+fn install_in(
+    dir: &Path,
+    name: String,
+    pack: InstalledPack,
+    roles: &[Role],
+) -> Result<(String, InstalledPack)> {
+    let mut index = PackIndex::load(dir)?;
+    index.check_collisions(&name, &pack)?;
+    write_roles(dir, &name, roles)?;
+    index.packs.insert(name.clone(), pack.clone());
+    index.save(dir)?;
+    Ok((name, pack))
+}
+
+fn remove_in(dir: &Path, name: &str) -> Result<()> {
+    let mut index = PackIndex::load(dir)?;
+    if index.packs.remove(name).is_none() {
+        bail!("The role pack '{name}' is not installed");
+    }
+    let path = pack_file(dir, name);
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    index.save(dir)
+}
+
+fn load_pack_roles(dir: &Path, name: &str) -> Result<Vec<Role>> {
+    let path = pack_file(dir, name);
+    let content = read_to_string(&path)
+        .with_context(|| format!("Failed to load role pack at {}", path.display()))?;
+    serde_yaml::from_str(&content).with_context(|| format!("Invalid role pack '{name}'"))
+}
+
+fn fetch_pack(source: &str, registry: Option<&str>) -> Result<(String, InstalledPack, Vec<Role>)> {
+    let (archive, default_name) = if source.starts_with("http://") || source.starts_with("https://")
+    {
+        (fetch_bytes(source)?, archive_stem(source))
+    } else if Path::new(source).exists() {
+        let archive =
+            fs::read(source).with_context(|| format!("Failed to load role pack at {source}"))?;
+        (archive, archive_stem(source))
+    } else {
+        if !is_valid_name(source) {
+            bail!("Invalid role pack '{source}', expect a name, a URL or a tarball");
+        }
+        let registry = registry.ok_or_else(|| {
+            anyhow!("No role_pack_registry is configured, install '{source}' by its URL or path")
+        })?;
+        (
+            fetch_bytes(&registry.replace("{name}", source))?,
+            source.to_string(),
+        )
+    };
+    let version = format!("{:x}", Sha256::digest(&archive))[..12].to_string();
+    let (manifest, roles) = extract(&archive)?;
+
+    let name = manifest.name.unwrap_or(default_name);
+    if !is_valid_name(&name) || name == "index" {
+        bail!("Invalid role pack name '{name}'");
+    }
+    if roles.is_empty() {
+        bail!("No roles in the role pack '{name}'");
+    }
+    let pack = InstalledPack {
+        source: source.to_string(),
+        version: manifest.version.unwrap_or(version),
+        roles: roles.iter().map(|v| v.name.clone()).collect(),
+    };
+    Ok((name, pack, roles))
+}
+
+/// Read the manifest and role files of the gzipped tarball in memory, nothing of it is written
+/// to disk. Absolute paths, `..` and links are refused.
+fn extract(archive: &[u8]) -> Result<(PackManifest, Vec<Role>)> {
+    let mut tarball = tar::Archive::new(GzDecoder::new(archive));
+    let mut files = BTreeMap::new();
+    for entry in tarball
+        .entries()
+        .with_context(|| "Failed to extract the role pack")?
+    {
+        let mut entry = entry.with_context(|| "Failed to extract the role pack")?;
+        let path = entry.path()?.into_owned();
+        if path
+            .components()
+            .any(|v| !matches!(v, Component::Normal(_) | Component::CurDir))
+        {
+            bail!("Unsafe path {} in the role pack", path.display());
+        }
+        match entry.header().entry_type() {
+            EntryType::Regular => {}
+            EntryType::Directory => continue,
+            _ => bail!("Unsupported entry {} in the role pack", path.display()),
+        }
+        if !matches!(
+            path.extension().and_then(|v| v.to_str()),
+            Some("yaml" | "yml")
+        ) {
+            continue;
+        }
+        let mut content = String::new();
+        entry
+            .read_to_string(&mut content)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        files.insert(path, content);
+    }
+    let mut manifest = PackManifest::default();
+    let mut roles = vec![];
+    for (path, content) in files {
+        if path.file_name().and_then(|v| v.to_str()) == Some(MANIFEST_FILE_NAME) {
+            manifest = serde_yaml::from_str(&content)
+                .with_context(|| format!("Invalid manifest {MANIFEST_FILE_NAME}"))?;
+            continue;
+        }
+        let file_roles: Vec<Role> = serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid roles in {}", path.display()))?;
+        roles.extend(file_roles);
+    }
+    Ok((manifest, roles))
+}
+
+fn write_roles(dir: &Path, name: &str, roles: &[Role]) -> Result<()> {
+    let content = serde_yaml::to_string(roles).with_context(|| "Failed to serde roles")?;
+    write_file(&pack_file(dir, name), &content)
+}
+
+/// Write through a temporary file renamed over the target, replacing a link instead of
+/// following it
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    ensure_parent_exists(path)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(content.as_bytes())?;
+    file.persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// The name of a pack from its URL or path, e.g. `devops` of `.../devops.tar.gz`
+fn archive_stem(source: &str) -> String {
+    let file_name = source.rsplit(['/', '\\']).next().unwrap_or(source);
+    let stem = file_name.split('.').next().unwrap_or(file_name);
+    stem.to_string()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    PACK_NAME_RE.is_match(name).unwrap_or_default()
+}
+
+fn pack_file(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.yaml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+
+    #[test]
+    fn test_archive_stem() {
+        assert_eq!(
+            archive_stem("https://example.com/packs/devops.tar.gz"),
+            "devops"
+        );
+        assert_eq!(archive_stem("./writing.tgz"), "writing");
+        assert!(is_valid_name("devops"));
+        assert!(!is_valid_name("../etc"));
+    }
+
+    fn tarball(entries: &[(&str, EntryType, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        for (path, entry_type, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            // Written raw, `set_path` refuses the unsafe paths under test
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            if *entry_type == EntryType::Symlink {
+                header.set_link_name("/etc/passwd").unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_extract() {
+        let archive = tarball(&[
+            (
+                "pack/pack.yaml",
+                EntryType::Regular,
+                "name: devops\nversion: 1.0.0",
+            ),
+            (
+                "pack/roles.yaml",
+                EntryType::Regular,
+                "- name: k8s\n  prompt: You are k8s",
+            ),
+            ("pack/README.md", EntryType::Regular, "# devops"),
+        ]);
+        let (manifest, roles) = extract(&archive).unwrap();
+        assert_eq!(manifest.name.as_deref(), Some("devops"));
+        assert_eq!(manifest.version.as_deref(), Some("1.0.0"));
+        assert_eq!(roles.len(), 1);
+        assert_eq!(roles[0].name, "k8s");
+        for entry in [
+            ("../roles.yaml", EntryType::Regular),
+            ("/tmp/roles.yaml", EntryType::Regular),
+            ("roles.yaml", EntryType::Symlink),
+            ("roles.yaml", EntryType::Link),
+        ] {
+            let archive = tarball(&[(entry.0, entry.1, "")]);
+            assert!(extract(&archive).is_err(), "{entry:?}");
+        }
+    }
+
+    #[test]
+    fn test_install_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let role = |name: &str| Role {
+            name: name.into(),
+            prompt: String::new(),
+            temperature: None,
+            constraints: None,
+        };
+        let pack = |source: &str, roles: &[&str]| InstalledPack {
+            source: source.into(),
+            version: "1".into(),
+            roles: roles.iter().map(|v| v.to_string()).collect(),
+        };
+        install_in(
+            dir,
+            "devops".into(),
+            pack("a.tar.gz", &["k8s"]),
+            &[role("k8s")],
+        )
+        .unwrap();
+        assert!(install_in(
+            dir,
+            "devops".into(),
+            pack("b.tar.gz", &["ci"]),
+            &[role("ci")]
+        )
+        .is_err());
+        assert!(install_in(
+            dir,
+            "infra".into(),
+            pack("c.tar.gz", &["k8s"]),
+            &[role("k8s")]
+        )
+        .is_err());
+        install_in(
+            dir,
+            "devops".into(),
+            pack("a.tar.gz", &["k8s", "ci"]),
+            &[role("k8s"), role("ci")],
+        )
+        .unwrap();
+        let index = PackIndex::load(dir).unwrap();
+        assert_eq!(index.packs["devops"].roles, ["k8s", "ci"]);
+        assert_eq!(load_pack_roles(dir, "devops").unwrap().len(), 2);
+
+        remove_in(dir, "devops").unwrap();
+        assert!(PackIndex::load(dir).unwrap().packs.is_empty());
+        assert!(!pack_file(dir, "devops").exists());
+        assert!(remove_in(dir, "devops").is_err());
+    }
+}
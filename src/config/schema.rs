@@ -0,0 +1,228 @@
+use anyhow::{Context, Result};
+use fancy_regex::Regex;
+use serde_json::Value;
+use std::fs::read_to_string;
+use std::path::Path;
+
+pub fn load_schema(path: &Path) -> Result<Value> {
+    let content = read_to_string(path)
+        .with_context(|| format!("Failed to load schema at {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Invalid schema at {}", path.display()))
+}
+
+/// The JSON text of the reply, without the code fence the model may have wrapped it in
+pub fn extract_json(output: &str) -> &str {
+    let output = output.trim();
+    match output.strip_prefix("```") {
+        Some(rest) => {
+            let rest = rest.split_once('\n').map(|(_, v)| v).unwrap_or_default();
+            rest.trim_end().trim_end_matches("```").trim()
+        }
+        None => output,
+    }
+}
+
+/// Validate the value against a JSON schema, returning the errors as `<path>: <problem>`.
+/// Supports the common keywords: type, enum, const, properties, required,
+/// additionalProperties, items, min/maxItems, min/maxLength, pattern, minimum, maximum, allOf,
+/// anyOf and oneOf.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = vec![];
+    validate_at(schema, value, "$", &mut errors);
+    errors
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            errors.push(format!("{path}: no value is allowed"));
+            return;
+        }
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(v) => vec![v.as_str()],
+            Value::Array(list) => list.iter().filter_map(|v| v.as_str()).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|v| is_type(value, v)) {
+            errors.push(format!(
+                "{path}: expected {}, got {}",
+                types.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            errors.push(format!(
+                "{path}: must be one of {}",
+                Value::from(options.clone())
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{path}: must be {expected}"));
+        }
+    }
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(|v| v.as_str()) {
+                    if !object.contains_key(name) {
+                        errors.push(format!("{path}: missing property '{name}'"));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(|v| v.as_object());
+            for (name, item) in object {
+                let item_path = format!("{path}.{name}");
+                match properties.and_then(|v| v.get(name)) {
+                    Some(item_schema) => validate_at(item_schema, item, &item_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{path}: unexpected property '{name}'"))
+                        }
+                        Some(item_schema) => validate_at(item_schema, item, &item_path, errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(list) => {
+            if let Some(min) = schema.get("minItems").and_then(|v| v.as_u64()) {
+                if (list.len() as u64) < min {
+                    errors.push(format!("{path}: expected at least {min} items"));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(|v| v.as_u64()) {
+                if list.len() as u64 > max {
+                    errors.push(format!("{path}: expected at most {max} items"));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in list.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{path}[{i}]"), errors);
+                }
+            }
+        }
+        Value::String(text) => {
+            let len = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(|v| v.as_u64()) {
+                if len < min {
+                    errors.push(format!("{path}: expected at least {min} characters"));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(|v| v.as_u64()) {
+                if len > max {
+                    errors.push(format!("{path}: expected at most {max} characters"));
+                }
+            }
+            if let Some(pattern) = schema.get("pattern").and_then(|v| v.as_str()) {
+                if let Ok(re) = Regex::new(pattern) {
+                    if !re.is_match(text).unwrap_or(true) {
+                        errors.push(format!("{path}: must match the pattern '{pattern}'"));
+                    }
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(|v| v.as_f64()) {
+                if number < min {
+                    errors.push(format!("{path}: must be at least {min}"));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(|v| v.as_f64()) {
+                if number > max {
+                    errors.push(format!("{path}: must be at most {max}"));
+                }
+            }
+        }
+        _ => {}
+    }
+    if let Some(Value::Array(list)) = schema.get("allOf") {
+        for item_schema in list {
+            validate_at(item_schema, value, path, errors);
+        }
+    }
+    if let Some(Value::Array(list)) = schema.get("anyOf") {
+        if !list.iter().any(|v| validate(v, value).is_empty()) {
+            errors.push(format!("{path}: does not match any of the anyOf schemas"));
+        }
+    }
+    if let Some(Value::Array(list)) = schema.get("oneOf") {
+        let count = list
+            .iter()
+            .filter(|v| validate(v, value).is_empty())
+            .count();
+        if count != 1 {
+            errors.push(format!(
+                "{path}: must match exactly one of the oneOf schemas, matches {count}"
+            ));
+        }
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => value.is_number(),
+        _ => type_name(value) == name,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name", "tags"],
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "age": { "type": "integer", "minimum": 0 },
+                "tags": { "type": "array", "items": { "enum": ["a", "b"] } }
+            },
+            "additionalProperties": false
+        });
+        assert!(validate(&schema, &json!({ "name": "x", "tags": ["a"] })).is_empty());
+        assert_eq!(
+            validate(
+                &schema,
+                &json!({ "name": "", "age": 1.5, "tags": ["c"], "extra": 1 })
+            ),
+            vec![
+                "$.name: expected at least 1 characters",
+                "$.age: expected integer, got number",
+                r#"$.tags[0]: must be one of ["a","b"]"#,
+                "$: unexpected property 'extra'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_json() {
+        assert_eq!(extract_json("```json\n{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(extract_json(" [1, 2] "), "[1, 2]");
+    }
+}
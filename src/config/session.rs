@@ -3,9 +3,10 @@ use super::role::Role;
 use super::{Input, Model};
 
 use crate::client::{Message, MessageContent, MessageRole};
-use crate::render::MarkdownRender;
+use crate::render::Renderer;
+use crate::utils::sha256sum;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
@@ -14,6 +15,49 @@ use std::path::Path;
 
 pub const TEMP_SESSION_NAME: &str = "temp";
 
+/// Appended to a truncated reply when it is sent back, so the model knows it is incomplete
+const TRUNCATED_MARKER: &str = "\n\n[The reply was interrupted by the user]";
+
+/// Whether the session was created by aichat, the temp session or the one of a project
+/// directory, rather than named by the user
+pub fn is_auto_session(name: &str) -> bool {
+    if name == TEMP_SESSION_NAME {
+        return true;
+    }
+    match name.strip_prefix("dir-") {
+        Some(rest) => {
+            let hash = rest.rsplit('-').next().unwrap_or_default();
+            hash.len() == 8 && hash.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+/// The session of a project directory, e.g. `dir-aichat-1a2b3c4d` for `/home/user/aichat`. The
+/// hash of the full path keeps apart the directories with the same name.
+pub fn dir_session_name(dir: &Path) -> String {
+    let path = dir.to_string_lossy();
+    let base: String = dir
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let hash = &sha256sum(&path)[..8];
+    if base.is_empty() {
+        format!("dir-{hash}")
+    } else {
+        format!("dir-{base}-{hash}")
+    }
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Session {
     #[serde(rename(serialize = "model", deserialize = "model"))]
@@ -25,6 +69,12 @@ pub struct Session {
     #[serde(default)]
     compressed_messages: Vec<Message>,
     compress_threshold: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instructions: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    context: Vec<String>,
     #[serde(skip)]
     pub name: String,
     #[serde(skip)]
@@ -48,6 +98,9 @@ impl Session {
             messages: vec![],
             compressed_messages: vec![],
             compress_threshold: None,
+            summary: None,
+            instructions: None,
+            context: vec![],
             data_urls: Default::default(),
             name: name.to_string(),
             path: None,
@@ -82,6 +135,11 @@ impl Session {
         self.temperature
     }
 
+    /// Instructions added to the system prompt of every request of the session
+    pub fn instructions(&self) -> Option<&str> {
+        self.instructions.as_deref()
+    }
+
     pub fn need_compress(&self, current_compress_threshold: usize) -> bool {
         let threshold = self
             .compress_threshold
@@ -107,6 +165,9 @@ impl Session {
         if let Some(temperature) = self.temperature() {
             data["temperature"] = temperature.into();
         }
+        if let Some(instructions) = self.instructions() {
+            data["instructions"] = instructions.into();
+        }
         data["total_tokens"] = tokens.into();
         if let Some(conext_window) = self.model.max_input_tokens {
             data["max_input_tokens"] = conext_window.into();
@@ -121,7 +182,7 @@ impl Session {
         Ok(output)
     }
 
-    pub fn info(&self, render: &mut MarkdownRender) -> Result<String> {
+    pub fn info(&self, render: &mut dyn Renderer) -> Result<String> {
         let mut items = vec![];
 
         if let Some(path) = &self.path {
@@ -142,6 +203,14 @@ impl Session {
             items.push(("max_input_tokens", max_input_tokens.to_string()));
         }
 
+        if let Some(summary) = &self.summary {
+            items.push(("summary", summary.to_string()));
+        }
+
+        if let Some(instructions) = &self.instructions {
+            items.push(("instructions", instructions.to_string()));
+        }
+
         let mut lines: Vec<String> = items
             .iter()
             .map(|(name, value)| format!("{name:<20}{value}"))
@@ -150,6 +219,7 @@ impl Session {
         if !self.is_empty() {
             lines.push("".into());
             let resolve_url_fn = |url: &str| resolve_data_url(&self.data_urls, url.to_string());
+            let mixed_models = self.models_stats().len() > 1;
 
             for message in &self.messages {
                 match message.role {
@@ -160,6 +230,12 @@ impl Session {
                         if let MessageContent::Text(text) = &message.content {
                             lines.push(render.render(text));
                         }
+                        if let Some(model) = message.model.as_ref().filter(|_| mixed_models) {
+                            lines.push(format!("({model})"));
+                        }
+                        if message.truncated {
+                            lines.push("(truncated)".into());
+                        }
                         lines.push("".into());
                     }
                     MessageRole::User => {
@@ -177,6 +253,113 @@ impl Session {
         Ok(output)
     }
 
+    /// Pin or unpin the n-th question and its reply, returns whether it is pinned now
+    pub fn toggle_pin(&mut self, n: usize) -> Result<bool> {
+        let index = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.role.is_user())
+            .nth(n.wrapping_sub(1))
+            .map(|(i, _)| i)
+            .ok_or_else(|| anyhow!("No question {n} in the session"))?;
+        let pinned = !self.messages[index].pinned;
+        self.messages[index].pinned = pinned;
+        if let Some(reply) = self.messages.get_mut(index + 1) {
+            if reply.role.is_assistant() {
+                reply.pinned = pinned;
+            }
+        }
+        self.dirty = true;
+        Ok(pinned)
+    }
+
+    /// The questions of the session, numbered for `.pin`, with `*` marking the pinned ones
+    pub fn list_pins(&self) -> String {
+        self.messages
+            .iter()
+            .filter(|v| v.role.is_user())
+            .enumerate()
+            .map(|(i, v)| {
+                let mark = if v.pinned { "*" } else { " " };
+                let text = v.content.render_input(|url| url.to_string());
+                let text: String = text
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(60)
+                    .collect();
+                format!("{mark}{:>3}. {text}", i + 1)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn set_summary(&mut self, summary: String) {
+        self.summary = Some(summary);
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// The messages as plain text, one `<role>: <content>` per message
+    pub fn transcript(&self) -> String {
+        self.messages
+            .iter()
+            .filter(|v| !v.role.is_system())
+            .map(|v| {
+                let role = match (&v.role, &v.model) {
+                    (MessageRole::User, _) => "user".to_string(),
+                    (_, Some(model)) => format!("assistant ({model})"),
+                    _ => "assistant".to_string(),
+                };
+                let role = if v.truncated {
+                    format!("{role} (truncated)")
+                } else {
+                    role
+                };
+                format!("{role}: {}", v.content.render_input(|url| url.to_string()))
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// The number of replies and their tokens, by the model that produced them
+    pub fn models_stats(&self) -> Vec<(String, usize, usize)> {
+        let mut stats: Vec<(String, usize, usize)> = vec![];
+        for message in self.messages.iter().filter(|v| v.role.is_assistant()) {
+            let model = message.model.clone().unwrap_or_else(|| "unknown".into());
+            let tokens = self.model.messages_tokens(std::slice::from_ref(message));
+            match stats.iter_mut().find(|v| v.0 == model) {
+                Some(v) => {
+                    v.1 += 1;
+                    v.2 += tokens;
+                }
+                None => stats.push((model, 1, tokens)),
+            }
+        }
+        stats
+    }
+
+    pub fn stats(&self) -> String {
+        let (tokens, percent) = self.tokens_and_percent();
+        let mut lines = vec![
+            format!("{:<20}{}", "questions", self.user_messages_len()),
+            format!("{:<20}{tokens} ({percent}%)", "total_tokens"),
+        ];
+        let models = self.models_stats();
+        if !models.is_empty() {
+            lines.push("".into());
+            lines.push(format!("{:<40}{:>8}{:>10}", "model", "replies", "tokens"));
+            for (model, replies, tokens) in models {
+                lines.push(format!("{model:<40}{replies:>8}{tokens:>10}"));
+            }
+        }
+        lines.join("\n")
+    }
+
     pub fn tokens_and_percent(&self) -> (usize, f32) {
         let tokens = self.tokens();
         let max_input_tokens = self.model.max_input_tokens.unwrap_or_default();
@@ -204,6 +387,30 @@ impl Session {
         self.compress_threshold = Some(value);
     }
 
+    pub fn set_instructions(&mut self, value: Option<String>) {
+        self.instructions = value;
+        self.dirty = true;
+    }
+
+    /// The paths and globs of the files sent with every request of the session
+    pub fn context(&self) -> &[String] {
+        &self.context
+    }
+
+    pub fn add_context(&mut self, pattern: String) {
+        if !self.context.contains(&pattern) {
+            self.context.push(pattern);
+            self.dirty = true;
+        }
+    }
+
+    pub fn clear_context(&mut self) {
+        if !self.context.is_empty() {
+            self.context.clear();
+            self.dirty = true;
+        }
+    }
+
     pub fn set_model(&mut self, model: Model) -> Result<()> {
         self.model_id = model.id();
         self.model = model;
@@ -211,11 +418,16 @@ impl Session {
     }
 
     pub fn compress(&mut self, prompt: String) {
+        let pinned: Vec<Message> = self.messages.iter().filter(|v| v.pinned).cloned().collect();
         self.compressed_messages.append(&mut self.messages);
         self.messages.push(Message {
             role: MessageRole::System,
             content: MessageContent::Text(prompt),
+            pinned: false,
+            model: None,
+            truncated: false,
         });
+        self.messages.extend(pinned);
         self.role = None;
         self.dirty = true;
     }
@@ -263,6 +475,16 @@ impl Session {
         self.messages.is_empty()
     }
 
+    /// Whether the input is the same as the last user message
+    pub fn is_last_input(&self, input: &Input) -> bool {
+        self.messages
+            .iter()
+            .rev()
+            .find(|v| v.role.is_user())
+            .map(|v| v.content == input.to_message_content())
+            .unwrap_or_default()
+    }
+
     pub fn add_message(&mut self, input: &Input, output: &str) -> Result<()> {
         let mut need_add_msg = true;
         if self.messages.is_empty() {
@@ -275,18 +497,38 @@ impl Session {
             self.messages.push(Message {
                 role: MessageRole::User,
                 content: input.to_message_content(),
+                pinned: false,
+                model: None,
+                truncated: false,
             });
         }
         self.data_urls.extend(input.data_urls());
         self.messages.push(Message {
             role: MessageRole::Assistant,
             content: MessageContent::Text(output.to_string()),
+            pinned: false,
+            model: Some(self.model.id()),
+            truncated: false,
         });
         self.role = None;
         self.dirty = true;
         Ok(())
     }
 
+    /// Mark the last reply as cut short by Ctrl-C
+    pub fn mark_last_truncated(&mut self) {
+        if let Some(message) = self.messages.last_mut().filter(|v| v.role.is_assistant()) {
+            message.truncated = true;
+            self.dirty = true;
+        }
+    }
+
+    pub fn seed_messages(&mut self, messages: Vec<Message>) {
+        self.messages.extend(messages);
+        self.role = None;
+        self.dirty = true;
+    }
+
     pub fn clear_messages(&mut self) {
         self.messages.clear();
         self.compressed_messages.clear();
@@ -308,16 +550,56 @@ impl Session {
                 messages = role.build_messages(input);
                 need_add_msg = false;
             }
-        } else if len == 1 && self.compressed_messages.len() >= 2 {
-            messages
-                .extend(self.compressed_messages[self.compressed_messages.len() - 2..].to_vec());
+        } else if messages[1..].iter().all(|v| v.pinned) && self.compressed_messages.len() >= 2 {
+            messages.extend(
+                self.compressed_messages[self.compressed_messages.len() - 2..]
+                    .iter()
+                    .filter(|v| !v.pinned)
+                    .cloned(),
+            );
+        }
+        for message in messages.iter_mut() {
+            if message.truncated {
+                if let MessageContent::Text(text) = &mut message.content {
+                    text.push_str(TRUNCATED_MARKER);
+                }
+                message.truncated = false;
+            }
+            message.pinned = false;
+            message.model = None;
         }
         if need_add_msg {
             messages.push(Message {
                 role: MessageRole::User,
                 content: input.to_message_content(),
+                pinned: false,
+                model: None,
+                truncated: false,
             });
         }
         messages
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_auto_session() {
+        assert!(is_auto_session("temp"));
+        assert!(is_auto_session("dir-aichat-1a2b3c4d"));
+        assert!(is_auto_session("dir-1a2b3c4d"));
+        assert!(!is_auto_session("dir-notes"));
+        assert!(!is_auto_session("rust"));
+    }
+
+    #[test]
+    fn test_dir_session_name() {
+        let name = dir_session_name(Path::new("/home/user/my project"));
+        assert!(name.starts_with("dir-my_project-"));
+        assert_eq!(name.len(), "dir-my_project-".len() + 8);
+        assert_ne!(name, dir_session_name(Path::new("/tmp/my project")));
+        assert!(dir_session_name(Path::new("/")).starts_with("dir-"));
+    }
+}
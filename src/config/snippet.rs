@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Snippet {
+    /// Snippet name, expanded by typing `:<name>` in the REPL
+    pub name: String,
+    /// Text to insert
+    pub content: String,
+}
@@ -0,0 +1,80 @@
+use super::role::Role;
+
+use crate::client::{Message, MessageContent, MessageRole};
+use crate::utils::{prompt_input_string, render_prompt};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConversationTemplate {
+    /// Role to start the session with
+    pub role: Option<String>,
+    /// Variables to be asked for, referenced as `{name}` in the messages
+    #[serde(default)]
+    pub variables: Vec<String>,
+    /// Messages to seed the session with
+    #[serde(default)]
+    pub messages: Vec<TemplateMessage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateMessage {
+    pub role: MessageRole,
+    pub content: String,
+}
+
+impl ConversationTemplate {
+    pub fn load(name: &str, path: &Path) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to load template {} at {}", name, path.display()))?;
+        let template: Self =
+            serde_yaml::from_str(&content).with_context(|| format!("Invalid template {name}"))?;
+        Ok(template)
+    }
+
+    pub fn prompt_variables(&self) -> Result<HashMap<&str, String>> {
+        let mut variables = HashMap::new();
+        for name in &self.variables {
+            let value = prompt_input_string(&format!("{name}:"), true)?;
+            variables.insert(name.as_str(), value);
+        }
+        Ok(variables)
+    }
+
+    pub fn build_messages(
+        &self,
+        role: Option<&Role>,
+        variables: &HashMap<&str, String>,
+    ) -> Result<Vec<Message>> {
+        let mut messages = vec![];
+        if let Some(role) = role {
+            if role.embedded() {
+                bail!(
+                    "Role '{}' embeds the input and cannot be used in a template",
+                    role.name
+                );
+            }
+            messages.push(Message {
+                role: MessageRole::System,
+                content: MessageContent::Text(role.prompt.clone()),
+                pinned: false,
+                model: None,
+                truncated: false,
+            });
+        }
+        for message in &self.messages {
+            messages.push(Message {
+                role: message.role,
+                content: MessageContent::Text(render_prompt(&message.content, variables)),
+                pinned: false,
+                model: None,
+                truncated: false,
+            });
+        }
+        Ok(messages)
+    }
+}
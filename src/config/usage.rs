@@ -0,0 +1,197 @@
+use anyhow::{Context, Result};
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, read_to_string};
+use std::path::Path;
+
+/// Tokens consumed per day, keyed by local date (YYYY-MM-DD)
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Usage {
+    #[serde(default)]
+    days: BTreeMap<String, usize>,
+    /// Estimated spend in USD per day, for the models with an `input_price` and `output_price`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    costs: BTreeMap<String, f64>,
+}
+
+impl Usage {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to load usage at {}", path.display()))?;
+        let usage: Self = serde_yaml::from_str(&content).with_context(|| "Invalid usage data")?;
+        Ok(usage)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(&self).with_context(|| "Failed to serde usage")?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write usage to {}", path.display()))
+    }
+
+    pub fn today(&self) -> usize {
+        self.days.get(&today()).cloned().unwrap_or_default()
+    }
+
+    /// The estimated spend of today in USD
+    pub fn today_cost(&self) -> f64 {
+        self.costs.get(&today()).cloned().unwrap_or_default()
+    }
+
+    /// The tokens and the spend of today, e.g. `1200 tokens, $0.03`
+    pub fn today_summary(&self) -> String {
+        format!("{} tokens, ${:.2}", self.today(), self.today_cost())
+    }
+
+    pub fn add(&mut self, tokens: usize, cost: Option<f64>) {
+        *self.days.entry(today()).or_default() += tokens;
+        if let Some(cost) = cost {
+            *self.costs.entry(today()).or_default() += cost;
+        }
+    }
+
+    /// The tokens and the spend from the given date on
+    fn since(&self, start: &str) -> (usize, f64) {
+        let tokens = self.days.range(start.to_string()..).map(|(_, v)| v).sum();
+        let cost = self.costs.range(start.to_string()..).map(|(_, v)| v).sum();
+        (tokens, cost)
+    }
+}
+
+/// Thresholds on the usage of the current week (from Monday) and month, warned about when the
+/// REPL starts and checked by `--check-budget`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UsageAlerts {
+    pub weekly_tokens: Option<usize>,
+    pub monthly_tokens: Option<usize>,
+    /// Estimated from the `input_price` and `output_price` of the models, in USD
+    pub weekly_cost: Option<f64>,
+    pub monthly_cost: Option<f64>,
+}
+
+/// The usage of a period compared with its threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageAlert {
+    pub name: &'static str,
+    pub used: f64,
+    pub limit: f64,
+}
+
+impl UsageAlert {
+    pub fn exceeded(&self) -> bool {
+        self.used >= self.limit
+    }
+
+    /// The used and the limit values, formatted as dollars for the costs
+    pub fn values(&self) -> (String, String) {
+        if self.name.ends_with("cost") {
+            (format!("${:.2}", self.used), format!("${:.2}", self.limit))
+        } else {
+            (format!("{}", self.used), format!("{}", self.limit))
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let (used, limit) = self.values();
+        let status = if self.exceeded() { "exceeded" } else { "ok" };
+        format!("{:<16}{used:>12} / {limit:<12}{status}", self.name)
+    }
+}
+
+impl UsageAlerts {
+    pub fn is_empty(&self) -> bool {
+        self.weekly_tokens.is_none()
+            && self.monthly_tokens.is_none()
+            && self.weekly_cost.is_none()
+            && self.monthly_cost.is_none()
+    }
+
+    pub fn check(&self, usage: &Usage) -> Vec<UsageAlert> {
+        self.check_on(usage, Local::now().date_naive())
+    }
+
+    fn check_on(&self, usage: &Usage, today: NaiveDate) -> Vec<UsageAlert> {
+        let week_start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+        let month_start = today.with_day(1).unwrap_or(today);
+        let (weekly_tokens, weekly_cost) = usage.since(&week_start.format("%Y-%m-%d").to_string());
+        let (monthly_tokens, monthly_cost) =
+            usage.since(&month_start.format("%Y-%m-%d").to_string());
+        [
+            (
+                "weekly_tokens",
+                self.weekly_tokens.map(|v| v as f64),
+                weekly_tokens as f64,
+            ),
+            (
+                "monthly_tokens",
+                self.monthly_tokens.map(|v| v as f64),
+                monthly_tokens as f64,
+            ),
+            ("weekly_cost", self.weekly_cost, weekly_cost),
+            ("monthly_cost", self.monthly_cost, monthly_cost),
+        ]
+        .into_iter()
+        .filter_map(|(name, limit, used)| limit.map(|limit| UsageAlert { name, used, limit }))
+        .collect()
+    }
+}
+
+fn today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut usage = Usage::default();
+        usage.add(1000, Some(0.02));
+        usage.add(200, None);
+        assert_eq!(usage.today(), 1200);
+        assert_eq!(usage.today_summary(), "1200 tokens, $0.02");
+    }
+
+    #[test]
+    fn test_check_on() {
+        let mut usage = Usage::default();
+        for (day, tokens, cost) in [
+            ("2024-04-28", 500, 0.5),
+            ("2024-05-03", 1000, 1.0),
+            ("2024-05-06", 2000, 2.0),
+            ("2024-05-08", 3000, 3.0),
+        ] {
+            usage.days.insert(day.into(), tokens);
+            usage.costs.insert(day.into(), cost);
+        }
+        let alerts = UsageAlerts {
+            weekly_tokens: Some(4000),
+            monthly_tokens: None,
+            weekly_cost: None,
+            monthly_cost: Some(5.0),
+        };
+        let today = NaiveDate::from_ymd_opt(2024, 5, 8).unwrap();
+        let output = alerts.check_on(&usage, today);
+        assert_eq!(
+            output,
+            vec![
+                UsageAlert {
+                    name: "weekly_tokens",
+                    used: 5000.0,
+                    limit: 4000.0
+                },
+                UsageAlert {
+                    name: "monthly_cost",
+                    used: 6.0,
+                    limit: 5.0
+                },
+            ]
+        );
+        assert!(output.iter().all(|v| v.exceeded()));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::utils::count_tokens;
+
+use anyhow::{bail, Context, Result};
+use fancy_regex::Regex;
+use std::fs::{read_dir, read_to_string, symlink_metadata};
+use std::path::{Path, PathBuf};
+
+/// Stop walking directories after this many matching files
+const MAX_FILES: usize = 200;
+
+/// The files matching a path or a glob, e.g. `src/**/*.rs`. Hidden directories and links are
+/// skipped.
+pub fn expand_pattern(pattern: &str) -> Result<Vec<PathBuf>> {
+    if !pattern.contains(['*', '?']) {
+        let path = PathBuf::from(pattern);
+        if path.is_file() {
+            return Ok(vec![path]);
+        }
+        if path.is_dir() {
+            return expand_pattern(&format!("{}/**/*", pattern.trim_end_matches('/')));
+        }
+        bail!("No such file or directory '{pattern}'");
+    }
+    let root: PathBuf = pattern
+        .split('/')
+        .take_while(|v| !v.contains(['*', '?']))
+        .collect::<Vec<_>>()
+        .join("/")
+        .into();
+    let root = if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    };
+    let re = glob_to_regex(pattern.trim_start_matches("./"))?;
+    let mut files = vec![];
+    walk(&root, &re, &mut files);
+    files.sort();
+    Ok(files)
+}
+
+/// The contents of the files, as many as fit the token budget, and the paths of the skipped ones
+pub fn build_context(files: &[PathBuf], budget: usize) -> (String, Vec<String>) {
+    let mut parts = vec![];
+    let mut skipped = vec![];
+    let mut used = 0;
+    for path in files {
+        let content = match read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let part = format!("`{}`:\n```\n{}\n```", path.display(), content.trim_end());
+        let tokens = count_tokens(&part);
+        if used + tokens > budget {
+            skipped.push(path.display().to_string());
+            continue;
+        }
+        used += tokens;
+        parts.push(part);
+    }
+    (parts.join("\n\n"), skipped)
+}
+
+fn walk(dir: &Path, re: &Regex, files: &mut Vec<PathBuf>) {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if files.len() >= MAX_FILES {
+            return;
+        }
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        // Links are not followed, they may point out of the project or loop
+        let file_type = match symlink_metadata(&path) {
+            Ok(v) => v.file_type(),
+            Err(_) => continue,
+        };
+        if file_type.is_symlink() {
+            continue;
+        }
+        if file_type.is_dir() {
+            walk(&path, re, files);
+        } else {
+            let name = path.to_string_lossy();
+            if re
+                .is_match(name.trim_start_matches("./"))
+                .unwrap_or_default()
+            {
+                files.push(path);
+            }
+        }
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> Result<Regex> {
+    let mut output = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    output.push_str("(.*/)?");
+                } else {
+                    output.push_str(".*");
+                }
+            }
+            '*' => output.push_str("[^/]*"),
+            '?' => output.push_str("[^/]"),
+            c => output.push_str(&fancy_regex::escape(&c.to_string())),
+        }
+    }
+    output.push('$');
+    Regex::new(&output).with_context(|| format!("Invalid glob '{pattern}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_to_regex() {
+        let re = glob_to_regex("src/**/*.rs").unwrap();
+        assert!(re.is_match("src/main.rs").unwrap());
+        assert!(re.is_match("src/config/mod.rs").unwrap());
+        assert!(!re.is_match("src/config/mod.rsx").unwrap());
+        assert!(!re.is_match("tests/main.rs").unwrap());
+        let re = glob_to_regex("*.md").unwrap();
+        assert!(re.is_match("README.md").unwrap());
+        assert!(!re.is_match("docs/README.md").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_skips_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::os::unix::fs::symlink(root, root.join("src/loop")).unwrap();
+        std::os::unix::fs::symlink("/etc/hosts", root.join("src/hosts.rs")).unwrap();
+        let re = glob_to_regex(&format!("{}/**/*.rs", root.display())).unwrap();
+        let mut files = vec![];
+        walk(root, &re, &mut files);
+        assert_eq!(files, [root.join("src/main.rs")]);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::client::ReplyStream;
+use crate::config::{GlobalConfig, Input};
+use crate::utils::{create_abort_signal, AbortSignal};
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam::channel::{unbounded, Sender};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{stdin, stdout, BufRead, Write};
+use std::sync::Arc;
+use std::thread::spawn;
+
+/// A message of the parent process, `id` is echoed back in the messages answering it
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Prompt {
+        id: Option<Value>,
+        text: String,
+        #[serde(default)]
+        files: Vec<String>,
+    },
+    Abort,
+    StartSession {
+        id: Option<Value>,
+        name: Option<String>,
+    },
+    EndSession {
+        id: Option<Value>,
+    },
+    Set {
+        id: Option<Value>,
+        key: String,
+        value: String,
+    },
+    Quit,
+}
+
+/// Talk with a parent process over stdin and stdout, each message being a JSON object
+/// preceded by a line with its length in bytes.
+///
+/// A `prompt` is answered with `delta` messages as the reply streams, then `done` with the whole
+/// reply or `error`. `abort` stops the streaming reply. `start_session`, `end_session` and `set`
+/// are answered with `ok` or `error`.
+pub fn run(config: &GlobalConfig) -> Result<()> {
+    let (tx, rx) = unbounded();
+    let current_abort: Arc<Mutex<Option<AbortSignal>>> = Default::default();
+    let reader_abort = current_abort.clone();
+    spawn(move || read_requests(tx, reader_abort));
+    for request in rx {
+        let request = match request {
+            Ok(v) => v,
+            Err(err) => {
+                write_message(&json!({ "type": "error", "message": format!("{err:#}") }))?;
+                continue;
+            }
+        };
+        match request {
+            Request::Prompt { id, text, files } => {
+                let abort = create_abort_signal();
+                *current_abort.lock() = Some(abort.clone());
+                let ret = prompt(config, &id, &text, files, abort);
+                *current_abort.lock() = None;
+                match ret {
+                    Ok(output) => {
+                        write_message(&json!({ "type": "done", "id": id, "text": output }))?
+                    }
+                    Err(err) => write_error(&id, &err)?,
+                }
+            }
+            Request::StartSession { id, name } => {
+                let ret = config.write().start_session(name.as_deref());
+                write_result(&id, ret)?;
+            }
+            Request::EndSession { id } => {
+                let ret = config.write().end_session();
+                write_result(&id, ret)?;
+            }
+            Request::Set { id, key, value } => {
+                let ret = config.write().update(&format!("{key} {value}"));
+                write_result(&id, ret)?;
+            }
+            Request::Abort => {}
+            Request::Quit => break,
+        }
+    }
+    Ok(())
+}
+
+fn prompt(
+    config: &GlobalConfig,
+    id: &Option<Value>,
+    text: &str,
+    files: Vec<String>,
+    abort: AbortSignal,
+) -> Result<String> {
+    let files = config.read().pack_files(text, files)?;
+    let input = Input::new(text, files)?;
+    let mut stream = ReplyStream::start_with_abort(config, input.clone(), abort);
+    for text in stream.by_ref() {
+        write_message(&json!({ "type": "delta", "id": id, "text": text }))?;
+    }
+    let output = stream.finish()?;
+    config.write().save_message(input, &output)?;
+    Ok(output)
+}
+
+/// Parse the requests on a thread of their own, so `abort` arrives while a reply streams
+fn read_requests(tx: Sender<Result<Request>>, current_abort: Arc<Mutex<Option<AbortSignal>>>) {
+    let mut reader = stdin().lock();
+    loop {
+        let request = match read_message(&mut reader) {
+            Ok(Some(v)) => serde_json::from_slice::<Request>(&v).with_context(|| "Invalid request"),
+            Ok(None) => {
+                let _ = tx.send(Ok(Request::Quit));
+                break;
+            }
+            Err(err) => {
+                let _ = tx.send(Err(err));
+                let _ = tx.send(Ok(Request::Quit));
+                break;
+            }
+        };
+        if let Ok(Request::Abort) = request {
+            if let Some(abort) = current_abort.lock().as_ref() {
+                abort.set_ctrlc();
+            }
+            continue;
+        }
+        if tx.send(request).is_err() {
+            break;
+        }
+    }
+}
+
+/// Read a message, returns None at the end of the input
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 {
+        return Ok(None);
+    }
+    let len: usize = header
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid length header '{}'", header.trim()))?;
+    let mut body = vec![0; len];
+    reader
+        .read_exact(&mut body)
+        .with_context(|| "Truncated message")?;
+    Ok(Some(body))
+}
+
+fn write_message(value: &Value) -> Result<()> {
+    let body = value.to_string();
+    let mut stdout = stdout().lock();
+    write!(stdout, "{}\n{body}", body.len())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn write_result(id: &Option<Value>, ret: Result<()>) -> Result<()> {
+    match ret {
+        Ok(()) => write_message(&json!({ "type": "ok", "id": id })),
+        Err(err) => write_error(id, &err),
+    }
+}
+
+fn write_error(id: &Option<Value>, err: &anyhow::Error) -> Result<()> {
+    write_message(&json!({ "type": "error", "id": id, "message": format!("{err:#}") }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_message() {
+        let data = b"27\n{\"type\":\"prompt\",\"text\":\"\"}2\n{}";
+        let mut reader = &data[..];
+        let body = read_message(&mut reader).unwrap().unwrap();
+        assert!(matches!(
+            serde_json::from_slice::<Request>(&body).unwrap(),
+            Request::Prompt { .. }
+        ));
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), b"{}");
+        assert!(read_message(&mut reader).unwrap().is_none());
+        assert!(read_message(&mut &b"x\n"[..]).is_err());
+    }
+}
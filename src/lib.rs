@@ -0,0 +1,35 @@
+//! The multi-provider client layer of aichat, for Rust programs that embed it
+//! instead of shelling out to the binary.
+//!
+//! ```no_run
+//! use aichat::{Config, Input, ReplyStream};
+//! use parking_lot::RwLock;
+//! use std::sync::Arc;
+//!
+//! let config = Arc::new(RwLock::new(Config::init(false)?));
+//! let mut stream = ReplyStream::start(&config, Input::from_str("Hello"));
+//! for text in &mut stream {
+//!     print!("{text}");
+//! }
+//! let reply = stream.finish()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+#[macro_use]
+extern crate log;
+
+// The binary compiles these modules itself, the library only exposes the client layer below
+#[allow(dead_code)]
+mod client;
+#[allow(dead_code)]
+mod config;
+#[allow(dead_code)]
+mod render;
+#[allow(dead_code)]
+mod utils;
+
+pub use crate::client::{
+    init_client, list_models, Client, Message, MessageContent, MessageRole, Model, ReplyStream,
+};
+pub use crate::config::{Config, GlobalConfig, Input};
+pub use crate::render::{ReplyEvent, ReplyHandler};
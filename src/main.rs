@@ -1,37 +1,92 @@
 mod cli;
 mod client;
 mod config;
+mod coprocess;
 mod render;
 mod repl;
+mod tui;
+mod upgrade;
 
 #[macro_use]
 extern crate log;
 #[macro_use]
 mod utils;
 
-use crate::cli::Cli;
-use crate::config::{Config, GlobalConfig};
-use crate::utils::{extract_block, run_command, CODE_BLOCK_RE};
+use crate::cli::{Cli, Command, JudgeArgs, OutputFormat, RolePackCommand, SweepArgs};
+use crate::config::backup::{backup, restore};
+use crate::config::batch::{load_batch, run_batch};
+use crate::config::best_of::best_of;
+use crate::config::input::STDIN_FILE;
+use crate::config::judge::judge;
+use crate::config::pipeline::run_pipeline;
+use crate::config::role_pack::{
+    install as install_role_pack, list as list_role_packs, remove as remove_role_pack,
+    update as update_role_packs,
+};
+use crate::config::schema::extract_json;
+use crate::config::{Config, GlobalConfig, OutputNormalization};
+use crate::utils::abort_signal::abort_after;
+use crate::utils::clipboard::set_tmux_buffer;
+use crate::utils::nuon::to_nuon;
+use crate::utils::{
+    count_tokens, detect_code_language, extract_block, parse_duration, render_prompt, run_command,
+    AbortSignal, CodeBlockStream, CODE_BLOCK_RE,
+};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
-use client::{ensure_model_capabilities, init_client, list_models};
+use client::{
+    ensure_model_capabilities, healthcheck::healthcheck, init_client_for_input, is_local_model,
+    list_models, Cassette, ErrorKind, ReplyStream,
+};
 use config::Input;
 use inquire::validator::Validation;
 use inquire::Text;
 use is_terminal::IsTerminal;
 use parking_lot::RwLock;
-use render::{render_error, render_stream, MarkdownRender};
+use render::stream::StreamStats;
+use render::{create_renderer, render_error, render_stream};
 use repl::Repl;
-use std::io::{stderr, stdin, stdout, Read};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::{stderr, stdin, stdout, Read, Write};
+use std::path::Path;
 use std::process;
 use std::sync::Arc;
+use std::time::Duration;
 use utils::{cl100k_base_singleton, create_abort_signal};
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    if let Some(path) = &cli.backup {
+        return backup(path, cli.exclude_keys);
+    }
+    if let Some(path) = &cli.restore {
+        return restore(path);
+    }
     let text = cli.text();
-    let config = Arc::new(RwLock::new(Config::init(text.is_none())?));
+    let config = Arc::new(RwLock::new(Config::init(
+        text.is_none() && cli.template.is_none(),
+    )?));
+    if cli.no_save {
+        config.write().save = false;
+        config.write().no_save = true;
+    }
+    if cli.offline {
+        config.write().offline = true;
+    }
+    if cli.lite {
+        config.write().lite = true;
+    }
+    if let Some(path) = &cli.record {
+        config.write().cassette = Some(Cassette::record(Path::new(path)));
+    } else if let Some(path) = &cli.replay {
+        config.write().cassette = Some(Cassette::replay(Path::new(path))?);
+    }
+    if let Some(path) = &cli.schema {
+        config.write().set_output_schema(path)?;
+    }
     if cli.list_roles {
         config
             .read()
@@ -41,7 +96,11 @@ fn main() -> Result<()> {
         return Ok(());
     }
     if cli.list_models {
-        for model in list_models(&config.read()) {
+        let config = config.read();
+        for model in list_models(&config) {
+            if config.offline && !is_local_model(&config, &model) {
+                continue;
+            }
             println!("{}", model.id());
         }
         return Ok(());
@@ -51,6 +110,55 @@ fn main() -> Result<()> {
         println!("{sessions}");
         return Ok(());
     }
+    if cli.prune_sessions {
+        let config = config.read();
+        let names = config.prune_sessions()?;
+        let action = if config.session_retention.archive {
+            "Archived"
+        } else {
+            "Deleted"
+        };
+        for name in &names {
+            println!("{action} session '{name}'");
+        }
+        if names.is_empty() {
+            println!("No sessions to prune.");
+        }
+        return Ok(());
+    }
+    if cli.lint_roles {
+        let issues = config.read().lint_roles();
+        for issue in &issues {
+            println!("{issue}");
+        }
+        if !issues.is_empty() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if cli.check_budget {
+        let alerts = config.read().check_usage_alerts()?;
+        if alerts.is_empty() {
+            println!("No usage_alerts are configured.");
+        }
+        for alert in &alerts {
+            println!("{}", alert.summary());
+        }
+        if alerts.iter().any(|v| v.exceeded()) {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if cli.healthcheck {
+        let reports = healthcheck(&config)?;
+        for report in &reports {
+            println!("{}", report.summary());
+        }
+        if !reports.iter().all(|v| v.is_ok()) {
+            process::exit(1);
+        }
+        return Ok(());
+    }
     if let Some(wrap) = &cli.wrap {
         config.write().set_wrap(wrap)?;
     }
@@ -65,16 +173,22 @@ fn main() -> Result<()> {
     } else if cli.execute {
         config.write().set_execute_role()?;
     } else if cli.code {
-        config.write().set_code_role()?;
+        config.write().set_code_role(cli.lang.as_deref())?;
     }
     if let Some(session) = &cli.session {
         config
             .write()
             .start_session(session.as_ref().map(|v| v.as_str()))?;
     }
+    if let Some(name) = &cli.new_from_template {
+        config.write().start_session_from_template(name)?;
+    }
     if let Some(model) = &cli.model {
         config.write().set_model(model)?;
     }
+    if cli.no_stream {
+        config.write().stream = false;
+    }
     if cli.no_highlight {
         config.write().highlight = false;
     }
@@ -83,88 +197,347 @@ fn main() -> Result<()> {
         println!("{}", info);
         return Ok(());
     }
-    let text = aggregate_text(text)?;
-    if cli.execute {
-        match text {
-            Some(text) => {
-                execute(&config, &text)?;
+    if cli.coprocess {
+        return coprocess::run(&config);
+    }
+    if let Some(path) = &cli.batch {
+        let items = load_batch(path)?;
+        let mut failed = false;
+        run_batch(&config, items, cli.batch_concurrency, |result| {
+            failed |= !result.is_ok();
+            println!("{}", serde_json::to_string(&result).unwrap_or_default());
+        })?;
+        if failed {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    match &cli.command {
+        Some(Command::Sweep(args)) => return sweep(&config, args),
+        Some(Command::Judge(args)) => return start_judge(&config, args),
+        Some(Command::Upgrade(args)) => {
+            if config.read().offline {
+                println!("Skip checking for updates in offline mode.");
                 return Ok(());
             }
-            None => bail!("No input text"),
+            if !config.read().check_updates {
+                bail!("Update checks are disabled by `check_updates: false`");
+            }
+            return upgrade::upgrade(args.check);
+        }
+        Some(Command::RolePack(command)) => return role_pack(&config, command),
+        None => {}
+    }
+    let stdin_attached = cli
+        .file
+        .as_ref()
+        .map(|v| v.iter().any(|file| file == STDIN_FILE))
+        .unwrap_or_default();
+    let text = if stdin_attached {
+        // `-f -` reads stdin as an attachment, e.g. a piped screenshot, which needs no more text
+        Some(text.unwrap_or_default())
+    } else {
+        aggregate_text(text, Some(&config))?
+    };
+    let text = match &cli.template {
+        Some(path) => {
+            let template = render_template(path, &cli.vars)?;
+            match text {
+                Some(text) => Some(format!("{template}\n{text}")),
+                None => Some(template),
+            }
+        }
+        None => text,
+    };
+    if cli.popup {
+        return start_popup(&config, text);
+    }
+    if cli.execute {
+        let text = text.ok_or_else(|| anyhow!("No input text"))?;
+        match execute(&config, &text) {
+            Ok(0) => return Ok(()),
+            Ok(code) => process::exit(code),
+            Err(err) => exit_with_error(&config, err),
         }
     }
     config.write().prelude()?;
+    if cli.tui {
+        return tui::run(&config);
+    }
     if let Err(err) = match text {
-        Some(text) => start_directive(&config, &text, cli.file, cli.no_stream, cli.code),
+        Some(text) if cli.best_of.is_some() => {
+            start_best_of(&config, &text, cli.file, cli.best_of.unwrap_or_default())
+        }
+        Some(text) if cli.pipeline.is_some() => {
+            let name = cli.pipeline.as_deref().unwrap_or_default();
+            start_pipeline(&config, name, &text, cli.show_stages)
+        }
+        Some(text) => {
+            let max_time = cli.max_time.as_deref().map(parse_duration).transpose()?;
+            let code_mode = cli.code.then(|| cli.lang.clone());
+            start_directive(&config, &text, cli.file, code_mode, cli.format, max_time)
+        }
         None => start_interactive(&config),
     } {
-        let highlight = stderr().is_terminal() && config.read().highlight;
-        render_error(err, highlight)
+        exit_with_error(&config, err)
     }
     Ok(())
 }
 
+/// Render the error and exit with a code telling the failure type
+fn exit_with_error(config: &GlobalConfig, err: anyhow::Error) -> ! {
+    let highlight = stderr().is_terminal() && config.read().highlight;
+    let kind = ErrorKind::classify(&err);
+    render_error(err, highlight);
+    if let Some(hint) = kind.hint() {
+        eprintln!("{hint}");
+    }
+    if kind == ErrorKind::ModelNotFound {
+        if let Some(model) = config.read().suggest_model() {
+            eprintln!("Try '-m {model}' instead.");
+        }
+    }
+    process::exit(kind.exit_code())
+}
+
 fn start_directive(
     config: &GlobalConfig,
     text: &str,
     include: Option<Vec<String>>,
-    no_stream: bool,
-    code_mode: bool,
+    code_mode: Option<Option<String>>,
+    format: OutputFormat,
+    max_time: Option<Duration>,
 ) -> Result<()> {
     if let Some(session) = &config.read().session {
         session.guard_save()?;
     }
-    let input = Input::new(text, include.unwrap_or_default())?;
-    let mut client = init_client(config)?;
+    let files = config
+        .read()
+        .pack_files(text, include.unwrap_or_default())?;
+    let input = config.read().with_output_schema(Input::new(text, files)?);
+    let mut client = init_client_for_input(config, &input)?;
     ensure_model_capabilities(client.as_mut(), input.required_capabilities())?;
     config.read().maybe_print_send_tokens(&input);
-    let output = if !stdout().is_terminal() || no_stream {
-        let output = client.send_message(input.clone())?;
-        let output = if code_mode && output.trim_start().starts_with("```") {
-            extract_block(&output)
+    config.read().guard_untrusted(&input)?;
+    config.read().guard_budget(&input)?;
+    let constraints = config.read().output_constraints();
+    let normalization = config.read().output_normalization.clone();
+    let abort = create_abort_signal();
+    if let Some(max_time) = max_time {
+        abort_after(abort.clone(), max_time);
+    }
+    // Without a deadline, wait for the whole reply; with one, stream it to keep the partial output
+    let send = |v: &Input| match max_time {
+        Some(_) => ReplyStream::start_with_abort(config, v.clone(), abort.clone()).finish(),
+        None => client.send_message(v.clone()),
+    };
+    let output = if format == OutputFormat::Nuon {
+        let output = constraints.enforce(&input, send)?;
+        println!("{}", to_nuon(&output));
+        output
+    } else if format == OutputFormat::JsonlStream {
+        let output = constraints.enforce(&input, |v| stream_jsonl(config, v, abort.clone()))?;
+        let config = config.read();
+        let input_tokens = config.model.total_tokens(&config.build_messages(&input)?);
+        let output_tokens = count_tokens(&output);
+        let usage = json!({
+            "type": "usage",
+            "model": config.model.id(),
+            "input_tokens": input_tokens,
+            "output_tokens": output_tokens,
+            "total_tokens": input_tokens + output_tokens,
+        });
+        println!("{usage}");
+        output
+    } else if let Some(lang) = code_mode {
+        let output = if stdout().is_terminal() {
+            constraints.enforce(&input, |v| {
+                render_stream(v, client.as_ref(), config, abort.clone())
+            })?
+        } else if constraints.is_empty() {
+            stream_code(config, &input, &normalization, abort.clone())?
         } else {
-            output.clone()
+            // A retry would print the code twice, so wait for the reply that passes
+            let output = constraints.enforce(&input, send)?;
+            print!("{}", normalization.apply(&extract_block(&output)));
+            output
         };
-        if no_stream {
-            let render_options = config.read().get_render_options()?;
-            let mut markdown_render = MarkdownRender::init(render_options)?;
-            println!("{}", markdown_render.render(&output).trim());
-        } else {
-            println!("{}", output);
-        }
+        let code = normalization.apply(&extract_block(&output));
+        fence_code(&code, lang.as_deref())
+    } else if config.read().output_schema.is_some() {
+        let output = constraints.enforce(&input, send)?;
+        println!("{}", extract_json(&output));
         output
     } else {
-        let abort = create_abort_signal();
-        render_stream(&input, client.as_ref(), config, abort)?
+        constraints.enforce(&input, |v| {
+            render_stream(v, client.as_ref(), config, abort.clone())
+        })?
+    };
+    if abort.aborted_timeout() {
+        eprintln!("Stopped at the deadline, the reply is incomplete");
+    }
+    config.write().save_message(input, &output)?;
+    Ok(())
+}
+
+/// Print the code of the reply as it streams, the prose around it is left out
+fn stream_code(
+    config: &GlobalConfig,
+    input: &Input,
+    normalization: &OutputNormalization,
+    abort: AbortSignal,
+) -> Result<String> {
+    let newline = normalization.newline();
+    let mut code = CodeBlockStream::default();
+    let mut printed = false;
+    let mut print_lines = |lines: Vec<String>| -> Result<()> {
+        let mut stdout = stdout().lock();
+        for line in lines {
+            if printed {
+                write!(stdout, "{newline}")?;
+            }
+            write!(stdout, "{line}")?;
+            printed = true;
+        }
+        stdout.flush()?;
+        Ok(())
+    };
+    let mut stream = ReplyStream::start_with_abort(config, input.clone(), abort);
+    for text in stream.by_ref() {
+        print_lines(code.push(&text))?;
+    }
+    let output = stream.finish()?;
+    match code.finish() {
+        Ok(lines) => {
+            print_lines(lines)?;
+            if normalization.trailing_newline {
+                print!("{newline}");
+            }
+        }
+        Err(prose) => print!("{}", normalization.apply(&prose)),
+    }
+    Ok(output)
+}
+
+/// Print each delta of the reply as a JSON line
+fn stream_jsonl(config: &GlobalConfig, input: &Input, abort: AbortSignal) -> Result<String> {
+    let mut stream = ReplyStream::start_with_abort(config, input.clone(), abort);
+    for text in stream.by_ref() {
+        println!("{}", json!({ "type": "delta", "text": text }));
+    }
+    stream.finish()
+}
+
+fn start_best_of(
+    config: &GlobalConfig,
+    text: &str,
+    include: Option<Vec<String>>,
+    n: usize,
+) -> Result<()> {
+    let files = config
+        .read()
+        .pack_files(text, include.unwrap_or_default())?;
+    let input = Input::new(text, files)?;
+    config.read().guard_budget(&input)?;
+    let output = best_of(config, &input, n)?;
+    print_output(config, &output)?;
+    config.write().save_message(input, &output)?;
+    Ok(())
+}
+
+fn start_pipeline(config: &GlobalConfig, name: &str, text: &str, show_stages: bool) -> Result<()> {
+    let stages = config.read().pipeline(name)?;
+    let num_stages = stages.len();
+    let output = run_pipeline(config, &stages, text, |index, model, output| {
+        if show_stages {
+            eprintln!("--- stage {index}/{num_stages} ({model}) ---");
+            if index < num_stages {
+                if let Err(err) = print_output(config, output) {
+                    eprintln!("{err}");
+                }
+                eprintln!();
+            }
+        }
+    })?;
+    print_output(config, &output)?;
+    config
+        .write()
+        .save_message(Input::from_str(text), &output)?;
+    Ok(())
+}
+
+/// Print a complete reply, rendered as markdown on a terminal
+fn print_output(config: &GlobalConfig, output: &str) -> Result<()> {
+    if stdout().is_terminal() {
+        let render_options = config.read().get_render_options()?;
+        let mut render = create_renderer(render_options)?;
+        println!("{}", render.render(output).trim());
+    } else {
+        println!("{output}");
+    }
+    Ok(())
+}
+
+/// Put the code in a fence of the given or detected language, dropping any prose around it,
+/// so that highlighting, the saved message and the piped output agree
+fn fence_code(output: &str, lang: Option<&str>) -> String {
+    let code = extract_block(output);
+    let lang = lang
+        .or_else(|| detect_code_language(&code))
+        .unwrap_or_default();
+    format!("```{lang}\n{code}\n```")
+}
+
+fn start_popup(config: &GlobalConfig, text: Option<String>) -> Result<()> {
+    let text = match text {
+        Some(text) => text,
+        None => Text::new(">").prompt()?,
     };
+    let input = Input::new(&text, vec![])?;
+    let mut client = init_client_for_input(config, &input)?;
+    ensure_model_capabilities(client.as_mut(), input.required_capabilities())?;
+    config.read().guard_budget(&input)?;
+    let abort = create_abort_signal();
+    let output = render_stream(&input, client.as_ref(), config, abort)?;
     config.write().save_message(input, &output)?;
+    if set_tmux_buffer(&output).is_ok() {
+        eprint!("Copied to the tmux buffer, press Enter to close");
+    } else {
+        eprint!("Press Enter to close");
+    }
+    stdin().read_line(&mut String::new())?;
     Ok(())
 }
 
 fn start_interactive(config: &GlobalConfig) -> Result<()> {
     cl100k_base_singleton();
+    if config.read().auto_session_per_dir && config.read().session.is_none() {
+        config.write().start_dir_session()?;
+    }
     let mut repl: Repl = Repl::init(config)?;
     repl.run()
 }
 
-fn execute(config: &GlobalConfig, text: &str) -> Result<()> {
-    let input = Input::from_str(text);
-    let client = init_client(config)?;
+/// Ask for a shell command, then execute, describe or abort it. Returns the exit code of the command.
+fn execute(config: &GlobalConfig, text: &str) -> Result<i32> {
+    let input = Input::new(text, vec![])?;
+    let client = init_client_for_input(config, &input)?;
     config.read().maybe_print_send_tokens(&input);
+    config.read().guard_budget(&input)?;
     let mut eval_str = client.send_message(input.clone())?;
     if let Ok(true) = CODE_BLOCK_RE.is_match(&eval_str) {
         eval_str = extract_block(&eval_str);
     }
     config.write().save_message(input, &eval_str)?;
-    config.read().maybe_copy(&eval_str);
+    config.write().maybe_copy(&eval_str);
     let render_options = config.read().get_render_options()?;
-    let mut markdown_render = MarkdownRender::init(render_options)?;
+    let mut render = create_renderer(render_options)?;
     if config.read().dry_run {
-        println!("{}", markdown_render.render(&eval_str).trim());
-        return Ok(());
+        println!("{}", render.render(&eval_str).trim());
+        return Ok(0);
     }
     if stdout().is_terminal() {
-        println!("{}", markdown_render.render(&eval_str).trim());
+        println!("{}", render.render(&eval_str).trim());
         let mut describe = false;
         loop {
             let answer = Text::new("[e]xecute, [d]escribe, [a]bort: ")
@@ -183,10 +556,7 @@ fn execute(config: &GlobalConfig, text: &str) -> Result<()> {
 
             match answer.as_str() {
                 "E" | "e" => {
-                    let code = run_command(&eval_str)?;
-                    if code != 0 {
-                        process::exit(code);
-                    }
+                    return run_command(&eval_str);
                 }
                 "D" | "d" => {
                     if !describe {
@@ -205,15 +575,145 @@ fn execute(config: &GlobalConfig, text: &str) -> Result<()> {
     } else {
         println!("{}", eval_str);
     }
+    Ok(0)
+}
+
+fn sweep(config: &GlobalConfig, args: &SweepArgs) -> Result<()> {
+    let text =
+        aggregate_text(args.text(), Some(config))?.ok_or_else(|| anyhow!("No input text"))?;
+    let models = if args.model.is_empty() {
+        vec![config.read().model.id()]
+    } else {
+        args.model.clone()
+    };
+    let temperatures: Vec<Option<f64>> = if args.temperature.is_empty() {
+        vec![config.read().temperature]
+    } else {
+        args.temperature.iter().map(|v| Some(*v)).collect()
+    };
+    let render_options = config.read().get_render_options()?;
+    let mut render = create_renderer(render_options)?;
+    for model in &models {
+        config.write().set_model(model)?;
+        for temperature in &temperatures {
+            config.write().set_temperature(*temperature);
+            let input = Input::from_str(&text);
+            let temperature = temperature.map_or_else(|| String::from("-"), |v| v.to_string());
+            let label = format!(
+                "### model: {}, temperature: {temperature}",
+                config.read().model.id()
+            );
+            let mut stats = StreamStats::default();
+            let mut stream = ReplyStream::start(config, input);
+            for text in stream.by_ref() {
+                stats.add(&text);
+            }
+            let output = stream.finish()?;
+            println!("{}\n", render.render(&label));
+            println!("{}\n", render.render(&output).trim());
+            println!("{}\n", stats.summary());
+        }
+    }
+    Ok(())
+}
+
+fn role_pack(config: &GlobalConfig, command: &RolePackCommand) -> Result<()> {
+    if config.read().no_save && !matches!(command, RolePackCommand::List) {
+        bail!("Cannot change the role packs with --no-save");
+    }
+    if config.read().offline {
+        match command {
+            RolePackCommand::Install { source } if !Path::new(source).exists() => {
+                bail!("Unable to fetch the role pack '{source}' in offline mode")
+            }
+            RolePackCommand::Update { .. } => {
+                bail!("Unable to update the role packs in offline mode")
+            }
+            _ => {}
+        }
+    }
+    let registry = config.read().role_pack_registry.clone();
+    let registry = registry.as_deref();
+    match command {
+        RolePackCommand::Install { source } => {
+            let (name, pack) = install_role_pack(source, registry)?;
+            println!(
+                "Installed the role pack '{name}' {}, roles: {}",
+                pack.version,
+                pack.roles.join(", ")
+            );
+        }
+        RolePackCommand::Update { name } => {
+            let updated = update_role_packs(name.as_deref(), registry)?;
+            if updated.is_empty() {
+                println!("The role packs are up to date.");
+            }
+            for (name, old, new) in updated {
+                println!("Updated the role pack '{name}' from {old} to {new}");
+            }
+        }
+        RolePackCommand::Remove { name } => {
+            remove_role_pack(name)?;
+            println!("Removed the role pack '{name}'");
+        }
+        RolePackCommand::List => {
+            for (name, pack) in list_role_packs()? {
+                println!("{name:<20}{:<16}{}", pack.version, pack.roles.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn start_judge(config: &GlobalConfig, args: &JudgeArgs) -> Result<()> {
+    let rubric = read_to_string(&args.criteria)
+        .with_context(|| format!("Failed to load rubric at {}", args.criteria))?;
+    let text = match &args.file {
+        Some(file) => {
+            read_to_string(file).with_context(|| format!("Failed to load text at {file}"))?
+        }
+        None => aggregate_text(None, None)?.ok_or_else(|| anyhow!("No text to judge"))?,
+    };
+    let model = args
+        .model
+        .clone()
+        .or_else(|| config.read().judge_model.clone());
+    if let Some(model) = model {
+        config.write().set_model(&model)?;
+    }
+    let report = judge(config, &rubric, &text)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
     Ok(())
 }
 
-fn aggregate_text(text: Option<String>) -> Result<Option<String>> {
+fn render_template(path: &str, vars: &[String]) -> Result<String> {
+    let template =
+        read_to_string(path).with_context(|| format!("Failed to load template at {path}"))?;
+    let mut variables = HashMap::new();
+    for var in vars {
+        let (key, value) = var
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid var '{var}', expect <key>=<value>"))?;
+        let value = match value.strip_prefix('@') {
+            Some(file) => read_to_string(file)
+                .with_context(|| format!("Failed to read '{file}' for var '{key}'"))?,
+            None => value.to_string(),
+        };
+        variables.insert(key, value);
+    }
+    Ok(render_prompt(&template, &variables))
+}
+
+/// Join the text with stdin, applying the input filters of the config to stdin
+fn aggregate_text(text: Option<String>, config: Option<&GlobalConfig>) -> Result<Option<String>> {
     let text = if stdin().is_terminal() {
         text
     } else {
         let mut stdin_text = String::new();
         stdin().read_to_string(&mut stdin_text)?;
+        if let Some(config) = config {
+            stdin_text = config.read().filter_input(&stdin_text);
+        }
         if let Some(text) = text {
             Some(format!("{text}\n{stdin_text}"))
         } else {
@@ -1,3 +1,5 @@
+use super::RendererKind;
+
 use anyhow::{anyhow, Context, Result};
 use crossterm::style::{Color, Stylize};
 use crossterm::terminal;
@@ -195,14 +197,21 @@ pub struct RenderOptions {
     pub theme: Option<Theme>,
     pub wrap: Option<String>,
     pub wrap_code: bool,
+    pub renderer: RendererKind,
 }
 
 impl RenderOptions {
-    pub(crate) fn new(theme: Option<Theme>, wrap: Option<String>, wrap_code: bool) -> Self {
+    pub(crate) fn new(
+        theme: Option<Theme>,
+        wrap: Option<String>,
+        wrap_code: bool,
+        renderer: RendererKind,
+    ) -> Self {
         Self {
             theme,
             wrap,
             wrap_code,
+            renderer,
         }
     }
 }
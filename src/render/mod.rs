@@ -1,19 +1,19 @@
 mod markdown;
-mod stream;
+mod renderer;
+pub(crate) mod stream;
 
 pub use self::markdown::{MarkdownRender, RenderOptions};
-use self::stream::{markdown_stream, raw_stream};
+pub use self::renderer::{create_renderer, Renderer, RendererKind};
+use self::stream::{fold_text, line_stream, markdown_stream, raw_stream};
 
 use crate::client::Client;
 use crate::config::{GlobalConfig, Input};
-use crate::utils::AbortSignal;
+use crate::utils::{sanitize_output, AbortSignal};
 
 use anyhow::{Context, Result};
 use crossbeam::channel::{unbounded, Sender};
 use crossbeam::sync::WaitGroup;
-use is_terminal::IsTerminal;
 use nu_ansi_term::{Color, Style};
-use std::io::stdout;
 use std::thread::spawn;
 
 pub fn render_stream(
@@ -22,6 +22,22 @@ pub fn render_stream(
     config: &GlobalConfig,
     abort: AbortSignal,
 ) -> Result<String> {
+    if !config.read().should_stream(client.model()) {
+        let output = client.send_message_with_abort(input.clone(), abort)?;
+        let render_options = config.read().get_render_options()?;
+        let mut render = create_renderer(render_options)?;
+        if render.is_raw() {
+            println!("{}", output);
+        } else {
+            let text = render.render(&output);
+            let text = match config.read().fold_lines() {
+                Some(max_lines) => fold_text(&text, max_lines),
+                None => text,
+            };
+            println!("{}", text.trim());
+        }
+        return Ok(output);
+    }
     let wg = WaitGroup::new();
     let wg_cloned = wg.clone();
     let render_options = config.read().get_render_options()?;
@@ -29,13 +45,17 @@ pub fn render_stream(
         let (tx, rx) = unbounded();
         let abort_clone = abort.clone();
         let highlight = config.read().highlight;
+        let show_stats = config.read().stream_stats;
+        let fold = config.read().fold_lines();
         spawn(move || {
             let run = move || {
-                if stdout().is_terminal() {
-                    let mut render = MarkdownRender::init(render_options)?;
-                    markdown_stream(&rx, &mut render, &abort)
-                } else {
+                let mut render = create_renderer(render_options)?;
+                if render.is_interactive() {
+                    markdown_stream(&rx, render.as_mut(), &abort, show_stats, fold)
+                } else if render.is_raw() {
                     raw_stream(&rx, &abort)
+                } else {
+                    line_stream(&rx, render.as_mut(), &abort)
                 }
             };
             if let Err(err) = run() {
@@ -54,14 +74,25 @@ pub fn render_stream(
             Ok(output)
         }
         Err(err) => {
-            if !output.is_empty() {
-                println!();
+            if output.is_empty() {
+                return Err(err);
             }
-            Err(err)
+            println!();
+            Err(err.context(PartialReply(output)))
         }
     }
 }
 
+/// The text streamed before the reply failed, attached to the error so it can be kept
+#[derive(Debug)]
+pub struct PartialReply(pub String);
+
+impl std::fmt::Display for PartialReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The reply broke off after {} characters", self.0.len())
+    }
+}
+
 pub fn render_error(err: anyhow::Error, highlight: bool) {
     let err = format!("{err:?}");
     if highlight {
@@ -76,6 +107,15 @@ pub struct ReplyHandler {
     sender: Sender<ReplyEvent>,
     buffer: String,
     abort: AbortSignal,
+    resume: Option<Resume>,
+}
+
+/// How the text of a resumed stream joins the text already printed
+enum Resume {
+    /// The model continues the printed text, which was sent back without its trailing whitespace
+    Continue { trimmed: bool },
+    /// The model answers again, the part matching the printed text is skipped
+    Dedupe { printed: String, pending: String },
 }
 
 impl ReplyHandler {
@@ -84,6 +124,7 @@ impl ReplyHandler {
             sender,
             abort,
             buffer: String::new(),
+            resume: None,
         }
     }
 
@@ -92,10 +133,63 @@ impl ReplyHandler {
         if text.is_empty() {
             return Ok(());
         }
-        self.buffer.push_str(text);
+        let text = sanitize_output(text);
+        let text = match self.resume.take() {
+            None => text,
+            Some(Resume::Continue { trimmed }) => {
+                let text = if trimmed { text.trim_start() } else { &text };
+                if text.is_empty() {
+                    self.resume = Some(Resume::Continue { trimmed });
+                    return Ok(());
+                }
+                text.to_string()
+            }
+            Some(Resume::Dedupe {
+                printed,
+                mut pending,
+            }) => {
+                pending.push_str(&text);
+                if printed.starts_with(&pending) {
+                    self.resume = Some(Resume::Dedupe { printed, pending });
+                    return Ok(());
+                }
+                match pending.strip_prefix(&printed) {
+                    Some(rest) => rest.to_string(),
+                    None => {
+                        // The new answer diverged, start it over below the printed one
+                        self.send(format!("\n\n{pending}"))?;
+                        self.buffer = pending;
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        self.buffer.push_str(&text);
+        self.send(text)
+    }
+
+    /// Continue an interrupted stream from the printed text, returns the text to send back as
+    /// the start of the assistant message
+    pub fn resume_continue(&mut self) -> String {
+        let partial = self.buffer.trim_end().to_string();
+        self.resume = Some(Resume::Continue {
+            trimmed: partial.len() < self.buffer.len(),
+        });
+        partial
+    }
+
+    /// Restart an interrupted stream, skipping what was already printed
+    pub fn resume_dedupe(&mut self) {
+        self.resume = Some(Resume::Dedupe {
+            printed: self.buffer.clone(),
+            pending: String::new(),
+        });
+    }
+
+    fn send(&mut self, text: String) -> Result<()> {
         let ret = self
             .sender
-            .send(ReplyEvent::Text(text.to_string()))
+            .send(ReplyEvent::Text(text))
             .with_context(|| "Failed to send ReplyEvent:Text");
         self.safe_ret(ret)?;
         Ok(())
@@ -131,3 +225,26 @@ pub enum ReplyEvent {
     Text(String),
     Done,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_abort_signal;
+
+    #[test]
+    fn test_resume() {
+        let (tx, _rx) = unbounded();
+        let mut handler = ReplyHandler::new(tx, create_abort_signal());
+        handler.text("The cat sat ").unwrap();
+        assert_eq!(handler.resume_continue(), "The cat sat");
+        handler.text(" on the mat").unwrap();
+        assert_eq!(handler.get_buffer(), "The cat sat on the mat");
+        handler.resume_dedupe();
+        handler.text("The cat sat on").unwrap();
+        handler.text(" the mat.").unwrap();
+        assert_eq!(handler.get_buffer(), "The cat sat on the mat.");
+        handler.resume_dedupe();
+        handler.text("A cat").unwrap();
+        assert_eq!(handler.get_buffer(), "A cat");
+    }
+}
@@ -0,0 +1,230 @@
+use super::{MarkdownRender, RenderOptions};
+
+use anyhow::{bail, Result};
+use is_terminal::IsTerminal;
+use nu_ansi_term::{Color, Style};
+use serde::Deserialize;
+use std::env;
+use std::io::stdout;
+
+/// Turns the markdown of the replies into terminal output
+pub trait Renderer: Send {
+    /// Render a whole text, keeping track of code blocks
+    fn render(&mut self, text: &str) -> String;
+
+    /// Render the unfinished last line of a streamed text, without changing the state
+    fn render_line(&self, line: &str) -> String;
+
+    /// Whether the streamed text can be redrawn in place with cursor moves
+    fn is_interactive(&self) -> bool {
+        false
+    }
+
+    /// Whether the text is printed as is, so it can be streamed without waiting for whole lines
+    fn is_raw(&self) -> bool {
+        false
+    }
+}
+
+/// The renderer to use, `auto` picks one from the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RendererKind {
+    #[default]
+    Auto,
+    /// Highlighted markdown, redrawn while streaming
+    Markdown,
+    /// Colors without cursor moves, for dumb terminals such as Emacs shell
+    Minimal,
+    /// No colors, wrapped text
+    Plain,
+    /// The text as it comes from the model, for other programs
+    Machine,
+}
+
+impl RendererKind {
+    pub fn stringify(&self) -> &str {
+        match self {
+            RendererKind::Auto => "auto",
+            RendererKind::Markdown => "markdown",
+            RendererKind::Minimal => "minimal",
+            RendererKind::Plain => "plain",
+            RendererKind::Machine => "machine",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "auto" => Ok(RendererKind::Auto),
+            "markdown" => Ok(RendererKind::Markdown),
+            "minimal" => Ok(RendererKind::Minimal),
+            "plain" => Ok(RendererKind::Plain),
+            "machine" => Ok(RendererKind::Machine),
+            _ => bail!(
+                "Invalid renderer '{value}', expect auto, markdown, minimal, plain or machine"
+            ),
+        }
+    }
+
+    /// Output that is not a terminal always gets the machine renderer. `auto` picks minimal for
+    /// `TERM=dumb`, plain when `NO_COLOR` is set and markdown otherwise.
+    pub fn resolve(self) -> Self {
+        if !stdout().is_terminal() {
+            return RendererKind::Machine;
+        }
+        if self != RendererKind::Auto {
+            return self;
+        }
+        if env::var("TERM").map(|v| v == "dumb").unwrap_or_default() {
+            RendererKind::Minimal
+        } else if env::var("NO_COLOR")
+            .map(|v| !v.is_empty())
+            .unwrap_or_default()
+        {
+            RendererKind::Plain
+        } else {
+            RendererKind::Markdown
+        }
+    }
+}
+
+pub fn create_renderer(options: RenderOptions) -> Result<Box<dyn Renderer>> {
+    let renderer: Box<dyn Renderer> = match options.renderer.resolve() {
+        RendererKind::Auto | RendererKind::Markdown => Box::new(MarkdownRender::init(options)?),
+        RendererKind::Minimal => Box::new(MinimalRender::default()),
+        RendererKind::Plain => Box::new(PlainRender::init(&options)),
+        RendererKind::Machine => Box::new(MachineRender),
+    };
+    Ok(renderer)
+}
+
+impl Renderer for MarkdownRender {
+    fn render(&mut self, text: &str) -> String {
+        self.render(text)
+    }
+
+    fn render_line(&self, line: &str) -> String {
+        self.render_line(line)
+    }
+
+    fn is_interactive(&self) -> bool {
+        true
+    }
+}
+
+/// Bold headings and colored code, with plain SGR sequences only
+#[derive(Debug, Default)]
+pub struct MinimalRender {
+    in_code: bool,
+}
+
+impl MinimalRender {
+    fn style_line(&self, line: &str) -> (String, bool) {
+        let is_fence = line.trim_start().starts_with("```");
+        let output = if is_fence {
+            Style::new().dimmed().paint(line).to_string()
+        } else if self.in_code {
+            Color::Cyan.paint(line).to_string()
+        } else if line.starts_with('#') {
+            Style::new().bold().paint(line).to_string()
+        } else {
+            line.to_string()
+        };
+        (output, is_fence)
+    }
+}
+
+impl Renderer for MinimalRender {
+    fn render(&mut self, text: &str) -> String {
+        text.split('\n')
+            .map(|line| {
+                let (output, is_fence) = self.style_line(line);
+                if is_fence {
+                    self.in_code = !self.in_code;
+                }
+                output
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn render_line(&self, line: &str) -> String {
+        self.style_line(line).0
+    }
+}
+
+/// No escape sequences, only wrapping
+#[derive(Debug, Default)]
+pub struct PlainRender {
+    wrap_width: Option<usize>,
+}
+
+impl PlainRender {
+    pub fn init(options: &RenderOptions) -> Self {
+        let columns = crossterm::terminal::size().map(|v| v.0 as usize).ok();
+        let wrap_width = match options.wrap.as_deref() {
+            Some("auto") => columns,
+            Some(value) => value
+                .parse::<usize>()
+                .ok()
+                .map(|v| columns.map(|c| c.min(v)).unwrap_or(v)),
+            None => None,
+        };
+        Self { wrap_width }
+    }
+}
+
+impl Renderer for PlainRender {
+    fn render(&mut self, text: &str) -> String {
+        text.split('\n')
+            .map(|line| self.render_line(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn render_line(&self, line: &str) -> String {
+        match self.wrap_width {
+            Some(width) if width > 0 => textwrap::wrap(line, width).join("\n"),
+            _ => line.to_string(),
+        }
+    }
+}
+
+/// The text as is
+#[derive(Debug, Default)]
+pub struct MachineRender;
+
+impl Renderer for MachineRender {
+    fn render(&mut self, text: &str) -> String {
+        text.to_string()
+    }
+
+    fn render_line(&self, line: &str) -> String {
+        line.to_string()
+    }
+
+    fn is_raw(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimal_render() {
+        let mut render = MinimalRender::default();
+        let output = render.render("# Title\ntext\n```sh\nls\n```\ndone");
+        let expected = [
+            Style::new().bold().paint("# Title").to_string(),
+            "text".into(),
+            Style::new().dimmed().paint("```sh").to_string(),
+            Color::Cyan.paint("ls").to_string(),
+            Style::new().dimmed().paint("```").to_string(),
+            "done".into(),
+        ];
+        assert_eq!(output, expected.join("\n"));
+        assert!(!render.in_code);
+    }
+}
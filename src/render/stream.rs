@@ -1,13 +1,14 @@
-use super::{MarkdownRender, ReplyEvent};
+use super::{Renderer, ReplyEvent};
 
-use crate::utils::AbortSignal;
+use crate::utils::{count_tokens, current_status, set_status_drawn, AbortSignal};
 
 use anyhow::Result;
 use crossbeam::channel::Receiver;
 use crossterm::{
     cursor,
     event::{self, Event, KeyCode, KeyModifiers},
-    queue, style,
+    queue,
+    style::{self, Stylize},
     terminal::{self, disable_raw_mode, enable_raw_mode},
 };
 use std::{
@@ -19,14 +20,18 @@ use textwrap::core::display_width;
 
 pub fn markdown_stream(
     rx: &Receiver<ReplyEvent>,
-    render: &mut MarkdownRender,
+    render: &mut dyn Renderer,
     abort: &AbortSignal,
+    show_stats: bool,
+    fold: Option<usize>,
 ) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
+    set_status_drawn(true);
 
-    let ret = markdown_stream_inner(rx, render, abort, &mut stdout);
+    let ret = markdown_stream_inner(rx, render, abort, show_stats, fold, &mut stdout);
 
+    set_status_drawn(false);
     disable_raw_mode()?;
 
     ret
@@ -37,10 +42,12 @@ pub fn raw_stream(rx: &Receiver<ReplyEvent>, abort: &AbortSignal) -> Result<()>
         if abort.aborted() {
             return Ok(());
         }
-        if let Ok(evt) = rx.try_recv() {
+        if let Ok(evt) = rx.recv_timeout(Duration::from_millis(50)) {
             match evt {
                 ReplyEvent::Text(text) => {
-                    print!("{}", text);
+                    let mut stdout = io::stdout();
+                    write!(stdout, "{}", text)?;
+                    stdout.flush()?;
                 }
                 ReplyEvent::Done => {
                     break;
@@ -51,17 +58,61 @@ pub fn raw_stream(rx: &Receiver<ReplyEvent>, abort: &AbortSignal) -> Result<()>
     Ok(())
 }
 
+/// Print the complete lines through the renderer, for terminals without cursor moves
+pub fn line_stream(
+    rx: &Receiver<ReplyEvent>,
+    render: &mut dyn Renderer,
+    abort: &AbortSignal,
+) -> Result<()> {
+    let mut buffer = String::new();
+    let mut stdout = io::stdout();
+    loop {
+        if abort.aborted() {
+            return Ok(());
+        }
+        if let Ok(evt) = rx.recv_timeout(Duration::from_millis(50)) {
+            match evt {
+                ReplyEvent::Text(text) => {
+                    buffer.push_str(&text);
+                    if let Some(index) = buffer.rfind('\n') {
+                        let lines: String = buffer.drain(..index + 1).collect();
+                        writeln!(stdout, "{}", render.render(&lines[..index]))?;
+                        stdout.flush()?;
+                    }
+                }
+                ReplyEvent::Done => {
+                    if !buffer.is_empty() {
+                        write!(stdout, "{}", render.render(&buffer))?;
+                        stdout.flush()?;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 fn markdown_stream_inner(
     rx: &Receiver<ReplyEvent>,
-    render: &mut MarkdownRender,
+    render: &mut dyn Renderer,
     abort: &AbortSignal,
+    show_stats: bool,
+    fold: Option<usize>,
     writer: &mut Stdout,
 ) -> Result<()> {
     let mut last_tick = Instant::now();
     let tick_rate = Duration::from_millis(50);
 
-    let mut buffer = String::new();
-    let mut buffer_rows = 1;
+    // The unfinished line, and what has been printed for it
+    let mut tail = Tail::default();
+
+    let mut stats = StreamStats::default();
+
+    // The lines printed, and the ones hidden once the reply reaches `fold`
+    let mut printed_lines = 0;
+    let mut hidden_lines = 0;
+    let mut hidden_tail = false;
 
     let columns = terminal::size()?.0;
 
@@ -78,51 +129,33 @@ fn markdown_stream_inner(
 
             match reply_event {
                 ReplyEvent::Text(text) => {
-                    let (col, mut row) = cursor::position()?;
-
-                    // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
-                    if col == 0 && row > 0 && display_width(&buffer) == columns as usize {
-                        row -= 1;
+                    stats.add(&text);
+                    if show_stats {
+                        // Remove the indicator drawn after the text
+                        queue!(writer, terminal::Clear(terminal::ClearType::UntilNewLine))?;
                     }
-
-                    if row + 1 >= buffer_rows {
-                        queue!(writer, cursor::MoveTo(0, row + 1 - buffer_rows),)?;
-                    } else {
-                        let scroll_rows = buffer_rows - row - 1;
-                        queue!(
-                            writer,
-                            terminal::ScrollUp(scroll_rows),
-                            cursor::MoveTo(0, 0),
-                        )?;
-                    }
-
-                    // No guarantee that text returned by render will not be re-layouted, so it is better to clear it.
-                    queue!(writer, terminal::Clear(terminal::ClearType::FromCursorDown))?;
-
-                    if text.contains('\n') {
-                        let text = format!("{buffer}{text}");
-                        let (head, tail) = split_line_tail(&text);
-                        let output = render.render(head);
-                        print_block(writer, &output, columns)?;
-                        buffer = tail.to_string();
-                    } else {
-                        buffer = format!("{buffer}{text}");
+                    let mut lines = text.split('\n').peekable();
+                    while let Some(line) = lines.next() {
+                        let line_end = lines.peek().is_some();
+                        if fold.map(|v| printed_lines >= v).unwrap_or_default() {
+                            if line_end {
+                                hidden_lines += 1;
+                                hidden_tail = false;
+                            } else if !line.is_empty() {
+                                hidden_tail = true;
+                            }
+                            continue;
+                        }
+                        tail.update(writer, render, line, line_end, columns)?;
+                        if line_end {
+                            printed_lines += 1;
+                            queue!(writer, style::Print("\n"), cursor::MoveToColumn(0))?;
+                            tail = Tail::default();
+                        }
                     }
-
-                    let output = render.render_line(&buffer);
-                    if output.contains('\n') {
-                        let (head, tail) = split_line_tail(&output);
-                        buffer_rows = print_block(writer, head, columns)?;
-                        queue!(writer, style::Print(&tail),)?;
-
-                        // No guarantee the buffer width of the buffer will not exceed the number of columns.
-                        // So we calculate the number of rows needed, rather than setting it directly to 1.
-                        buffer_rows += need_rows(tail, columns);
-                    } else {
-                        queue!(writer, style::Print(&output))?;
-                        buffer_rows = need_rows(&output, columns);
+                    if show_stats {
+                        draw_indicator(writer, &stats.indicator(), tail.column(columns), columns)?;
                     }
-
                     writer.flush()?;
                 }
                 ReplyEvent::Done => {
@@ -157,9 +190,194 @@ fn markdown_stream_inner(
 
     spinner.stop(writer)?;
 
+    let hidden_lines = hidden_lines + hidden_tail as usize;
+    if hidden_lines > 0 {
+        queue!(
+            writer,
+            style::PrintStyledContent(fold_marker(hidden_lines).dim()),
+        )?;
+        writer.flush()?;
+    }
+
+    if show_stats {
+        queue!(
+            writer,
+            terminal::Clear(terminal::ClearType::UntilNewLine),
+            style::Print("\r\n"),
+            style::PrintStyledContent(stats.summary().dim()),
+        )?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Timing of a streamed reply
+#[derive(Debug, Clone)]
+pub struct StreamStats {
+    start: Instant,
+    first_token: Option<Duration>,
+    tokens: usize,
+}
+
+impl Default for StreamStats {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            first_token: None,
+            tokens: 0,
+        }
+    }
+}
+
+impl StreamStats {
+    pub fn add(&mut self, text: &str) {
+        if self.first_token.is_none() {
+            self.first_token = Some(self.start.elapsed());
+        }
+        self.tokens += count_tokens(text);
+    }
+
+    pub fn first_token_latency(&self) -> Option<Duration> {
+        self.first_token
+    }
+
+    /// Tokens per second since the first token arrived
+    pub fn tokens_per_second(&self) -> f64 {
+        let elapsed = match self.first_token {
+            Some(first_token) => self.start.elapsed().saturating_sub(first_token),
+            None => return 0.0,
+        };
+        if elapsed.as_millis() == 0 {
+            return 0.0;
+        }
+        self.tokens as f64 / elapsed.as_secs_f64()
+    }
+
+    pub fn indicator(&self) -> String {
+        format!(" {:.1} tok/s", self.tokens_per_second())
+    }
+
+    pub fn summary(&self) -> String {
+        let latency = self
+            .first_token_latency()
+            .map(|v| format!("{:.2}s", v.as_secs_f64()))
+            .unwrap_or_else(|| "-".into());
+        format!(
+            "first token {latency}, {} tokens, {:.1} tokens/s",
+            self.tokens,
+            self.tokens_per_second()
+        )
+    }
+}
+
+/// Draw the indicator after the cursor without moving it, if it fits on the line
+fn draw_indicator(writer: &mut Stdout, indicator: &str, col: usize, columns: u16) -> Result<()> {
+    if col + display_width(indicator) >= columns as usize {
+        return Ok(());
+    }
+    queue!(
+        writer,
+        cursor::SavePosition,
+        style::PrintStyledContent(indicator.dark_grey()),
+        cursor::RestorePosition,
+    )?;
     Ok(())
 }
 
+/// The line that replaces the folded part of a reply
+pub fn fold_marker(hidden_lines: usize) -> String {
+    format!("… (+{hidden_lines} lines, use .last full or .last pager)")
+}
+
+/// Keep the first lines of the text, and a marker for the rest
+pub fn fold_text(text: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= max_lines {
+        return text.to_string();
+    }
+    format!(
+        "{}\n{}",
+        lines[..max_lines].join("\n"),
+        fold_marker(lines.len() - max_lines)
+    )
+}
+
+/// Tracks the unfinished line so that only its rows are repainted when it changes.
+/// Lines that are complete are never touched again, so the scrollback stays intact.
+#[derive(Debug, Default)]
+struct Tail {
+    buffer: String,
+    output: String,
+    /// Set once the start of the line has scrolled off the screen, from then on the
+    /// remaining text is appended as is, since it is impossible to repaint.
+    overflow: bool,
+}
+
+impl Tail {
+    /// The column of the cursor after the printed line, without asking the terminal
+    fn column(&self, columns: u16) -> usize {
+        let last_line = self.output.rsplit('\n').next().unwrap_or_default();
+        display_width(last_line) % columns.max(1) as usize
+    }
+
+    fn update(
+        &mut self,
+        writer: &mut Stdout,
+        render: &mut dyn Renderer,
+        text: &str,
+        line_end: bool,
+        columns: u16,
+    ) -> Result<()> {
+        self.buffer.push_str(text);
+        // A complete line goes through `render` to keep track of code blocks
+        let output = if line_end {
+            render.render(&self.buffer)
+        } else {
+            render.render_line(&self.buffer)
+        };
+        if self.overflow {
+            queue!(writer, style::Print(text))?;
+        } else if let Some(delta) = output.strip_prefix(&self.output) {
+            print_text(writer, delta)?;
+        } else if self.erase(writer, columns)? {
+            print_text(writer, &output)?;
+        } else {
+            self.overflow = true;
+            queue!(writer, style::Print(text))?;
+        }
+        self.output = output;
+        Ok(())
+    }
+
+    /// Move to the first row of the printed line and clear it. Returns false if that row is
+    /// no longer on the screen.
+    fn erase(&self, writer: &mut Stdout, columns: u16) -> Result<bool> {
+        if self.output.is_empty() {
+            return Ok(true);
+        }
+        let (col, mut row) = cursor::position()?;
+
+        // Fix unexpected duplicate lines on kitty, see https://github.com/sigoden/aichat/issues/105
+        let last_line = self.output.rsplit('\n').next().unwrap_or_default();
+        let last_width = display_width(last_line);
+        if col == 0 && row > 0 && last_width > 0 && last_width.is_multiple_of(columns as usize) {
+            row -= 1;
+        }
+
+        let rows = need_rows(&self.output, columns);
+        if rows > row + 1 {
+            return Ok(false);
+        }
+        queue!(
+            writer,
+            cursor::MoveTo(0, row + 1 - rows),
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )?;
+        Ok(true)
+    }
+}
+
 struct Spinner {
     index: usize,
     message: String,
@@ -183,8 +401,17 @@ impl Spinner {
         }
         let frame = Self::DATA[self.index % Self::DATA.len()];
         let dots = ".".repeat((self.index / 5) % 4);
-        let line = format!("{frame}{}{:<3}", self.message, dots);
-        queue!(writer, cursor::MoveToColumn(0), style::Print(line),)?;
+        // A status such as a rate-limit backoff replaces the message while it lasts
+        let message = current_status()
+            .map(|v| format!(" {v}"))
+            .unwrap_or_else(|| self.message.clone());
+        let line = format!("{frame}{message}{:<3}", dots);
+        queue!(
+            writer,
+            cursor::MoveToColumn(0),
+            style::Print(line),
+            terminal::Clear(terminal::ClearType::UntilNewLine)
+        )?;
         if self.index == 0 {
             queue!(writer, cursor::Hide)?;
         }
@@ -198,6 +425,7 @@ impl Spinner {
             return Ok(());
         }
         self.stopped = true;
+        set_status_drawn(false);
         queue!(
             writer,
             cursor::MoveToColumn(0),
@@ -230,29 +458,40 @@ fn gather_events(rx: &Receiver<ReplyEvent>) -> Vec<ReplyEvent> {
     events
 }
 
-fn print_block(writer: &mut Stdout, text: &str, columns: u16) -> Result<u16> {
-    let mut num = 0;
-    for line in text.split('\n') {
-        queue!(
-            writer,
-            style::Print(line),
-            style::Print("\n"),
-            cursor::MoveLeft(columns),
-        )?;
-        num += 1;
-    }
-    Ok(num)
+/// The output may be wrapped into multiple lines, which need the carriage return in raw mode
+fn print_text(writer: &mut Stdout, text: &str) -> Result<()> {
+    queue!(writer, style::Print(text.replace('\n', "\r\n")))?;
+    Ok(())
 }
 
-fn split_line_tail(text: &str) -> (&str, &str) {
-    if let Some((head, tail)) = text.rsplit_once('\n') {
-        (head, tail)
-    } else {
-        ("", text)
-    }
+fn need_rows(text: &str, columns: u16) -> u16 {
+    text.split('\n')
+        .map(|line| {
+            let width = display_width(line).max(1) as u16;
+            width.div_ceil(columns)
+        })
+        .sum()
 }
 
-fn need_rows(text: &str, columns: u16) -> u16 {
-    let buffer_width = display_width(text).max(1) as u16;
-    (buffer_width + columns - 1) / columns
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_need_rows() {
+        assert_eq!(need_rows("", 10), 1);
+        assert_eq!(need_rows("0123456789", 10), 1);
+        assert_eq!(need_rows("0123456789a", 10), 2);
+        assert_eq!(need_rows("abc\n0123456789a", 10), 3);
+        assert_eq!(need_rows("\x1b[1mabc\x1b[0m", 3), 1);
+    }
+
+    #[test]
+    fn test_fold_text() {
+        assert_eq!(fold_text("a\nb", 2), "a\nb");
+        assert_eq!(
+            fold_text("a\nb\nc\nd", 2),
+            "a\nb\n… (+2 lines, use .last full or .last pager)"
+        );
+    }
 }
@@ -1,9 +1,13 @@
 use super::{ReplCommand, REPL_COMMANDS};
 
-use crate::config::GlobalConfig;
+use crate::config::{Config, GlobalConfig};
 
 use reedline::{Completer, Span, Suggestion};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::PathBuf;
+use std::time::SystemTime;
 
 impl Completer for ReplCompleter {
     fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
@@ -21,6 +25,30 @@ impl Completer for ReplCompleter {
         if parts_len == 0 {
             return suggestions;
         }
+
+        let (word, word_start) = parts[parts_len - 1];
+        if let Some(value) = word.strip_prefix('@') {
+            let span = Span::new(word_start, pos);
+            suggestions.extend(
+                complete_path(value)
+                    .into_iter()
+                    .map(|v| create_suggestion(format!("@{v}"), None, span)),
+            );
+            return suggestions;
+        }
+        if let Some(name) = word.strip_prefix(':') {
+            let span = Span::new(word_start, pos);
+            suggestions.extend(
+                self.config
+                    .read()
+                    .snippets
+                    .iter()
+                    .filter(|v| v.name.starts_with(name))
+                    .map(|v| create_suggestion(v.content.clone(), Some(v.name.clone()), span)),
+            );
+            return suggestions;
+        }
+
         let (cmd, cmd_start) = parts[0];
 
         if !cmd.starts_with('.') {
@@ -46,7 +74,20 @@ impl Completer for ReplCompleter {
             })
             .collect();
 
-        if parts_len > 1 {
+        if cmd == ".session" && parts_len == 2 {
+            let span = Span::new(parts[1].1, pos);
+            let names: Vec<String> = self
+                .config
+                .read()
+                .list_sessions()
+                .into_iter()
+                .filter(|name| name.starts_with(parts[1].0))
+                .collect();
+            for name in names {
+                let summary = self.session_summary(&name);
+                suggestions.push(create_suggestion(name, summary, span));
+            }
+        } else if parts_len > 1 {
             let span = Span::new(parts[parts_len - 1].1, pos);
             let args: Vec<&str> = parts.iter().skip(1).map(|(v, _)| *v).collect();
             suggestions.extend(
@@ -70,7 +111,20 @@ impl Completer for ReplCompleter {
                     format!("{name} ")
                 };
                 create_suggestion(name, Some(description.to_string()), span)
-            }))
+            }));
+            if parts_len == 1 {
+                suggestions.extend(
+                    self.config
+                        .read()
+                        .plugins
+                        .iter()
+                        .map(|v| (format!(".{} ", v.name), v))
+                        .filter(|(name, _)| name.starts_with(cmd))
+                        .map(|(name, v)| {
+                            create_suggestion(name, Some(v.description.clone()), span)
+                        }),
+                );
+            }
         }
         suggestions
     }
@@ -80,6 +134,8 @@ pub struct ReplCompleter {
     config: GlobalConfig,
     commands: Vec<ReplCommand>,
     groups: HashMap<&'static str, usize>,
+    /// The session summaries by name, read again only when the file is modified
+    session_summaries: RefCell<HashMap<String, (SystemTime, Option<String>)>>,
 }
 
 impl ReplCompleter {
@@ -101,7 +157,23 @@ impl ReplCompleter {
             config: config.clone(),
             commands,
             groups,
+            session_summaries: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn session_summary(&self, name: &str) -> Option<String> {
+        let path = Config::session_file(name).ok()?;
+        let modified = path.metadata().and_then(|v| v.modified()).ok()?;
+        if let Some((cached, summary)) = self.session_summaries.borrow().get(name) {
+            if *cached == modified {
+                return summary.clone();
+            }
         }
+        let summary = Config::read_session_summary(&path);
+        self.session_summaries
+            .borrow_mut()
+            .insert(name.to_string(), (modified, summary.clone()));
+        summary
     }
 }
 
@@ -116,6 +188,33 @@ fn create_suggestion(value: String, description: Option<String>, span: Span) ->
     }
 }
 
+fn complete_path(value: &str) -> Vec<String> {
+    let (dir, prefix) = match value.rsplit_once('/') {
+        Some((dir, prefix)) => (format!("{dir}/"), prefix),
+        None => (String::new(), value),
+    };
+    let dir_path = if dir.is_empty() {
+        PathBuf::from(".")
+    } else if let (Some(rest), Some(home)) = (dir.strip_prefix("~/"), dirs::home_dir()) {
+        home.join(rest)
+    } else {
+        PathBuf::from(&dir)
+    };
+    let mut output = vec![];
+    if let Ok(rd) = read_dir(dir_path) {
+        for entry in rd.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) || (prefix.is_empty() && name.starts_with('.')) {
+                continue;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            output.push(format!("{dir}{name}{suffix}"));
+        }
+    }
+    output.sort_unstable();
+    output
+}
+
 fn split_line(line: &str) -> Vec<(&str, usize)> {
     let mut parts = vec![];
     let mut part_start = None;
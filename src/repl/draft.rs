@@ -0,0 +1,95 @@
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+
+/// The input being sent, kept on disk until its reply is done so it can be restored after a crash
+pub struct Draft {
+    path: PathBuf,
+}
+
+impl Draft {
+    /// The draft of the session, or of this process without a session, so that REPLs running
+    /// side by side do not overwrite each other's draft
+    pub fn new(dir: &Path, session: Option<&str>) -> Self {
+        let name = match session {
+            Some(session) => format!("session-{session}.txt"),
+            None => format!("pid-{}.txt", std::process::id()),
+        };
+        Self {
+            path: dir.join(name),
+        }
+    }
+
+    pub fn save(&self, text: &str) {
+        if text.trim().is_empty() {
+            return;
+        }
+        if let Some(dir) = self.path.parent() {
+            let _ = create_dir_all(dir);
+        }
+        let _ = write(&self.path, text);
+    }
+
+    pub fn discard(&self) {
+        discard_draft(&self.path);
+    }
+}
+
+/// The draft left by a crashed run, the one of the session, or the latest one of a run without a
+/// session. Returns its path and text.
+pub fn find_draft(dir: &Path, session: Option<&str>) -> Option<(PathBuf, String)> {
+    let path = match session {
+        Some(session) => dir.join(format!("session-{session}.txt")),
+        None => {
+            let own = format!("pid-{}.txt", std::process::id());
+            read_dir(dir)
+                .ok()?
+                .flatten()
+                .filter(|v| {
+                    let name = v.file_name().to_string_lossy().to_string();
+                    name.starts_with("pid-") && name != own
+                })
+                .max_by_key(|v| v.metadata().and_then(|v| v.modified()).ok())?
+                .path()
+        }
+    };
+    let text = read_to_string(&path)
+        .ok()
+        .filter(|v| !v.trim().is_empty())?;
+    Some((path, text))
+}
+
+pub fn discard_draft(path: &Path) {
+    if path.exists() {
+        let _ = remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draft() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let draft = Draft::new(dir, Some("work"));
+        draft.save("how do I rebase");
+        assert_eq!(
+            find_draft(dir, Some("work")).map(|v| v.1).as_deref(),
+            Some("how do I rebase")
+        );
+        assert!(find_draft(dir, Some("other")).is_none());
+        draft.discard();
+        assert!(find_draft(dir, Some("work")).is_none());
+
+        // The draft of this process is not one left by a crashed run
+        let draft = Draft::new(dir, None);
+        draft.save("unsent");
+        assert!(find_draft(dir, None).is_none());
+        write(dir.join("pid-1.txt"), "crashed").unwrap();
+        assert_eq!(
+            find_draft(dir, None).map(|v| v.1).as_deref(),
+            Some("crashed")
+        );
+    }
+}
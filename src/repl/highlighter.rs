@@ -2,9 +2,15 @@ use super::REPL_COMMANDS;
 
 use crate::config::GlobalConfig;
 
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
 use nu_ansi_term::{Color, Style};
 use reedline::{Highlighter, StyledText};
 
+lazy_static! {
+    static ref TOKEN_RE: Regex = Regex::new(r"(?<!\S)@\S+|\{\{[\w.-]+\}\}|`[^`\n]+`").unwrap();
+}
+
 pub struct ReplHighlighter {
     config: GlobalConfig,
 }
@@ -20,14 +26,13 @@ impl ReplHighlighter {
 impl Highlighter for ReplHighlighter {
     fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
         let color = Color::Default;
-        let match_color = if self.config.read().highlight {
-            Color::Green
-        } else {
-            color
-        };
-
         let mut styled_text = StyledText::new();
 
+        if !self.config.read().highlight {
+            styled_text.push((Style::new().fg(color), line.to_string()));
+            return styled_text;
+        }
+
         if REPL_COMMANDS.iter().any(|cmd| line.contains(cmd.name)) {
             let matches: Vec<&str> = REPL_COMMANDS
                 .iter()
@@ -43,13 +48,29 @@ impl Highlighter for ReplHighlighter {
             });
             let buffer_split: Vec<&str> = line.splitn(2, &longest_match).collect();
 
-            styled_text.push((Style::new().fg(color), buffer_split[0].to_string()));
-            styled_text.push((Style::new().fg(match_color), longest_match));
-            styled_text.push((Style::new().fg(color), buffer_split[1].to_string()));
+            push_tokens(&mut styled_text, buffer_split[0]);
+            styled_text.push((Style::new().fg(Color::Green), longest_match));
+            push_tokens(&mut styled_text, buffer_split[1]);
         } else {
-            styled_text.push((Style::new().fg(color), line.to_string()));
+            push_tokens(&mut styled_text, line);
         }
 
         styled_text
     }
 }
+
+/// Color the `@file` mentions, the `{{variables}}` and the code spans of the text
+fn push_tokens(styled_text: &mut StyledText, text: &str) {
+    let mut last = 0;
+    for token in TOKEN_RE.find_iter(text).flatten() {
+        let color = match token.as_str().chars().next() {
+            Some('@') => Color::Cyan,
+            Some('{') => Color::Purple,
+            _ => Color::Yellow,
+        };
+        styled_text.push((Style::new(), text[last..token.start()].to_string()));
+        styled_text.push((Style::new().fg(color), token.as_str().to_string()));
+        last = token.end();
+    }
+    styled_text.push((Style::new(), text[last..].to_string()));
+}
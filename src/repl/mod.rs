@@ -1,31 +1,61 @@
 mod completer;
+mod draft;
 mod highlighter;
+mod patch;
 mod prompt;
+mod runner;
 
 use self::completer::ReplCompleter;
+use self::draft::{discard_draft, find_draft, Draft};
 use self::highlighter::ReplHighlighter;
+use self::patch::{extract_patches, git_apply};
 use self::prompt::ReplPrompt;
+use self::runner::{extract_code_blocks, run_code, PythonSession};
 
-use crate::client::{ensure_model_capabilities, init_client};
-use crate::config::{GlobalConfig, Input, State};
-use crate::render::{render_error, render_stream};
-use crate::utils::{create_abort_signal, set_text, AbortSignal};
+use crate::client::{
+    ensure_model_capabilities, init_client, init_client_for_input, list_models, ErrorKind, Model,
+};
+use crate::config::input::STDIN_FILE;
+use crate::config::prompt_library::search as search_prompts;
+use crate::config::{
+    Config, FinetuneOptions, GlobalConfig, Input, KeepPartialReply, Plugin, Role, State,
+    TEXT_SET_KEYS,
+};
+use crate::render::{create_renderer, render_error, render_stream, PartialReply};
+use crate::utils::extract::{extract_math, extract_tables, format_table};
+use crate::utils::status::{notify, take_notifications};
+use crate::utils::{create_abort_signal, fetch_url, wrap_untrusted, AbortSignal, CopyTarget};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use fancy_regex::Regex;
+use inquire::{Confirm, Select};
 use lazy_static::lazy_static;
+use nu_ansi_term::{Color, Style};
 use reedline::{
     default_emacs_keybindings, default_vi_insert_keybindings, default_vi_normal_keybindings,
-    ColumnarMenu, EditMode, Emacs, KeyCode, KeyModifiers, Keybindings, Reedline, ReedlineEvent,
-    ReedlineMenu, ValidationResult, Validator, Vi,
+    ColumnarMenu, EditCommand, EditMode, Emacs, KeyCode, KeyModifiers, Keybindings, Reedline,
+    ReedlineEvent, ReedlineMenu, ValidationResult, Validator, Vi,
 };
 use reedline::{MenuBuilder, Signal};
-use std::{env, process};
+use std::{
+    cell::{Cell, RefCell},
+    env,
+    io::Write,
+    process::{self, Stdio},
+};
 
 const MENU_NAME: &str = "completion_menu";
 
+const REPHRASE_PROMPT: &str = "Rewrite the following question so that it asks for the same thing while complying with content policies. Output only the rewritten question.";
+
+const TRANSLATE_PROMPT: &str = "Translate the following text to __LANGUAGE__, keeping its formatting and code blocks. Output only the translation.";
+
+const SESSION_SUMMARY_PROMPT: &str = "Summarize the following conversation in one short paragraph, naming its main topics, so it can be found later by content. Output only the summary.";
+
+const FOLLOWUPS_PROMPT: &str = "Propose 3 short follow-up questions the user may ask next about the following conversation. Output only the questions, one per line.";
+
 lazy_static! {
-    static ref REPL_COMMANDS: [ReplCommand; 14] = [
+    static ref REPL_COMMANDS: [ReplCommand; 31] = [
         ReplCommand::new(".help", "Print this help message", State::all()),
         ReplCommand::new(".info", "Print system info", State::all()),
         ReplCommand::new(".model", "Switch LLM model", State::all()),
@@ -37,32 +67,114 @@ lazy_static! {
             "Start a context-aware chat session",
             State::notin_session(),
         ),
+        ReplCommand::new(
+            ".session switch",
+            "Switch to another session, keeping the current one open",
+            State::all()
+        ),
         ReplCommand::new(".info session", "Show session info", State::in_session(),),
+        ReplCommand::new(
+            ".session stats",
+            "Show the replies and tokens of the session by model",
+            State::in_session(),
+        ),
         ReplCommand::new(
             ".clear messages",
             "Clear messages in the session",
             State::unable_change_role()
         ),
+        ReplCommand::new(
+            ".compress session",
+            "Compress messages in the session",
+            State::unable_change_role()
+        ),
+        ReplCommand::new(
+            ".pin",
+            "Keep a question and its reply when compressing the session",
+            State::in_session(),
+        ),
+        ReplCommand::new(
+            ".context",
+            "Send files with every request of the session (add <path|glob>, list, clear)",
+            State::in_session(),
+        ),
         ReplCommand::new(
             ".exit session",
             "End the current session",
             State::in_session(),
         ),
+        ReplCommand::new(
+            ".shell",
+            "Treat every input as a shell command request (on/off)",
+            State::able_change_role()
+        ),
         ReplCommand::new(
             ".file",
             "Attach files to the message and then submit it",
             State::all()
         ),
+        ReplCommand::new(
+            ".summarize",
+            "Summarize the session, a file or a URL",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".translate",
+            "Translate the last reply, or the given text, to a language",
+            State::all()
+        ),
         ReplCommand::new(".set", "Modify the configuration parameters", State::all()),
         ReplCommand::new(
             ".copy",
-            "Copy the last reply to the clipboard",
+            "Copy the last reply to the clipboard, or to 'primary', 'tmux' or 'file <path>'; 'list' and 'restore <n>' for earlier copies, 'table' and 'math' for parts",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".retry",
+            "Resend the last question, --rephrase to rewrite it first",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".apply",
+            "Preview a diff of the last reply and apply it to the working tree",
+            State::all()
+        ),
+        ReplCommand::new(".pick", "Choose one of the candidate replies", State::all()),
+        ReplCommand::new(
+            ".run",
+            "Run a code block of the last reply and send back the output, 'reset' restarts python",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".wc",
+            "Count the characters, words and tokens of the text",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".prompts",
+            "Search the prompt library and insert a prompt (import, export)",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".export finetune",
+            "Export sessions as fine-tuning JSONL: <path> [<session>...] [--roles <roles>] [--redact]",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".last",
+            "Show the whole last reply, in the terminal or a pager (full, pager)",
+            State::all()
+        ),
+        ReplCommand::new(
+            ".ask-followups",
+            "Suggest follow-up questions after each reply (on, off)",
             State::all()
         ),
         ReplCommand::new(".exit", "Exit the REPL", State::all()),
     ];
     static ref COMMAND_RE: Regex = Regex::new(r"^\s*(\.\S*)\s*").unwrap();
     static ref MULTILINE_RE: Regex = Regex::new(r"(?s)^\s*:::\s*(.*)\s*:::\s*$").unwrap();
+    static ref MODEL_OVERRIDE_RE: Regex = Regex::new(r"(?s)^\s*@(\S+?):\s+(.*)$").unwrap();
 }
 
 pub struct Repl {
@@ -70,10 +182,19 @@ pub struct Repl {
     editor: Reedline,
     prompt: ReplPrompt,
     abort: AbortSignal,
+    python: RefCell<Option<PythonSession>>,
+    ask_followups: Cell<bool>,
+    /// The next input is a `.retry`, so resending it is intended
+    retrying: Cell<bool>,
+    followups: RefCell<Vec<String>>,
+    pending_buffer: RefCell<Option<String>>,
 }
 
 impl Repl {
     pub fn init(config: &GlobalConfig) -> Result<Self> {
+        config.write().load_plugins()?;
+        config.write().in_repl = true;
+
         let editor = Self::create_editor(config)?;
 
         let prompt = ReplPrompt::new(config);
@@ -85,11 +206,17 @@ impl Repl {
             editor,
             prompt,
             abort,
+            python: RefCell::new(None),
+            ask_followups: Cell::new(false),
+            retrying: Cell::new(false),
+            followups: RefCell::new(vec![]),
+            pending_buffer: RefCell::new(None),
         })
     }
 
     pub fn run(&mut self) -> Result<()> {
         self.banner();
+        self.restore_draft();
 
         let mut already_ctrlc = false;
 
@@ -100,19 +227,38 @@ impl Repl {
             if self.abort.aborted_ctrlc() && !already_ctrlc {
                 already_ctrlc = true;
             }
+            self.print_notifications();
+            if let Some(text) = self.pending_buffer.take() {
+                self.editor
+                    .run_edit_commands(&[EditCommand::InsertString(text)]);
+            }
             let sig = self.editor.read_line(&self.prompt);
             match sig {
                 Ok(Signal::Success(line)) => {
                     already_ctrlc = false;
                     self.abort.reset();
-                    match self.handle(&line) {
+                    let draft = self.save_draft(&line);
+                    let ret = self.handle(&line);
+                    if let Some(draft) = draft {
+                        draft.discard();
+                    }
+                    match ret {
                         Ok(quit) => {
                             if quit {
                                 break;
                             }
                         }
                         Err(err) => {
+                            let kind = ErrorKind::classify(&err);
                             render_error(err, self.config.read().highlight);
+                            if kind == ErrorKind::ContentFilter {
+                                println!("Run '.retry --rephrase' to rewrite the question and try again.");
+                            }
+                            if kind == ErrorKind::ModelNotFound {
+                                if let Some(model) = self.config.read().suggest_model() {
+                                    println!("Run '.model {model}' to switch to a replacement.");
+                                }
+                            }
                             println!()
                         }
                     }
@@ -132,7 +278,8 @@ impl Repl {
                 _ => {}
             }
         }
-        self.handle(".exit session")?;
+        self.summarize_session_before_exit();
+        self.config.write().end_all_sessions()?;
         Ok(())
     }
 
@@ -145,7 +292,7 @@ impl Repl {
         match parse_command(line) {
             Some((cmd, args)) => match cmd {
                 ".help" => {
-                    dump_repl_help();
+                    dump_repl_help(&self.config.read().plugins);
                 }
                 ".info" => match args {
                     Some("role") => {
@@ -191,19 +338,202 @@ impl Repl {
                     },
                     None => println!(r#"Usage: .role <name> [text...]"#),
                 },
-                ".session" => {
-                    self.config.write().start_session(args)?;
+                ".session" => match args.map(|v| v.split_once(' ').unwrap_or((v, ""))) {
+                    Some(("switch", name)) if !name.trim().is_empty() => {
+                        self.config.write().switch_session(name.trim())?;
+                    }
+                    Some(("switch", _)) => println!("Usage: .session switch <name>"),
+                    Some(("stats", "")) => {
+                        println!("{}", self.config.read().session_stats()?);
+                    }
+                    _ => {
+                        self.config.write().start_session(args)?;
+                    }
+                },
+                ".summarize" => match args {
+                    None | Some("session") => {
+                        self.summarize_session()?;
+                    }
+                    Some(target) => {
+                        // Only the fetched pages are untrusted, the local files are the user's
+                        let content =
+                            if target.starts_with("http://") || target.starts_with("https://") {
+                                self.config.read().guard_fetch(target)?;
+                                wrap_untrusted(target, &fetch_url(target)?)
+                            } else {
+                                std::fs::read_to_string(target)
+                                    .with_context(|| format!("Unable to read file '{target}'"))?
+                            };
+                        let prompt = self.config.read().summarize_text_prompt.clone();
+                        self.ask(&format!("{prompt}\n\n{content}"), vec![])?;
+                    }
+                },
+                ".translate" => match args.map(|v| v.split_once(' ').unwrap_or((v, ""))) {
+                    Some((language, text)) => {
+                        let text = match text.trim() {
+                            "" => self.config.read().last_reply().to_string(),
+                            text => text.to_string(),
+                        };
+                        if text.is_empty() {
+                            bail!("No reply to translate");
+                        }
+                        let prompt = TRANSLATE_PROMPT.replace("__LANGUAGE__", language);
+                        self.ask(&format!("{prompt}\n\n{text}"), vec![])?;
+                    }
+                    None => println!("Usage: .translate <language> [text]"),
+                },
+                ".shell" => {
+                    let value = match args {
+                        Some("on") => true,
+                        Some("off") => false,
+                        None => !self.config.read().shell_mode,
+                        Some(_) => bail!("Usage: .shell [on|off]"),
+                    };
+                    self.config.write().shell_mode = value;
                 }
                 ".set" => {
                     if let Some(args) = args {
-                        self.config.write().update(args)?;
+                        match args.strip_prefix("--save ") {
+                            Some(args) => {
+                                let args = self.expand_set_args(args)?;
+                                self.config.write().update(&args)?;
+                                self.config.read().save_setting(&args)?;
+                                println!("Saved to the config file.");
+                            }
+                            None => {
+                                let args = self.expand_set_args(args)?;
+                                self.config.write().update(&args)?
+                            }
+                        }
                     }
                 }
+                ".context" => match args.map(|v| v.split_once(' ').unwrap_or((v, ""))) {
+                    Some(("add", pattern)) if !pattern.trim().is_empty() => {
+                        let files = self.config.write().add_context(pattern.trim())?;
+                        println!("Added {} files to the context.", files.len());
+                        if let Some(notice) = self.config.read().context_left_out() {
+                            println!("{notice}");
+                        }
+                    }
+                    Some(("list", _)) => println!("{}", self.config.read().list_context()?),
+                    Some(("clear", _)) => {
+                        self.config.write().clear_context()?;
+                        println!("Cleared the context.");
+                    }
+                    _ => println!("Usage: .context add <path|glob> | list | clear"),
+                },
+                ".apply" => self.apply_patch(args)?,
+                ".run" => match args {
+                    Some("reset") => {
+                        self.python.replace(None);
+                        println!("The python session is restarted.");
+                    }
+                    _ => self.run_code_block(args)?,
+                },
+                ".ask-followups" => match args {
+                    Some("on") => {
+                        self.ask_followups.set(true);
+                        println!("Follow-up questions will be suggested after each reply.");
+                    }
+                    Some("off") => {
+                        self.ask_followups.set(false);
+                        self.followups.borrow_mut().clear();
+                    }
+                    _ => println!("Usage: .ask-followups on|off"),
+                },
+                ".wc" => match args {
+                    Some(text) => {
+                        let stats = self.config.read().input_stats(text)?;
+                        println!("{stats}");
+                    }
+                    None => println!("Usage: .wc <text>"),
+                },
+                ".prompts" => match args.map(|v| v.split_once(' ').unwrap_or((v, ""))) {
+                    Some(("import", path)) if !path.is_empty() => {
+                        let names = self.config.read().import_prompts(path.trim())?;
+                        println!("Imported {}", names.join(", "));
+                    }
+                    Some(("export", dir)) if !dir.is_empty() => {
+                        let count = self.config.read().export_prompts(dir.trim())?;
+                        println!("Exported {count} prompts to {}", dir.trim());
+                    }
+                    Some(("import" | "export", _)) => {
+                        println!("Usage: .prompts import <path> or .prompts export <dir>")
+                    }
+                    _ => self.pick_prompt(args.unwrap_or_default())?,
+                },
+                ".export" => match args.and_then(|v| v.strip_prefix("finetune ")) {
+                    Some(args) => self.export_finetune(args)?,
+                    None => println!(
+                        "Usage: .export finetune <path> [<session>...] [--roles <roles>] [--redact]"
+                    ),
+                },
+                ".last" => match args {
+                    Some("full") => {
+                        let text = self.config.read().last_reply().to_string();
+                        let render_options = self.config.read().get_render_options()?;
+                        let mut render = create_renderer(render_options)?;
+                        println!("{}", render.render(&text).trim());
+                    }
+                    Some("pager") => self.page_last_reply()?,
+                    _ => println!("Usage: .last full|pager"),
+                },
                 ".copy" => {
-                    let config = self.config.read();
-                    self.copy(config.last_reply())
-                        .with_context(|| "Failed to copy the last output")?;
+                    let (target, args) = CopyTarget::parse(args.unwrap_or_default())?;
+                    match args {
+                        "" => {
+                            let text = self.config.read().last_reply().to_string();
+                            self.config
+                                .write()
+                                .copy_text_to(&target, &text)
+                                .with_context(|| "Failed to copy the last output")?;
+                        }
+                        "list" => println!("{}", self.config.read().list_copy_history()),
+                        args => match args.split_once(' ').unwrap_or((args, "")) {
+                            ("restore", n) if !n.trim().is_empty() => {
+                                let n = n.trim().parse().with_context(|| "Invalid number")?;
+                                self.config.write().restore_copy(&target, n)?;
+                                println!("Copied text {n} to {target}.");
+                            }
+                            ("table", args) => self.copy_table(&target, args)?,
+                            ("math", args) => self.copy_math(&target, args)?,
+                            _ => println!(
+                                "Usage: .copy [clipboard|primary|tmux|file <path>] [list|restore <n>|table [<n>] [csv|tsv]|math [<n>]]"
+                            ),
+                        },
+                    }
                 }
+                ".retry" => {
+                    let (text, files) = self
+                        .config
+                        .read()
+                        .last_input
+                        .clone()
+                        .ok_or_else(|| anyhow!("No question to retry"))?;
+                    match args {
+                        None => {
+                            self.retrying.set(true);
+                            self.ask(&text, files)?;
+                        }
+                        Some("--rephrase") => {
+                            let text = self.rephrase(&text)?;
+                            println!("{text}\n");
+                            self.retrying.set(true);
+                            self.ask(&text, files)?;
+                        }
+                        Some(_) => bail!("Usage: .retry [--rephrase]"),
+                    }
+                }
+                ".pick" => match args {
+                    Some(index) => {
+                        let index: usize = index.parse().with_context(|| "Invalid index")?;
+                        let (input, output) = self.config.write().pick_candidate(index)?;
+                        self.config.write().save_message(input, &output)?;
+                        self.config.write().maybe_copy(&output);
+                        self.maybe_compress_session();
+                    }
+                    None => println!("Usage: .pick <n>"),
+                },
                 ".read" => {
                     println!(r#"Deprecated. Use '.file' instead."#);
                 }
@@ -214,6 +544,9 @@ impl Repl {
                             None => (args, ""),
                         };
                         let files = shell_words::split(files).with_context(|| "Invalid args")?;
+                        if files.iter().any(|v| v == STDIN_FILE) {
+                            bail!("'{STDIN_FILE}' is only supported by '-f' on the command line, with a piped stdin");
+                        }
                         self.ask(text, files)?;
                     }
                     None => println!("Usage: .file <files>...[ -- <text>...]"),
@@ -223,6 +556,7 @@ impl Repl {
                         self.config.write().clear_role()?;
                     }
                     Some("session") => {
+                        self.summarize_session_before_exit();
                         self.config.write().end_session()?;
                     }
                     Some(_) => unknown_command()?,
@@ -230,6 +564,31 @@ impl Repl {
                         return Ok(true);
                     }
                 },
+                ".compress" => match args {
+                    Some("session") => {
+                        compress_session(&self.config)?;
+                        println!("✨ Successfully compressed the session.");
+                    }
+                    _ => unknown_command()?,
+                },
+                ".pin" => {
+                    let mut config = self.config.write();
+                    let session = config
+                        .session
+                        .as_mut()
+                        .ok_or_else(|| anyhow!("No active session"))?;
+                    match args {
+                        Some(n) => {
+                            let n = n.parse().with_context(|| "Usage: .pin [<n>]")?;
+                            if session.toggle_pin(n)? {
+                                println!("Pinned question {n} and its reply.");
+                            } else {
+                                println!("Unpinned question {n} and its reply.");
+                            }
+                        }
+                        None => println!("{}", session.list_pins()),
+                    }
+                }
                 ".clear" => match args {
                     Some("messages") => {
                         self.config.write().clear_session_messages()?;
@@ -242,10 +601,26 @@ impl Repl {
                     }
                     _ => unknown_command()?,
                 },
-                _ => unknown_command()?,
+                _ => {
+                    let plugin = self.config.read().find_plugin(cmd);
+                    match plugin {
+                        Some(plugin) => self.run_plugin(&plugin, args)?,
+                        None => unknown_command()?,
+                    }
+                }
             },
             None => {
-                self.ask(line, vec![])?;
+                let followup = parse_followup_choice(line)
+                    .and_then(|n| self.followups.borrow().get(n.wrapping_sub(1)).cloned());
+                if let Some(text) = followup {
+                    self.ask(&text, vec![])?;
+                } else if let Some((model, text)) = self.parse_model_override(line) {
+                    self.ask_with_model(model, &text)?;
+                } else if self.config.read().shell_mode {
+                    self.execute(line)?;
+                } else {
+                    self.ask_with_suggested_role(line)?;
+                }
             }
         }
 
@@ -254,33 +629,419 @@ impl Repl {
         Ok(false)
     }
 
+    /// Split `@<model>: <text>` into the model and the text, when the model exists
+    fn parse_model_override(&self, line: &str) -> Option<(Model, String)> {
+        let captures = MODEL_OVERRIDE_RE.captures(line).ok()??;
+        let model = self
+            .config
+            .read()
+            .resolve_model(captures.get(1)?.as_str())?;
+        Some((model, captures.get(2)?.as_str().to_string()))
+    }
+
+    /// Send one message to another model, keeping the active model of the session
+    fn ask_with_model(&self, model: Model, text: &str) -> Result<()> {
+        let model = std::mem::replace(&mut self.config.write().model, model);
+        let ret = self.ask(text, vec![]);
+        self.config.write().model = model;
+        ret
+    }
+
+    /// Offer the builtin role that suits the question before sending it
+    fn ask_with_suggested_role(&self, text: &str) -> Result<()> {
+        let role = self.config.read().suggest_role(text);
+        let kind = match role {
+            Some(Role::EXECUTE) => "a shell question",
+            Some(Role::CODE) => "a code request",
+            _ => return self.ask(text, vec![]),
+        };
+        let role = role.unwrap_or_default();
+        let ans = Confirm::new(&format!("This looks like {kind}, switch to {role}?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            return self.ask(text, vec![]);
+        }
+        if role == Role::EXECUTE {
+            return self.execute(text);
+        }
+        self.config.write().set_code_role(None)?;
+        let ret = self.ask(text, vec![]);
+        self.config.write().clear_role()?;
+        ret
+    }
+
+    fn execute(&self, text: &str) -> Result<()> {
+        let old_role = self.config.read().role.clone();
+        self.config.write().set_execute_role()?;
+        let ret = crate::execute(&self.config, text);
+        match old_role {
+            Some(role) => self.config.write().set_role_obj(role)?,
+            None => self.config.write().clear_role()?,
+        }
+        ret.map(|_| ())
+    }
+
+    /// Copy the n-th markdown table of the last reply as CSV or TSV
+    fn copy_table(&self, target: &CopyTarget, args: &str) -> Result<()> {
+        let mut index: usize = 1;
+        let mut separator = ',';
+        for arg in args.split_whitespace() {
+            match arg {
+                "csv" => separator = ',',
+                "tsv" => separator = '\t',
+                n => index = n.parse().with_context(|| "Invalid number")?,
+            }
+        }
+        let tables = extract_tables(self.config.read().last_reply());
+        let table = match index.checked_sub(1).and_then(|i| tables.get(i)) {
+            Some(table) => table,
+            None => bail!(
+                "No table {index} in the last reply, it has {}",
+                tables.len()
+            ),
+        };
+        self.config
+            .write()
+            .copy_text_to(target, &format_table(table, separator))?;
+        println!("Copied table {index} to {target}.");
+        Ok(())
+    }
+
+    /// Copy the raw LaTeX of the n-th math expression of the last reply, or of all of them
+    fn copy_math(&self, target: &CopyTarget, args: &str) -> Result<()> {
+        let math = extract_math(self.config.read().last_reply());
+        if math.is_empty() {
+            bail!("No math in the last reply");
+        }
+        let text = match args.trim() {
+            "" => math.join("\n\n"),
+            n => {
+                let index: usize = n.parse().with_context(|| "Invalid number")?;
+                match index.checked_sub(1).and_then(|i| math.get(i)) {
+                    Some(text) => text.clone(),
+                    None => bail!("No math {index} in the last reply, it has {}", math.len()),
+                }
+            }
+        };
+        self.config.write().copy_text_to(target, &text)?;
+        println!("Copied the math to {target}.");
+        Ok(())
+    }
+
+    /// Show the background events finished since the last prompt, dimmed to set them apart from
+    /// the replies
+    fn print_notifications(&self) {
+        let style = Style::new().dimmed();
+        for text in take_notifications() {
+            if self.config.read().highlight {
+                println!("{}", style.paint(format!("· {text}")));
+            } else {
+                println!("· {text}");
+            }
+        }
+    }
+
+    /// Offer to put the input left unsent by a crashed run back in the editor buffer
+    fn restore_draft(&self) {
+        let dir = match Config::drafts_dir() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let session = self
+            .config
+            .read()
+            .session
+            .as_ref()
+            .map(|v| v.name().to_string());
+        let (path, draft) = match find_draft(&dir, session.as_deref()) {
+            Some(v) => v,
+            None => return,
+        };
+        let ans = Confirm::new("Restore the unsent draft of the last run?")
+            .with_default(true)
+            .prompt()
+            .unwrap_or_default();
+        if ans {
+            *self.pending_buffer.borrow_mut() = Some(draft);
+        }
+        discard_draft(&path);
+    }
+
+    /// Keep the submitted input on disk until it is handled, unless nothing may be saved
+    fn save_draft(&self, text: &str) -> Option<Draft> {
+        let config = self.config.read();
+        if config.no_save {
+            return None;
+        }
+        let session = config.session.as_ref().map(|v| v.name());
+        let draft = Draft::new(&Config::drafts_dir().ok()?, session);
+        draft.save(text);
+        Some(draft)
+    }
+
+    /// Search the prompt library and put the chosen prompt in the editor buffer
+    fn pick_prompt(&self, query: &str) -> Result<()> {
+        let prompts = self.config.read().list_prompts();
+        if prompts.is_empty() {
+            bail!(
+                "No saved prompts, add markdown files to {} or run '.prompts import'",
+                Config::prompts_dir()?.display()
+            );
+        }
+        let matches = search_prompts(&prompts, query);
+        let prompt = match matches.as_slice() {
+            [] => bail!("No prompt matches '{query}'"),
+            [prompt] => *prompt,
+            _ => {
+                let options: Vec<String> = matches
+                    .iter()
+                    .map(|v| format!("{:<24}{}", v.name, v.preview()))
+                    .collect();
+                let choice = Select::new("Prompt:", options).raw_prompt()?;
+                matches[choice.index]
+            }
+        };
+        *self.pending_buffer.borrow_mut() = Some(prompt.content.clone());
+        Ok(())
+    }
+
+    fn export_finetune(&self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        let path = args.next().ok_or_else(|| anyhow!("Missing the path"))?;
+        let mut names = vec![];
+        let mut options = FinetuneOptions::default();
+        while let Some(arg) = args.next() {
+            match arg {
+                "--redact" => options.redact = true,
+                "--roles" => {
+                    let roles = args.next().ok_or_else(|| anyhow!("Missing the roles"))?;
+                    options.roles = FinetuneOptions::parse_roles(roles)?;
+                }
+                name => names.push(name.to_string()),
+            }
+        }
+        let count = self.config.read().export_finetune(path, &names, &options)?;
+        println!("Exported {count} conversations to {path}");
+        Ok(())
+    }
+
+    /// Open the last reply in `$PAGER`, defaults to `less -R`
+    fn page_last_reply(&self) -> Result<()> {
+        let text = self.config.read().last_reply().to_string();
+        if text.is_empty() {
+            bail!("No reply to show");
+        }
+        let pager = env::var("PAGER").unwrap_or_else(|_| "less -R".into());
+        let mut args = pager.split_whitespace();
+        let cmd = args.next().unwrap_or("less");
+        let mut child = process::Command::new(cmd)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run the pager '{pager}'"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        child.wait()?;
+        Ok(())
+    }
+
+    /// Ask the model to rewrite a question, outside the current session and role
+    fn rephrase(&self, text: &str) -> Result<String> {
+        self.side_ask(&format!("{REPHRASE_PROMPT}\n\n{text}"))
+    }
+
+    /// Ask the `followups_model` for follow-up questions and list them, typing `#<n>` asks one of them
+    fn suggest_followups(&self, question: &str, answer: &str) -> Result<()> {
+        let model = {
+            let config = self.config.read();
+            match &config.followups_model {
+                Some(name) => Some(
+                    Model::find(&list_models(&config), name)
+                        .ok_or_else(|| anyhow!("Invalid followups_model '{name}'"))?,
+                ),
+                None => None,
+            }
+        };
+        let text = format!("{FOLLOWUPS_PROMPT}\n\nQuestion:\n{question}\n\nAnswer:\n{answer}");
+        let output = match model {
+            Some(model) => {
+                let model = std::mem::replace(&mut self.config.write().model, model);
+                let ret = self.side_ask(&text);
+                self.config.write().model = model;
+                ret?
+            }
+            None => self.side_ask(&text)?,
+        };
+        let followups = parse_followups(&output);
+        if followups.is_empty() {
+            return Ok(());
+        }
+        let dim = Style::new().dimmed();
+        println!();
+        for (i, followup) in followups.iter().enumerate() {
+            println!("{}", dim.paint(format!("[#{}] {followup}", i + 1)));
+        }
+        println!("{}", dim.paint("Type #<n> to ask the follow-up question."));
+        self.followups.replace(followups);
+        Ok(())
+    }
+
+    /// Store a summary in the session before leaving it, shown when choosing a session
+    fn summarize_session_before_exit(&self) {
+        if !self.config.read().need_session_summary() {
+            return;
+        }
+        let transcript = match &self.config.read().session {
+            Some(session) => session.transcript(),
+            None => return,
+        };
+        match self.side_ask(&format!("{SESSION_SUMMARY_PROMPT}\n\n{transcript}")) {
+            Ok(summary) => {
+                if let Some(session) = self.config.write().session.as_mut() {
+                    session.set_summary(summary);
+                }
+            }
+            Err(err) => eprintln!("Failed to summarize the session, {err}"),
+        }
+    }
+
+    /// Send a one-off message, outside the current session and role
+    fn side_ask(&self, text: &str) -> Result<String> {
+        let input = Input::from_str(text);
+        let (session, role) = {
+            let mut config = self.config.write();
+            (config.session.take(), config.role.take())
+        };
+        let ret = init_client(&self.config).and_then(|client| client.send_message(input));
+        let mut config = self.config.write();
+        config.session = session;
+        config.role = role;
+        ret.map(|v| v.trim().to_string())
+    }
+
     fn ask(&self, text: &str, files: Vec<String>) -> Result<()> {
+        let retrying = self.retrying.replace(false);
         if text.is_empty() && files.is_empty() {
             return Ok(());
         }
         while self.config.read().is_compressing_session() {
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
-        let input = if files.is_empty() {
-            Input::from_str(text)
-        } else {
-            Input::new(text, files)?
-        };
+        self.followups.borrow_mut().clear();
+        let text = self.config.read().filter_input(text);
+        let text = text.as_str();
+        self.config.read().guard_paste(text)?;
+        self.config.write().last_input = Some((text.to_string(), files.clone()));
+        let files = self.config.read().pack_files(text, files)?;
+        let input = Input::new(text, files)?;
+        if !retrying {
+            self.config.read().guard_duplicate(&input)?;
+        }
         self.config.read().maybe_print_send_tokens(&input);
-        let mut client = init_client(&self.config)?;
+        self.config.read().guard_untrusted(&input)?;
+        self.config.read().guard_budget(&input)?;
+        let mut client = init_client_for_input(&self.config, &input)?;
         ensure_model_capabilities(client.as_mut(), input.required_capabilities())?;
-        let output = render_stream(&input, client.as_ref(), &self.config, self.abort.clone())?;
+        let candidates = self.config.read().candidates;
+        if candidates > 1 {
+            let outputs = client.send_message_candidates(input.clone(), candidates)?;
+            let render_options = self.config.read().get_render_options()?;
+            let mut render = create_renderer(render_options)?;
+            for (i, output) in outputs.iter().enumerate() {
+                println!("[{}]\n{}\n", i + 1, render.render(output).trim());
+            }
+            println!("Use '.pick <n>' to keep one of the candidates.");
+            self.config.write().set_candidates(input, outputs);
+            return Ok(());
+        }
+        let constraints = self.config.read().output_constraints();
+        let ret = constraints.enforce(&input, |v| {
+            render_stream(v, client.as_ref(), &self.config, self.abort.clone())
+        });
+        let output = match ret {
+            Ok(output) => output,
+            Err(err) => {
+                // Keep what was streamed before the error, like a reply cut short by Ctrl-C
+                if let Some(PartialReply(output)) = err.downcast_ref::<PartialReply>() {
+                    self.keep_partial_reply(input, output)?;
+                }
+                return Err(err);
+            }
+        };
+        if self.abort.aborted_ctrlc() {
+            return self.keep_partial_reply(input, &output);
+        }
         self.config.write().save_message(input, &output)?;
-        self.config.read().maybe_copy(&output);
+        self.config.write().maybe_copy(&output);
+        self.maybe_compress_session();
+        if self.ask_followups.get() {
+            if let Err(err) = self.suggest_followups(text, &output) {
+                eprintln!("Failed to suggest follow-up questions, {err}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the reply cut short by Ctrl-C or by an error according to `keep_partial_reply`
+    fn keep_partial_reply(&self, input: Input, output: &str) -> Result<()> {
+        if output.is_empty() {
+            return Ok(());
+        }
+        let keep = match self.config.read().keep_partial_reply {
+            KeepPartialReply::Always => true,
+            KeepPartialReply::Never => false,
+            KeepPartialReply::Ask => Confirm::new("Keep the partial reply, marked as truncated?")
+                .with_default(true)
+                .prompt()
+                .unwrap_or_default(),
+        };
+        if keep {
+            self.config.write().save_partial_message(input, output)?;
+        }
+        Ok(())
+    }
+
+    fn summarize_session(&self) -> Result<()> {
+        let has_messages = self
+            .config
+            .read()
+            .session
+            .as_ref()
+            .map(|v| !v.is_empty())
+            .unwrap_or_default();
+        if !has_messages {
+            bail!("No session messages to summarize")
+        }
+        let input = Input::from_str(&self.config.read().summarize_prompt);
+        let mut client = init_client(&self.config)?;
+        ensure_model_capabilities(client.as_mut(), input.required_capabilities())?;
+        let summary = render_stream(&input, client.as_ref(), &self.config, self.abort.clone())?;
+        if summary.is_empty() {
+            return Ok(());
+        }
+        let ans = Confirm::new("Replace the session history with the summary?")
+            .with_default(false)
+            .prompt()?;
+        if ans {
+            self.config.write().compress_session(&summary);
+        }
+        Ok(())
+    }
+
+    fn maybe_compress_session(&self) {
         if self.config.write().should_compress_session() {
             let config = self.config.clone();
             std::thread::spawn(move || -> anyhow::Result<()> {
-                let _ = compress_session(&config);
+                match compress_session(&config) {
+                    Ok(()) => notify("Compressed the session"),
+                    Err(err) => notify(&format!("Failed to compress the session, {err}")),
+                }
                 config.write().end_compressing_session();
                 Ok(())
             });
         }
-        Ok(())
     }
 
     fn banner(&self) {
@@ -289,7 +1050,14 @@ impl Repl {
             r#"Welcome to aichat {version}
 Type ".help" for more information.
 "#
-        )
+        );
+        if let Ok(alerts) = self.config.read().check_usage_alerts() {
+            for alert in alerts.iter().filter(|v| v.exceeded()) {
+                let (used, limit) = alert.values();
+                let text = format!("Warning: {} is at {used}, over {limit}", alert.name);
+                println!("{}", Color::Yellow.paint(text));
+            }
+        }
     }
 
     fn create_editor(config: &GlobalConfig) -> Result<Reedline> {
@@ -309,15 +1077,40 @@ Type ".help" for more information.
             .with_ansi_colors(true);
 
         if let Ok(cmd) = env::var("VISUAL").or_else(|_| env::var("EDITOR")) {
-            let temp_file =
-                env::temp_dir().join(format!("aichat-{}.txt", chrono::Utc::now().timestamp()));
-            let command = process::Command::new(cmd);
-            editor = editor.with_buffer_editor(command, temp_file);
+            if let Ok(command) = editor_command(&cmd) {
+                let temp_file =
+                    env::temp_dir().join(format!("aichat-{}.txt", chrono::Utc::now().timestamp()));
+                editor = editor.with_buffer_editor(command, temp_file);
+            }
         }
 
         Ok(editor)
     }
 
+    /// Read the value of `.set <key> @<file>` from the file, and open the editor for
+    /// `.set <key>` of a free-text key
+    fn expand_set_args(&self, args: &str) -> Result<String> {
+        let (key, value) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix('@').filter(|v| !v.is_empty()) {
+            let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+                (Some(path), Some(home)) => home.join(path),
+                _ => path.into(),
+            };
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to load the value at {}", path.display()))?;
+            return Ok(format!("{key} {}", text.trim_end()));
+        }
+        if value.is_empty() && TEXT_SET_KEYS.contains(&key) {
+            let current = self.config.read().text_setting(key).unwrap_or_default();
+            let text = edit_text(&current)?;
+            let text = text.trim_end();
+            let text = if text.trim().is_empty() { "null" } else { text };
+            return Ok(format!("{key} {text}"));
+        }
+        Ok(args.to_string())
+    }
+
     fn extra_keybindings(keybindings: &mut Keybindings) {
         keybindings.add_binding(
             KeyModifiers::NONE,
@@ -354,11 +1147,92 @@ Type ".help" for more information.
         ReedlineMenu::EngineCompleter(Box::new(completion_menu))
     }
 
-    fn copy(&self, text: &str) -> Result<()> {
-        if text.is_empty() {
-            bail!("Empty text")
+    /// Run the nth code block of the last reply, then send the output back for the next iteration
+    fn run_code_block(&self, index: Option<&str>) -> Result<()> {
+        let index: usize = match index {
+            Some(index) => index.parse().with_context(|| "Invalid index")?,
+            None => 1,
+        };
+        let blocks = extract_code_blocks(self.config.read().last_reply());
+        let block = index
+            .checked_sub(1)
+            .and_then(|i| blocks.get(i))
+            .ok_or_else(|| anyhow!("No code block {index} in the last reply"))?;
+        let (program, flag) = block
+            .interpreter()
+            .ok_or_else(|| anyhow!("Unable to infer the interpreter of the code block"))?;
+        let render_options = self.config.read().get_render_options()?;
+        let mut render = create_renderer(render_options)?;
+        let fenced = format!("```{}\n{}\n```", block.lang, block.code);
+        println!("{}", render.render(&fenced).trim());
+        let ans = Confirm::new(&format!("Run it with {program}?"))
+            .with_default(false)
+            .prompt()?;
+        if !ans {
+            return Ok(());
+        }
+        let output = if program == "python3" {
+            let mut python = self.python.borrow_mut();
+            let session = match python.as_mut() {
+                Some(session) => session,
+                None => python.insert(PythonSession::start()?),
+            };
+            session.run(&block.code)?
+        } else {
+            run_code(program, flag, &block.code)?
+        };
+        print!("{}", output.stdout);
+        eprint!("{}", output.stderr);
+        println!();
+        self.ask(&output.to_message(), vec![])
+    }
+
+    fn apply_patch(&self, index: Option<&str>) -> Result<()> {
+        let index: usize = match index {
+            Some(index) => index.parse().with_context(|| "Invalid index")?,
+            None => 1,
+        };
+        let patches = extract_patches(self.config.read().last_reply());
+        let patch = index
+            .checked_sub(1)
+            .and_then(|i| patches.get(i))
+            .ok_or_else(|| anyhow!("No diff {index} in the last reply"))?;
+        let render_options = self.config.read().get_render_options()?;
+        let mut render = create_renderer(render_options)?;
+        println!("{}", render.render(&format!("```diff\n{patch}```")).trim());
+        let stat = match git_apply(patch, true) {
+            Ok(stat) => stat,
+            Err(err) => {
+                println!("{err}");
+                let ans = Confirm::new("Ask the model to fix the diff?")
+                    .with_default(false)
+                    .prompt()?;
+                if ans {
+                    self.ask(&format!("{err}\n\nReply with a corrected unified diff against the current files."), vec![])?;
+                }
+                return Ok(());
+            }
+        };
+        print!("{stat}");
+        let ans = Confirm::new("Apply it to the working tree?")
+            .with_default(false)
+            .prompt()?;
+        if ans {
+            git_apply(patch, false)?;
+            println!("Applied the diff.");
+        }
+        Ok(())
+    }
+
+    fn run_plugin(&self, plugin: &Plugin, args: Option<&str>) -> Result<()> {
+        let request = self.config.read().plugin_request(args);
+        let res = plugin.call(request)?;
+        if let Some(output) = res.output {
+            println!("{output}");
+        }
+        if let Some(prompt) = res.prompt {
+            self.ask(&prompt, vec![])?;
         }
-        set_text(text)?;
         Ok(())
     }
 }
@@ -402,10 +1276,15 @@ fn unknown_command() -> Result<()> {
     bail!(r#"Unknown command. Type ".help" for more information."#);
 }
 
-fn dump_repl_help() {
+fn dump_repl_help(plugins: &[Plugin]) {
     let head = REPL_COMMANDS
         .iter()
         .map(|cmd| format!("{:<24} {}", cmd.name, cmd.description))
+        .chain(
+            plugins
+                .iter()
+                .map(|v| format!("{:<24} {}", format!(".{}", v.name), v.description)),
+        )
         .collect::<Vec<String>>()
         .join("\n");
     println!(
@@ -417,6 +1296,58 @@ Press Ctrl+C to abort aichat, Ctrl+D to exit the REPL"###,
     );
 }
 
+/// The follow-up chosen by a `#<n>` line, a bare number is sent as a question
+fn parse_followup_choice(line: &str) -> Option<usize> {
+    line.trim().strip_prefix('#')?.parse().ok()
+}
+
+/// Take the questions from the reply of the model, dropping the list markers
+fn parse_followups(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .trim_start_matches(['.', ')', '-', '*'])
+                .trim()
+        })
+        .filter(|line| !line.is_empty())
+        .take(3)
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Edit the text in `$VISUAL` or `$EDITOR`
+fn edit_text(text: &str) -> Result<String> {
+    let cmd = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .map_err(|_| anyhow!("Set $EDITOR to edit the value, or use '.set <key> @<file>'"))?;
+    let temp_file =
+        env::temp_dir().join(format!("aichat-set-{}.txt", chrono::Utc::now().timestamp()));
+    std::fs::write(&temp_file, text)
+        .with_context(|| format!("Failed to write {}", temp_file.display()))?;
+    let status = editor_command(&cmd)?
+        .arg(&temp_file)
+        .status()
+        .with_context(|| format!("Failed to run the editor '{cmd}'"))?;
+    let output = std::fs::read_to_string(&temp_file);
+    let _ = std::fs::remove_file(&temp_file);
+    if !status.success() {
+        bail!("The editor '{cmd}' exited with {status}");
+    }
+    output.with_context(|| "Failed to read the edited value")
+}
+
+/// The command of an editor with its arguments, e.g. `code --wait`
+fn editor_command(cmd: &str) -> Result<process::Command> {
+    let args = shell_words::split(cmd).with_context(|| format!("Invalid editor '{cmd}'"))?;
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| anyhow!("Invalid editor '{cmd}'"))?;
+    let mut command = process::Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
 fn parse_command(line: &str) -> Option<(&str, Option<&str>)> {
     match COMMAND_RE.captures(line) {
         Ok(Some(captures)) => {
@@ -442,6 +1373,23 @@ fn compress_session(config: &GlobalConfig) -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_followups() {
+        let text = "1. What is a trait?\n2) How do lifetimes work?\n\n- Why use Rc?\n4. Extra?";
+        assert_eq!(
+            parse_followups(text),
+            ["What is a trait?", "How do lifetimes work?", "Why use Rc?"]
+        );
+    }
+
+    #[test]
+    fn test_parse_followup_choice() {
+        assert_eq!(parse_followup_choice("#2"), Some(2));
+        assert_eq!(parse_followup_choice(" #1 "), Some(1));
+        assert_eq!(parse_followup_choice("2"), None);
+        assert_eq!(parse_followup_choice("# heading"), None);
+    }
+
     #[test]
     fn test_process_command_line() {
         assert_eq!(parse_command(" ."), Some((".", None)));
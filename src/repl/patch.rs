@@ -0,0 +1,74 @@
+use super::runner::extract_code_blocks;
+
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The unified diffs of the reply, from `diff` or `patch` code blocks or any block that looks
+/// like one, or the reply itself when it is a bare diff
+pub fn extract_patches(text: &str) -> Vec<String> {
+    let patches: Vec<String> = extract_code_blocks(text)
+        .into_iter()
+        .filter(|v| matches!(v.lang.as_str(), "diff" | "patch") || is_unified_diff(&v.code))
+        .map(|v| ensure_newline(v.code))
+        .collect();
+    if patches.is_empty() && is_unified_diff(text) {
+        return vec![ensure_newline(text.trim().to_string())];
+    }
+    patches
+}
+
+/// Run `git apply` on the patch, only checking it and printing its stat when `check` is set
+pub fn git_apply(patch: &str, check: bool) -> Result<String> {
+    let args: &[&str] = if check {
+        &["apply", "--check", "--stat", "-"]
+    } else {
+        &["apply", "-"]
+    };
+    let mut child = Command::new("git")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| "Failed to run 'git apply'")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(patch.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "The patch does not apply: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn is_unified_diff(text: &str) -> bool {
+    let mut lines = text.lines();
+    lines.any(|v| v.starts_with("--- "))
+        && lines.any(|v| v.starts_with("+++ "))
+        && lines.any(|v| v.starts_with("@@"))
+}
+
+fn ensure_newline(mut patch: String) -> String {
+    if !patch.ends_with('\n') {
+        patch.push('\n');
+    }
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_patches() {
+        let diff = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1 +1 @@\n-old\n+new";
+        let reply = format!("Apply this:\n\n```diff\n{diff}\n```\n\n```rust\nfn main() {{}}\n```");
+        assert_eq!(extract_patches(&reply), vec![format!("{diff}\n")]);
+        assert_eq!(extract_patches(diff), vec![format!("{diff}\n")]);
+        assert!(extract_patches("no patch here").is_empty());
+    }
+}
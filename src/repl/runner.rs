@@ -0,0 +1,181 @@
+use crate::utils::detect_code_language;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Reads one JSON encoded script per line, executes it in globals that persist across scripts,
+/// and replies with one JSON encoded result per line
+const PYTHON_DRIVER: &str = r#"
+import contextlib, io, json, sys, traceback
+reader, writer = sys.stdin, sys.stdout
+sys.stdin = io.StringIO()
+scope = {"__name__": "__main__"}
+for line in reader:
+    out, err, code = io.StringIO(), io.StringIO(), 0
+    with contextlib.redirect_stdout(out), contextlib.redirect_stderr(err):
+        try:
+            exec(compile(json.loads(line), "<aichat>", "exec"), scope)
+        except SystemExit as e:
+            code = e.code if isinstance(e.code, int) else 1
+        except BaseException:
+            traceback.print_exc()
+            code = 1
+    writer.write(json.dumps({"code": code, "stdout": out.getvalue(), "stderr": err.getvalue()}) + "\n")
+    writer.flush()
+"#;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub lang: String,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// The program and the flag to evaluate a script with, inferred from the fence or the code
+    pub fn interpreter(&self) -> Option<(&'static str, &'static str)> {
+        let lang = match self.lang.to_lowercase().as_str() {
+            "" => detect_code_language(&self.code)?.to_string(),
+            lang => lang.to_string(),
+        };
+        let interpreter = match lang.as_str() {
+            "python" | "py" | "python3" => ("python3", "-c"),
+            "bash" => ("bash", "-c"),
+            "sh" | "shell" | "console" => ("sh", "-c"),
+            "zsh" => ("zsh", "-c"),
+            "javascript" | "js" | "node" => ("node", "-e"),
+            "ruby" | "rb" => ("ruby", "-e"),
+            "perl" | "pl" => ("perl", "-e"),
+            _ => return None,
+        };
+        Some(interpreter)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunOutput {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl RunOutput {
+    /// The message to send the output back to the model
+    pub fn to_message(&self) -> String {
+        let mut message = format!("I ran the code, it exited with code {}.", self.code);
+        for (name, text) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if !text.trim().is_empty() {
+                message.push_str(&format!("\n\n{name}:\n```\n{}\n```", text.trim_end()));
+            }
+        }
+        message
+    }
+}
+
+/// Extract the fenced code blocks of a reply
+pub fn extract_code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = vec![];
+    let mut current: Option<(String, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match current.take() {
+            None => {
+                if let Some(lang) = trimmed.strip_prefix("```") {
+                    current = Some((lang.trim().to_string(), vec![]));
+                }
+            }
+            Some((lang, lines)) if trimmed.starts_with("```") => blocks.push(CodeBlock {
+                lang,
+                code: lines.join("\n"),
+            }),
+            Some((lang, mut lines)) => {
+                lines.push(line);
+                current = Some((lang, lines));
+            }
+        }
+    }
+    blocks
+}
+
+pub fn run_code(program: &str, flag: &str, code: &str) -> Result<RunOutput> {
+    let output = Command::new(program)
+        .arg(flag)
+        .arg(code)
+        .output()
+        .with_context(|| format!("Failed to run '{program}'"))?;
+    Ok(RunOutput {
+        code: output.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+/// A python interpreter kept alive across `.run`, so that variables survive between executions
+pub struct PythonSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl PythonSession {
+    pub fn start() -> Result<Self> {
+        let mut child = Command::new("python3")
+            .args(["-u", "-c", PYTHON_DRIVER])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| "Failed to start 'python3'")?;
+        let stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout"))?;
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    pub fn run(&mut self, code: &str) -> Result<RunOutput> {
+        writeln!(self.stdin, "{}", serde_json::to_string(code)?)?;
+        self.stdin.flush()?;
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            bail!("The python session exited, run '.run reset' to restart it");
+        }
+        let output =
+            serde_json::from_str(&line).with_context(|| "Invalid python session output")?;
+        Ok(output)
+    }
+}
+
+impl Drop for PythonSession {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_code_blocks() {
+        let text = "Try:\n```python\nprint(1)\n```\nor\n```\necho 2\n```\n";
+        let blocks = extract_code_blocks(text);
+        assert_eq!(
+            blocks,
+            vec![
+                CodeBlock {
+                    lang: "python".into(),
+                    code: "print(1)".into()
+                },
+                CodeBlock {
+                    lang: "".into(),
+                    code: "echo 2".into()
+                },
+            ]
+        );
+        assert_eq!(blocks[0].interpreter(), Some(("python3", "-c")));
+    }
+}
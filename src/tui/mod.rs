@@ -0,0 +1,399 @@
+use crate::client::{ensure_model_capabilities, init_client_for_input};
+use crate::config::{GlobalConfig, Input};
+use crate::render::{ReplyEvent, ReplyHandler};
+use crate::utils::create_abort_signal;
+
+use anyhow::{anyhow, Result};
+use crossbeam::channel::unbounded;
+use crossterm::{
+    event::{
+        self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers,
+    },
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use std::io::{stdout, Stdout};
+use std::thread;
+use std::time::Duration;
+use textwrap::core::display_width;
+
+type TuiTerminal = Terminal<CrosstermBackend<Stdout>>;
+
+const SIDEBAR_WIDTH: u16 = 28;
+const HELP: &str = " Enter: send, Tab: switch focus, PgUp/PgDn: scroll, Esc: quit ";
+
+/// Start the full-screen TUI, the plain REPL remains the default
+pub fn run(config: &GlobalConfig) -> Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    execute!(stdout, EnterAlternateScreen, EnableBracketedPaste)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    let ret = Tui::new(config).run(&mut terminal);
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        DisableBracketedPaste,
+        LeaveAlternateScreen
+    )?;
+    terminal.show_cursor()?;
+    ret?;
+    config.write().end_all_sessions()
+}
+
+/// Leave the full screen while `f` may ask for confirmation on the terminal, then come back
+fn suspend<T>(terminal: &mut TuiTerminal, f: impl FnOnce() -> T) -> Result<T> {
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        DisableBracketedPaste,
+        LeaveAlternateScreen
+    )?;
+    terminal.show_cursor()?;
+    let ret = f();
+    enable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        EnterAlternateScreen,
+        EnableBracketedPaste
+    )?;
+    terminal.clear()?;
+    Ok(ret)
+}
+
+struct Tui {
+    config: GlobalConfig,
+    transcript: Vec<Entry>,
+    input: String,
+    scroll_back: u16,
+    focus: Focus,
+    sidebar: Vec<SidebarItem>,
+    sidebar_state: ListState,
+}
+
+enum Entry {
+    User(String),
+    Assistant(String),
+    Notice(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Input,
+    Sidebar,
+}
+
+enum SidebarItem {
+    Session(String),
+    Role(String),
+}
+
+impl Tui {
+    fn new(config: &GlobalConfig) -> Self {
+        let mut tui = Self {
+            config: config.clone(),
+            transcript: vec![],
+            input: String::new(),
+            scroll_back: 0,
+            focus: Focus::Input,
+            sidebar: vec![],
+            sidebar_state: ListState::default(),
+        };
+        tui.refresh_sidebar();
+        tui
+    }
+
+    fn run(&mut self, terminal: &mut TuiTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                Event::Paste(text) if self.focus == Focus::Input => {
+                    self.input.push_str(&text.replace("\r\n", "\n"));
+                    continue;
+                }
+                _ => continue,
+            };
+            match (key.code, key.modifiers) {
+                (KeyCode::Esc, _) | (KeyCode::Char('c' | 'd'), KeyModifiers::CONTROL) => break,
+                (KeyCode::Tab, _) => {
+                    self.focus = match self.focus {
+                        Focus::Input => Focus::Sidebar,
+                        Focus::Sidebar => Focus::Input,
+                    };
+                }
+                (KeyCode::PageUp, _) => self.scroll_back = self.scroll_back.saturating_add(10),
+                (KeyCode::PageDown, _) => self.scroll_back = self.scroll_back.saturating_sub(10),
+                _ => {
+                    let ret = match self.focus {
+                        Focus::Input => self.handle_input_key(key, terminal),
+                        Focus::Sidebar => self.handle_sidebar_key(key),
+                    };
+                    if let Err(err) = ret {
+                        self.transcript.push(Entry::Notice(format!("{err:#}")));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_input_key(&mut self, key: KeyEvent, terminal: &mut TuiTerminal) -> Result<()> {
+        match key.code {
+            KeyCode::Enter => self.ask(terminal)?,
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Up => self.scroll_back = self.scroll_back.saturating_add(1),
+            KeyCode::Down => self.scroll_back = self.scroll_back.saturating_sub(1),
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.input.push(c);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_sidebar_key(&mut self, key: KeyEvent) -> Result<()> {
+        let len = self.sidebar.len();
+        if len == 0 {
+            return Ok(());
+        }
+        let selected = self.sidebar_state.selected().unwrap_or_default();
+        match key.code {
+            KeyCode::Up => self.sidebar_state.select(Some((selected + len - 1) % len)),
+            KeyCode::Down => self.sidebar_state.select(Some((selected + 1) % len)),
+            KeyCode::Enter => {
+                let notice = match &self.sidebar[selected] {
+                    SidebarItem::Session(name) => {
+                        self.config.write().switch_session(name)?;
+                        format!("Switched to session '{name}'")
+                    }
+                    SidebarItem::Role(name) => {
+                        self.config.write().set_role(name)?;
+                        format!("Use role '{name}'")
+                    }
+                };
+                self.transcript.push(Entry::Notice(notice));
+                self.refresh_sidebar();
+                self.focus = Focus::Input;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn ask(&mut self, terminal: &mut TuiTerminal) -> Result<()> {
+        let text = std::mem::take(&mut self.input);
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+        let input = Input::new(&text, vec![])?;
+        suspend(terminal, || {
+            self.config.read().guard_paste(&text)?;
+            self.config.read().guard_duplicate(&input)
+        })??;
+        self.scroll_back = 0;
+        self.transcript.push(Entry::User(text.clone()));
+        let mut client = init_client_for_input(&self.config, &input)?;
+        ensure_model_capabilities(client.as_mut(), input.required_capabilities())?;
+        suspend(terminal, || self.config.read().guard_budget(&input))??;
+
+        self.transcript.push(Entry::Assistant(String::new()));
+        let (tx, rx) = unbounded();
+        let abort = create_abort_signal();
+        let mut handler = ReplyHandler::new(tx, abort.clone());
+        let client = client.as_ref();
+        let output = thread::scope(|s| -> Result<String> {
+            let input = &input;
+            let task = s.spawn(move || {
+                client
+                    .send_message_streaming(input, &mut handler)
+                    .map(|_| handler.get_buffer().to_string())
+            });
+            loop {
+                for event in rx.try_iter() {
+                    if let (ReplyEvent::Text(text), Some(Entry::Assistant(reply))) =
+                        (event, self.transcript.last_mut())
+                    {
+                        reply.push_str(&text);
+                    }
+                }
+                terminal.draw(|f| self.draw(f))?;
+                if task.is_finished() {
+                    break;
+                }
+                if event::poll(Duration::from_millis(50))? {
+                    if let Event::Key(key) = event::read()? {
+                        if key.code == KeyCode::Esc
+                            || (key.code == KeyCode::Char('c')
+                                && key.modifiers.contains(KeyModifiers::CONTROL))
+                        {
+                            abort.set_ctrlc();
+                        }
+                    }
+                }
+            }
+            task.join().map_err(|_| anyhow!("Failed to get answer"))?
+        })?;
+        if let Some(Entry::Assistant(reply)) = self.transcript.last_mut() {
+            *reply = output.clone();
+        }
+        self.config.write().save_message(input, &output)?;
+        Ok(())
+    }
+
+    fn refresh_sidebar(&mut self) {
+        let config = self.config.read();
+        let current = config.session.as_ref().map(|v| v.name().to_string());
+        let mut sessions: Vec<String> = vec![];
+        for name in config
+            .background_sessions
+            .iter()
+            .map(|v| v.name().to_string())
+            .chain(config.list_sessions())
+        {
+            if Some(&name) != current.as_ref() && !sessions.contains(&name) {
+                sessions.push(name);
+            }
+        }
+        self.sidebar = sessions
+            .into_iter()
+            .map(SidebarItem::Session)
+            .chain(
+                config
+                    .roles
+                    .iter()
+                    .map(|v| SidebarItem::Role(v.name.clone())),
+            )
+            .collect();
+        if self.sidebar_state.selected().is_none() && !self.sidebar.is_empty() {
+            self.sidebar_state.select(Some(0));
+        }
+    }
+
+    fn draw(&mut self, f: &mut Frame) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(SIDEBAR_WIDTH), Constraint::Min(1)])
+            .split(f.size());
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(3)])
+            .split(columns[1]);
+
+        let focused = |focus: Focus| {
+            if self.focus == focus {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            }
+        };
+
+        let items: Vec<ListItem> = self
+            .sidebar
+            .iter()
+            .map(|v| match v {
+                SidebarItem::Session(name) => ListItem::new(format!("session:{name}")),
+                SidebarItem::Role(name) => ListItem::new(format!("role:{name}")),
+            })
+            .collect();
+        let sidebar = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(focused(Focus::Sidebar))
+                    .title(" Sessions / Roles "),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_stateful_widget(sidebar, columns[0], &mut self.sidebar_state);
+
+        let conversation_area = rows[0];
+        let lines = self.transcript_lines();
+        let width = conversation_area.width.saturating_sub(2).max(1) as usize;
+        let total: usize = lines
+            .iter()
+            .map(|line| line.width().max(1).div_ceil(width))
+            .sum();
+        let height = conversation_area.height.saturating_sub(2) as usize;
+        let max_scroll = total.saturating_sub(height) as u16;
+        self.scroll_back = self.scroll_back.min(max_scroll);
+        let conversation = Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .scroll((max_scroll - self.scroll_back, 0))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(self.conversation_title()),
+            );
+        f.render_widget(conversation, conversation_area);
+
+        let input_area = rows[1];
+        let input_width = input_area.width.saturating_sub(2) as usize;
+        // Pasted text may have multiple lines, only the last one is shown
+        let mut visible = self.input.rsplit('\n').next().unwrap_or_default();
+        while display_width(visible) >= input_width.max(1) {
+            let mut chars = visible.chars();
+            chars.next();
+            visible = chars.as_str();
+        }
+        let input = Paragraph::new(visible).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(focused(Focus::Input))
+                .title(HELP),
+        );
+        f.render_widget(input, input_area);
+        if self.focus == Focus::Input {
+            f.set_cursor(
+                input_area.x + 1 + display_width(visible) as u16,
+                input_area.y + 1,
+            );
+        }
+    }
+
+    fn transcript_lines(&self) -> Vec<Line<'static>> {
+        let mut lines = vec![];
+        for entry in &self.transcript {
+            match entry {
+                Entry::User(text) => {
+                    let style = Style::default()
+                        .fg(Color::Green)
+                        .add_modifier(Modifier::BOLD);
+                    for line in text.lines() {
+                        lines.push(Line::from(Span::styled(format!("> {line}"), style)));
+                    }
+                }
+                Entry::Assistant(text) => {
+                    lines.extend(text.lines().map(|v| Line::from(v.to_string())));
+                }
+                Entry::Notice(text) => {
+                    let style = Style::default().fg(Color::Yellow);
+                    lines.push(Line::from(Span::styled(text.to_string(), style)));
+                }
+            }
+            lines.push(Line::from(""));
+        }
+        lines
+    }
+
+    fn conversation_title(&self) -> String {
+        let config = self.config.read();
+        let mut parts = vec![config.model.id()];
+        if let Some(session) = &config.session {
+            parts.push(format!("session:{}", session.name()));
+        }
+        if let Some(role) = &config.role {
+            parts.push(format!("role:{}", role.name));
+        }
+        format!(" {} ", parts.join(" "))
+    }
+}
@@ -0,0 +1,276 @@
+use crate::utils::init_tokio_runtime;
+
+use anyhow::{anyhow, bail, Context, Result};
+use minisign_verify::{PublicKey, Signature};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/milanglacier/aichat/releases/latest";
+
+/// The minisign public key the release archives are signed with, set when building a release
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("AICHAT_RELEASE_PUBLIC_KEY");
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Check the latest GitHub release and replace the running binary with it
+pub fn upgrade(check_only: bool) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release: Release = serde_json::from_slice(&download(RELEASES_URL)?)
+        .with_context(|| "Invalid response of GitHub releases")?;
+    let latest = release.tag_name.trim_start_matches('v');
+    if !is_newer(latest, current) {
+        println!("aichat {current} is up to date.");
+        return Ok(());
+    }
+    println!("aichat {latest} is available, the current version is {current}.");
+    if check_only {
+        return Ok(());
+    }
+
+    let public_key = RELEASE_PUBLIC_KEY.ok_or_else(|| {
+        anyhow!("This build cannot verify releases, upgrade it with the tool it was installed with")
+    })?;
+    let asset = select_asset(&release, target_triple()?)?;
+    let archive = download(&asset.browser_download_url)?;
+    verify_checksum(&release, asset, &archive)?;
+    verify_signature(&release, asset, &archive, public_key)?;
+
+    let tmp_dir = env::temp_dir().join(format!("aichat-upgrade-{}", std::process::id()));
+    fs::create_dir_all(&tmp_dir)?;
+    let ret = install(&tmp_dir, &asset.name, &archive);
+    let _ = fs::remove_dir_all(&tmp_dir);
+    ret?;
+    println!("Upgraded aichat to {latest}.");
+    Ok(())
+}
+
+fn install(tmp_dir: &Path, name: &str, archive: &[u8]) -> Result<()> {
+    let archive_path = tmp_dir.join(name);
+    fs::write(&archive_path, archive)?;
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(&archive_path)
+        .arg("-C")
+        .arg(tmp_dir)
+        .status()
+        .with_context(|| "Failed to run 'tar' to extract the release")?;
+    if !status.success() {
+        bail!("Failed to extract {name}");
+    }
+    let bin_name = if cfg!(windows) {
+        "aichat.exe"
+    } else {
+        "aichat"
+    };
+    let new_exe = find_file(tmp_dir, bin_name)
+        .ok_or_else(|| anyhow!("No {bin_name} in the release archive"))?;
+    let exe = env::current_exe()?;
+    replace_exe(&new_exe, &exe)
+}
+
+/// Stage the new binary next to the current one, then rename it over, which is atomic on the
+/// same filesystem
+fn replace_exe(new_exe: &Path, exe: &Path) -> Result<()> {
+    let staged = exe.with_extension("new");
+    fs::copy(new_exe, &staged)
+        .with_context(|| format!("Failed to write to {}", staged.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(windows)]
+    {
+        // A running executable cannot be replaced on Windows, but it can be renamed
+        let old = exe.with_extension("old");
+        let _ = fs::remove_file(&old);
+        fs::rename(exe, &old)?;
+    }
+    fs::rename(&staged, exe).with_context(|| format!("Failed to replace {}", exe.display()))?;
+    Ok(())
+}
+
+/// The archive of the target, leaving out the checksums and signatures published next to it
+fn select_asset<'a>(release: &'a Release, target: &str) -> Result<&'a Asset> {
+    release
+        .assets
+        .iter()
+        .find(|v| {
+            v.name.contains(target)
+                && [".tar.gz", ".tgz", ".zip"]
+                    .iter()
+                    .any(|ext| v.name.ends_with(ext))
+        })
+        .ok_or_else(|| anyhow!("No release asset for {target}"))
+}
+
+/// Compare the sha256 of the archive with the one published next to it, refuse unverified archives
+fn verify_checksum(release: &Release, asset: &Asset, archive: &[u8]) -> Result<()> {
+    let checksum_asset = release
+        .assets
+        .iter()
+        .find(|v| v.name == format!("{}.sha256", asset.name))
+        .or_else(|| release.assets.iter().find(|v| is_checksum(&v.name)))
+        .ok_or_else(|| anyhow!("The release publishes no checksum, refusing to install it"))?;
+    let checksums = String::from_utf8(download(&checksum_asset.browser_download_url)?)?;
+    check_checksum(&checksums, &asset.name, archive)
+}
+
+/// Check the archive against a `.sha256` file, either a bare hash or `<hash>  <name>` lines
+fn check_checksum(checksums: &str, name: &str, archive: &[u8]) -> Result<()> {
+    let expected = checksums
+        .lines()
+        .find_map(|line| {
+            let mut parts = line.split_whitespace();
+            let hash = parts.next()?;
+            match parts.next() {
+                None => Some(hash),
+                Some(file) if file.trim_start_matches('*') == name => Some(hash),
+                Some(_) => None,
+            }
+        })
+        .ok_or_else(|| anyhow!("No checksum for {name}"))?;
+    let actual = format!("{:x}", Sha256::digest(archive));
+    if !expected.eq_ignore_ascii_case(&actual) {
+        bail!("The checksum of {name} does not match, refusing to install it");
+    }
+    Ok(())
+}
+
+/// Verify the `.minisig` signature published next to the archive, a checksum alone only guards
+/// against a corrupted download
+fn verify_signature(
+    release: &Release,
+    asset: &Asset,
+    archive: &[u8],
+    public_key: &str,
+) -> Result<()> {
+    let signature_asset = release
+        .assets
+        .iter()
+        .find(|v| v.name == format!("{}.minisig", asset.name))
+        .ok_or_else(|| anyhow!("The release publishes no signature, refusing to install it"))?;
+    let signature = String::from_utf8(download(&signature_asset.browser_download_url)?)?;
+    let public_key = PublicKey::from_base64(public_key)
+        .map_err(|err| anyhow!("Invalid release public key, {err}"))?;
+    let signature =
+        Signature::decode(&signature).map_err(|err| anyhow!("Invalid signature, {err}"))?;
+    public_key.verify(archive, &signature, false).map_err(|_| {
+        anyhow!(
+            "The signature of {} is invalid, refusing to install it",
+            asset.name
+        )
+    })
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    init_tokio_runtime()?.block_on(async {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("aichat/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let res = client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch {url}"))?;
+        let status = res.status();
+        if !status.is_success() {
+            bail!("Failed to fetch {url}, {status}");
+        }
+        Ok(res.bytes().await?.to_vec())
+    })
+}
+
+fn target_triple() -> Result<&'static str> {
+    let target = match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => "x86_64-unknown-linux-musl",
+        ("linux", "aarch64") => "aarch64-unknown-linux-musl",
+        ("macos", "x86_64") => "x86_64-apple-darwin",
+        ("macos", "aarch64") => "aarch64-apple-darwin",
+        ("windows", "x86_64") => "x86_64-pc-windows-msvc",
+        ("windows", "aarch64") => "aarch64-pc-windows-msvc",
+        (os, arch) => bail!("No prebuilt binary for {arch} {os}"),
+    };
+    Ok(target)
+}
+
+fn is_checksum(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".sha256") || name.contains("checksums") || name.contains("sha256sums")
+}
+
+fn is_newer(latest: &str, current: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> { v.split('.').filter_map(|v| v.parse().ok()).collect() };
+    parse(latest) > parse(current)
+}
+
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(path) = find_file(&path, name) {
+                return Some(path);
+            }
+        } else if path.file_name().map(|v| v == name).unwrap_or_default() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            name: name.to_string(),
+            browser_download_url: format!("https://example.com/{name}"),
+        }
+    }
+
+    #[test]
+    fn test_select_asset() {
+        let release = Release {
+            tag_name: "v1.0.0".into(),
+            assets: vec![
+                asset("aichat-v1.0.0-x86_64-unknown-linux-musl.tar.gz.sha256"),
+                asset("aichat-v1.0.0-x86_64-unknown-linux-musl.tar.gz.minisig"),
+                asset("aichat-v1.0.0-x86_64-unknown-linux-musl.tar.gz"),
+                asset("aichat-v1.0.0-x86_64-pc-windows-msvc.zip"),
+            ],
+        };
+        let target = "x86_64-unknown-linux-musl";
+        assert_eq!(
+            select_asset(&release, target).unwrap().name,
+            "aichat-v1.0.0-x86_64-unknown-linux-musl.tar.gz"
+        );
+        assert!(select_asset(&release, "aarch64-apple-darwin").is_err());
+    }
+
+    #[test]
+    fn test_check_checksum() {
+        let archive = b"archive";
+        let hash = format!("{:x}", Sha256::digest(archive));
+        let name = "aichat-x86_64-apple-darwin.tar.gz";
+        assert!(check_checksum(&hash, name, archive).is_ok());
+        let checksums = format!("{}  other.tar.gz\n{hash} *{name}\n", "0".repeat(64));
+        assert!(check_checksum(&checksums, name, archive).is_ok());
+        assert!(check_checksum(&checksums, name, b"tampered").is_err());
+        assert!(check_checksum(&checksums, "missing.tar.gz", archive).is_err());
+    }
+}
@@ -2,12 +2,15 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
+use std::thread::{sleep, spawn};
+use std::time::Duration;
 
 pub type AbortSignal = Arc<AbortSignalInner>;
 
 pub struct AbortSignalInner {
     ctrlc: AtomicBool,
     ctrld: AtomicBool,
+    timeout: AtomicBool,
 }
 
 pub fn create_abort_signal() -> AbortSignal {
@@ -19,6 +22,7 @@ impl AbortSignalInner {
         Arc::new(Self {
             ctrlc: AtomicBool::new(false),
             ctrld: AtomicBool::new(false),
+            timeout: AtomicBool::new(false),
         })
     }
 
@@ -29,6 +33,9 @@ impl AbortSignalInner {
         if self.aborted_ctrld() {
             return true;
         }
+        if self.aborted_timeout() {
+            return true;
+        }
         false
     }
 
@@ -40,9 +47,14 @@ impl AbortSignalInner {
         self.ctrld.load(Ordering::SeqCst)
     }
 
+    pub fn aborted_timeout(&self) -> bool {
+        self.timeout.load(Ordering::SeqCst)
+    }
+
     pub fn reset(&self) {
         self.ctrlc.store(false, Ordering::SeqCst);
         self.ctrld.store(false, Ordering::SeqCst);
+        self.timeout.store(false, Ordering::SeqCst);
     }
 
     pub fn set_ctrlc(&self) {
@@ -53,3 +65,11 @@ impl AbortSignalInner {
         self.ctrld.store(true, Ordering::SeqCst);
     }
 }
+
+/// Abort when the duration elapses
+pub fn abort_after(abort: AbortSignal, duration: Duration) {
+    spawn(move || {
+        sleep(duration);
+        abort.timeout.store(true, Ordering::SeqCst);
+    });
+}
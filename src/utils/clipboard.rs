@@ -18,3 +18,114 @@ pub fn set_text(text: &str) -> anyhow::Result<()> {
 pub fn set_text(_text: &str) -> anyhow::Result<()> {
     anyhow::bail!("No available clipboard")
 }
+
+pub fn set_tmux_buffer(text: &str) -> anyhow::Result<()> {
+    let status = std::process::Command::new("tmux")
+        .args(["set-buffer", "--", text])
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("Failed to set the tmux buffer")
+    }
+    Ok(())
+}
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+))]
+pub fn set_primary_text(text: &str) -> anyhow::Result<()> {
+    use arboard::{LinuxClipboardKind, SetExtLinux};
+    let mut clipboard = CLIPBOARD.lock().unwrap();
+    match clipboard.as_mut() {
+        Some(clipboard) => clipboard
+            .set()
+            .clipboard(LinuxClipboardKind::Primary)
+            .text(text)?,
+        None => anyhow::bail!("No available clipboard"),
+    }
+    Ok(())
+}
+
+#[cfg(not(all(
+    unix,
+    not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+)))]
+pub fn set_primary_text(_text: &str) -> anyhow::Result<()> {
+    anyhow::bail!("The primary selection is only available on X11 and Wayland")
+}
+
+/// Where `.copy` puts the text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CopyTarget {
+    Clipboard,
+    /// The X11 primary selection, pasted with the middle click
+    Primary,
+    Tmux,
+    File(std::path::PathBuf),
+}
+
+impl CopyTarget {
+    /// Split the target off the front of the arguments, defaulting to the clipboard
+    pub fn parse(args: &str) -> anyhow::Result<(Self, &str)> {
+        let args = args.trim();
+        let (first, rest) = args.split_once(' ').unwrap_or((args, ""));
+        let target = match first {
+            "clipboard" => Self::Clipboard,
+            "primary" => Self::Primary,
+            "tmux" => Self::Tmux,
+            "file" => {
+                let rest = rest.trim();
+                let (path, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                if path.is_empty() {
+                    anyhow::bail!("Usage: .copy file <path>")
+                }
+                return Ok((Self::File(path.into()), rest.trim()));
+            }
+            _ => return Ok((Self::Clipboard, args)),
+        };
+        Ok((target, rest.trim()))
+    }
+
+    pub fn set_text(&self, text: &str) -> anyhow::Result<()> {
+        match self {
+            Self::Clipboard => set_text(text),
+            Self::Primary => set_primary_text(text),
+            Self::Tmux => set_tmux_buffer(text),
+            Self::File(path) => std::fs::write(path, text)
+                .map_err(|err| anyhow::anyhow!("Failed to write {}: {err}", path.display())),
+        }
+    }
+}
+
+impl std::fmt::Display for CopyTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Clipboard => write!(f, "the clipboard"),
+            Self::Primary => write!(f, "the primary selection"),
+            Self::Tmux => write!(f, "the tmux buffer"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            CopyTarget::parse("tmux table 2").unwrap(),
+            (CopyTarget::Tmux, "table 2")
+        );
+        assert_eq!(
+            CopyTarget::parse("file out.md").unwrap(),
+            (CopyTarget::File("out.md".into()), "")
+        );
+        assert_eq!(
+            CopyTarget::parse("math 1").unwrap(),
+            (CopyTarget::Clipboard, "math 1")
+        );
+        assert!(CopyTarget::parse("file").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref MATH_RE: Regex = Regex::new(
+        r"(?s)\$\$(.+?)\$\$|\\\[(.+?)\\\]|\\\((.+?)\\\)|(?<![\\$\w])\$(?![\s$])([^$\n]+?)(?<!\s)\$(?![\w$])"
+    )
+    .unwrap();
+}
+
+/// The markdown tables of the text, as rows of cells, ignoring fenced code blocks
+pub fn extract_tables(text: &str) -> Vec<Vec<Vec<String>>> {
+    let mut tables = vec![];
+    let mut rows: Vec<&str> = vec![];
+    for line in strip_code_blocks(text).lines().chain([""]) {
+        let line = line.trim();
+        if line.starts_with('|') {
+            rows.push(line);
+            continue;
+        }
+        if rows.len() >= 2 && is_separator(rows[1]) {
+            let table = rows
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != 1)
+                .map(|(_, v)| split_cells(v))
+                .collect();
+            tables.push(table);
+        }
+        rows.clear();
+    }
+    tables
+}
+
+/// Join the rows as CSV, or as TSV when the separator is a tab
+pub fn format_table(rows: &[Vec<String>], separator: char) -> String {
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| {
+                    if separator == '\t' {
+                        cell.replace(['\t', '\n'], " ")
+                    } else if cell.contains([separator, '"', '\n']) {
+                        format!("\"{}\"", cell.replace('"', "\"\""))
+                    } else {
+                        cell.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(&separator.to_string())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The raw LaTeX of the math in the text: `$$...$$`, `\[...\]`, `\(...\)` and `$...$`
+pub fn extract_math(text: &str) -> Vec<String> {
+    let text = strip_code_blocks(text);
+    MATH_RE
+        .captures_iter(&text)
+        .flatten()
+        .filter_map(|caps| {
+            (1..=4)
+                .find_map(|i| caps.get(i))
+                .map(|v| v.as_str().trim().to_string())
+        })
+        .collect()
+}
+
+fn strip_code_blocks(text: &str) -> String {
+    let mut in_block = false;
+    let mut lines = vec![];
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_block = !in_block;
+            continue;
+        }
+        if !in_block {
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+fn is_separator(line: &str) -> bool {
+    split_cells(line)
+        .iter()
+        .all(|v| !v.is_empty() && v.chars().all(|c| matches!(c, '-' | ':' | ' ')))
+}
+
+fn split_cells(line: &str) -> Vec<String> {
+    let line = line.trim().trim_start_matches('|');
+    let line = match line.strip_suffix('|') {
+        Some(v) if !v.ends_with('\\') => v,
+        _ => line,
+    };
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cell.push('|');
+                chars.next();
+            }
+            '|' => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_tables() {
+        let text = "Prices:\n\n| Item | Price |\n|:-----|------:|\n| Tea, green | $3 |\n| A \\| B | \"4\" |\n\nDone";
+        let tables = extract_tables(text);
+        assert_eq!(tables.len(), 1);
+        assert_eq!(
+            format_table(&tables[0], ','),
+            "Item,Price\n\"Tea, green\",$3\nA | B,\"\"\"4\"\"\""
+        );
+        assert_eq!(
+            format_table(&tables[0], '\t'),
+            "Item\tPrice\nTea, green\t$3\nA | B\t\"4\""
+        );
+    }
+
+    #[test]
+    fn test_extract_math() {
+        let text = "Euler: $e^{i\\pi} + 1 = 0$, costs $3 and $4.\n$$\n\\int_0^1 x\\,dx\n$$\n```\n$skip$\n```\nAlso \\(a^2\\).";
+        assert_eq!(
+            extract_math(text),
+            vec!["e^{i\\pi} + 1 = 0", "\\int_0^1 x\\,dx", "a^2"]
+        );
+    }
+}
@@ -1,23 +1,53 @@
-mod abort_signal;
-mod clipboard;
+pub(crate) mod abort_signal;
+pub(crate) mod clipboard;
+pub(crate) mod extract;
+pub(crate) mod nuon;
 mod prompt_input;
 mod render_prompt;
+pub(crate) mod status;
 mod tiktoken;
+mod untrusted;
 
 pub use self::abort_signal::{create_abort_signal, AbortSignal};
-pub use self::clipboard::set_text;
+pub use self::clipboard::CopyTarget;
 pub use self::prompt_input::*;
 pub use self::render_prompt::render_prompt;
+pub use self::status::{clear_status, current_status, set_status, set_status_drawn};
 pub use self::tiktoken::cl100k_base_singleton;
+pub use self::untrusted::{scan_untrusted, wrap_untrusted};
 
 use fancy_regex::Regex;
 use lazy_static::lazy_static;
 use sha2::{Digest, Sha256};
 use std::env;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, Instant};
 
 lazy_static! {
     pub static ref CODE_BLOCK_RE: Regex = Regex::new(r"(?ms)```\w*(.*)```").unwrap();
+    /// The last `env_context`, with when and in which directory it was computed
+    static ref ENV_CONTEXT: parking_lot::Mutex<Option<(Instant, PathBuf, String)>> =
+        Default::default();
+}
+
+/// How long `env_context` is reused, so the system prompt is stable across the messages
+const ENV_CONTEXT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Whether the url points to this machine
+pub fn is_local_url(url: &str) -> bool {
+    let host = url
+        .split("://")
+        .nth(1)
+        .unwrap_or(url)
+        .split(['/', '?'])
+        .next()
+        .unwrap_or_default();
+    let host = match host.strip_prefix('[') {
+        Some(v) => v.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    host == "localhost" || host == "::1" || host == "0.0.0.0" || host.starts_with("127.")
 }
 
 pub fn now() -> String {
@@ -80,6 +110,18 @@ pub fn light_theme_from_colorfgbg(colorfgbg: &str) -> Option<bool> {
     Some(light)
 }
 
+/// Remove the characters of a reply that a terminal would interpret, e.g. an OSC sequence that sets
+/// the title or the clipboard, and the bidi overrides that reorder the displayed text
+pub fn sanitize_output(text: &str) -> String {
+    text.chars()
+        .filter(|c| match c {
+            '\n' | '\t' => true,
+            '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' => false,
+            c => !c.is_control(),
+        })
+        .collect()
+}
+
 pub fn init_tokio_runtime() -> anyhow::Result<tokio::runtime::Runtime> {
     use anyhow::Context;
     tokio::runtime::Builder::new_current_thread()
@@ -88,6 +130,35 @@ pub fn init_tokio_runtime() -> anyhow::Result<tokio::runtime::Runtime> {
         .with_context(|| "Failed to init tokio")
 }
 
+pub fn fetch_url(url: &str) -> anyhow::Result<String> {
+    use anyhow::Context;
+    init_tokio_runtime()?.block_on(async {
+        let res = reqwest::get(url)
+            .await
+            .with_context(|| format!("Failed to fetch {url}"))?;
+        let status = res.status();
+        if !status.is_success() {
+            anyhow::bail!("Failed to fetch {url}, {status}");
+        }
+        let text = res.text().await?;
+        Ok(text)
+    })
+}
+
+pub fn fetch_bytes(url: &str) -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context;
+    init_tokio_runtime()?.block_on(async {
+        let res = reqwest::get(url)
+            .await
+            .with_context(|| format!("Failed to fetch {url}"))?;
+        let status = res.status();
+        if !status.is_success() {
+            anyhow::bail!("Failed to fetch {url}, {status}");
+        }
+        Ok(res.bytes().await?.to_vec())
+    })
+}
+
 pub fn sha256sum(input: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(input);
@@ -147,6 +218,86 @@ pub fn detect_shell() -> (String, String, &'static str) {
     }
 }
 
+/// A compact description of the user's machine, so answers fit the actual environment. It is
+/// computed again only after `ENV_CONTEXT_TTL` or when the current directory changes.
+pub fn env_context() -> String {
+    let cwd = env::current_dir().unwrap_or_default();
+    let mut cache = ENV_CONTEXT.lock();
+    if let Some((time, dir, context)) = cache.as_ref() {
+        if *dir == cwd && time.elapsed() < ENV_CONTEXT_TTL {
+            return context.clone();
+        }
+    }
+    let context = build_env_context(&cwd);
+    *cache = Some((Instant::now(), cwd, context.clone()));
+    context
+}
+
+fn build_env_context(cwd: &Path) -> String {
+    let (shell, _, _) = detect_shell();
+    let mut items = vec![format!("os: {}", detect_os()), format!("shell: {shell}")];
+    if !cwd.as_os_str().is_empty() {
+        items.push(format!("cwd: {}", cwd.display()));
+    }
+    if let Some(branch) = git_branch() {
+        items.push(format!("git branch: {branch}"));
+    }
+    items.push(format!("date: {}", now()));
+    if let Some(locale) = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|v| env::var(v).ok().filter(|v| !v.is_empty()))
+    {
+        items.push(format!("locale: {locale}"));
+    }
+    let items: Vec<String> = items.into_iter().map(|v| format!("- {v}")).collect();
+    format!("The environment of the user:\n{}", items.join("\n"))
+}
+
+fn git_branch() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let branch = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!branch.is_empty()).then_some(branch)
+}
+
+/// The root of the git repository containing the current directory
+pub fn git_toplevel() -> Option<std::path::PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!path.is_empty()).then(|| path.into())
+}
+
+/// Parse a duration such as `500ms`, `20s`, `2m` or `1h`, plain numbers are seconds
+pub fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    let value = value.trim();
+    let index = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(index);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid duration '{value}'"))?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => anyhow::bail!("Invalid duration '{value}', use a unit of ms, s, m or h"),
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
 pub fn run_command(eval_str: &str) -> anyhow::Result<i32> {
     let (_shell_name, shell_cmd, shell_arg) = detect_shell();
     let status = Command::new(shell_cmd)
@@ -172,10 +323,202 @@ pub fn extract_block(input: &str) -> String {
     }
 }
 
+/// Extract the code of a streaming reply as it arrives, like `extract_block` does with the whole
+/// reply: the lines between the first and the last fence, or the whole text without a fence
+#[derive(Debug, Default)]
+pub struct CodeBlockStream {
+    line: String,
+    /// The lines before the first fence
+    prose: Vec<String>,
+    /// The lines from the latest fence on, code only if another fence follows
+    held: Option<Vec<String>>,
+    in_code: bool,
+    /// Blank lines kept back until more code follows, as the code is trimmed
+    blanks: usize,
+    started: bool,
+}
+
+impl CodeBlockStream {
+    /// Feed a chunk of the reply, returns the lines known to be code
+    pub fn push(&mut self, text: &str) -> Vec<String> {
+        self.line.push_str(text);
+        let mut output = vec![];
+        while let Some(i) = self.line.find('\n') {
+            let line: String = self.line.drain(..=i).collect();
+            self.push_line(line.trim_end_matches(['\n', '\r']), &mut output);
+        }
+        output
+    }
+
+    /// Returns the remaining code lines, or the prose when the reply had no fence
+    pub fn finish(&mut self) -> Result<Vec<String>, String> {
+        let mut output = vec![];
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.push_line(&line, &mut output);
+        }
+        if self.in_code {
+            Ok(output)
+        } else {
+            Err(self.prose.join("\n"))
+        }
+    }
+
+    fn push_line(&mut self, line: &str, output: &mut Vec<String>) {
+        let is_fence = line.trim_start().starts_with("```");
+        if !self.in_code {
+            if is_fence {
+                self.in_code = true;
+            } else {
+                self.prose.push(line.to_string());
+            }
+            return;
+        }
+        if is_fence {
+            if let Some(held) = self.held.take() {
+                for line in held {
+                    self.emit(line, output);
+                }
+            }
+            self.held = Some(vec![line.to_string()]);
+        } else if let Some(held) = self.held.as_mut() {
+            held.push(line.to_string());
+        } else {
+            self.emit(line.to_string(), output);
+        }
+    }
+
+    fn emit(&mut self, line: String, output: &mut Vec<String>) {
+        if line.trim().is_empty() {
+            if self.started {
+                self.blanks += 1;
+            }
+            return;
+        }
+        output.extend(std::iter::repeat_n(String::new(), self.blanks));
+        self.blanks = 0;
+        self.started = true;
+        output.push(line);
+    }
+}
+
+/// Guess the language of a piece of unfenced code
+pub fn detect_code_language(code: &str) -> Option<&'static str> {
+    let code = code.trim();
+    if let Some(line) = code.lines().next().and_then(|v| v.strip_prefix("#!")) {
+        let interpreter = line.split_whitespace().last().unwrap_or_default();
+        let interpreter = interpreter.rsplit('/').next().unwrap_or_default();
+        return match interpreter {
+            v if v.starts_with("python") => Some("python"),
+            "node" | "deno" => Some("javascript"),
+            "ruby" => Some("ruby"),
+            "perl" => Some("perl"),
+            "bash" | "sh" | "zsh" => Some("sh"),
+            _ => None,
+        };
+    }
+    let has = |patterns: &[&str]| patterns.iter().any(|v| code.contains(v));
+    let upper = code.to_uppercase();
+    let lang = if code.starts_with("<?php") {
+        "php"
+    } else if has(&["#include"]) {
+        if has(&["std::", "cout", "template<", "class "]) {
+            "cpp"
+        } else {
+            "c"
+        }
+    } else if has(&["package main", "fmt.Println", ":= "]) {
+        "go"
+    } else if has(&["fn ", "let mut ", "println!", "impl "]) && has(&["{"]) {
+        "rust"
+    } else if has(&["public class ", "public static void", "System.out."]) {
+        "java"
+    } else if has(&["def ", "import ", "print("]) && !has(&["{", ";"]) {
+        "python"
+    } else if has(&["function ", "const ", "console.log", "=> {"]) {
+        "javascript"
+    } else if upper.starts_with("SELECT ")
+        || upper.starts_with("INSERT INTO ")
+        || upper.starts_with("CREATE TABLE ")
+        || upper.starts_with("UPDATE ")
+    {
+        "sql"
+    } else if code.starts_with("<!DOCTYPE") || code.starts_with("<html") {
+        "html"
+    } else if (code.starts_with('{') || code.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(code).is_ok()
+    {
+        "json"
+    } else {
+        return None;
+    };
+    Some(lang)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_code_block_stream() {
+        let text = "Here it is:\n```sh\n\nls -la\n```\nthen\n```\necho done\n```\nDone.";
+        let mut stream = CodeBlockStream::default();
+        let mut output = vec![];
+        for chunk in text.as_bytes().chunks(5) {
+            output.extend(stream.push(std::str::from_utf8(chunk).unwrap()));
+        }
+        output.extend(stream.finish().unwrap());
+        assert_eq!(output.join("\n"), extract_block(text));
+        let mut stream = CodeBlockStream::default();
+        stream.push("no fence\nat all");
+        assert_eq!(stream.finish(), Err("no fence\nat all".to_string()));
+    }
+
+    #[test]
+    fn test_detect_code_language() {
+        assert_eq!(
+            detect_code_language("#!/usr/bin/env python3\nprint(1)"),
+            Some("python")
+        );
+        assert_eq!(
+            detect_code_language("fn main() {\n    println!(\"hi\");\n}"),
+            Some("rust")
+        );
+        assert_eq!(
+            detect_code_language("import os\nprint(os.getcwd())"),
+            Some("python")
+        );
+        assert_eq!(detect_code_language("select * from users"), Some("sql"));
+        assert_eq!(detect_code_language("ls -la"), None);
+    }
+
+    #[test]
+    fn test_sanitize_output() {
+        assert_eq!(
+            sanitize_output("a\x1b]0;pwned\x07b\r\n\tc\u{202E}d"),
+            "a]0;pwnedb\n\tcd"
+        );
+        assert_eq!(sanitize_output("中文 👍"), "中文 👍");
+    }
+
+    #[test]
+    fn test_is_local_url() {
+        assert!(is_local_url("http://localhost:11434"));
+        assert!(is_local_url("http://127.0.0.1:8080/v1"));
+        assert!(is_local_url("http://[::1]:8080"));
+        assert!(!is_local_url("https://api.openai.com/v1"));
+        assert!(!is_local_url("http://localhost.example.com"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("20s").unwrap(), Duration::from_secs(20));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("3").unwrap(), Duration::from_secs(3));
+        assert!(parse_duration("3d").is_err());
+    }
+
     #[test]
     fn test_tokenize() {
         assert_eq!(tokenize("😊 hello world"), ["😊", " hello", " world"]);
@@ -0,0 +1,85 @@
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref LIST_ITEM_RE: Regex = Regex::new(r"^\s*(?:[-*+]|\d+[.)])\s+(.*)$").unwrap();
+    static ref TABLE_SEP_RE: Regex = Regex::new(r"^\|?(\s*:?-+:?\s*\|)*\s*:?-+:?\s*\|?$").unwrap();
+}
+
+/// Convert a reply to NUON so it can be piped into `from nuon`
+///
+/// - A markdown table becomes a table
+/// - A markdown list becomes a list
+/// - Anything else becomes a string
+pub fn to_nuon(text: &str) -> String {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    if let Some(output) = table_to_nuon(&lines) {
+        return output;
+    }
+    if let Some(output) = list_to_nuon(&lines) {
+        return output;
+    }
+    quote(text.trim())
+}
+
+fn table_to_nuon(lines: &[&str]) -> Option<String> {
+    let start = lines.iter().position(|v| v.starts_with('|'))?;
+    let rows: Vec<&str> = lines[start..]
+        .iter()
+        .take_while(|v| v.starts_with('|'))
+        .cloned()
+        .collect();
+    if rows.len() < 2 || !TABLE_SEP_RE.is_match(rows[1]).unwrap_or_default() {
+        return None;
+    }
+    let header = split_row(rows[0]);
+    let body: Vec<String> = rows[2..]
+        .iter()
+        .map(|row| {
+            let mut cells = split_row(row);
+            cells.resize(header.len(), quote(""));
+            format!("[{}]", cells.join(", "))
+        })
+        .collect();
+    Some(format!("[[{}]; {}]", header.join(", "), body.join(", ")))
+}
+
+fn list_to_nuon(lines: &[&str]) -> Option<String> {
+    let mut items = vec![];
+    for line in lines {
+        let captures = LIST_ITEM_RE.captures(line).ok()??;
+        items.push(quote(captures[1].trim()));
+    }
+    if items.is_empty() {
+        return None;
+    }
+    Some(format!("[{}]", items.join(", ")))
+}
+
+fn split_row(row: &str) -> Vec<String> {
+    let row = row.trim().trim_start_matches('|').trim_end_matches('|');
+    row.split('|').map(|v| quote(v.trim())).collect()
+}
+
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_nuon() {
+        assert_eq!(to_nuon("- a\n- b \"c\"\n"), r#"["a", "b \"c\""]"#);
+        assert_eq!(
+            to_nuon("Here:\n\n| name | age |\n|---|:-:|\n| tom | 3 |\n| bob |\n"),
+            r#"[["name", "age"]; ["tom", "3"], ["bob", ""]]"#
+        );
+        assert_eq!(to_nuon("hello\nworld"), r#""hello\nworld""#);
+    }
+}
@@ -0,0 +1,40 @@
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    /// What the app is waiting for right now, e.g. a rate-limit backoff
+    static ref STATUS: Mutex<Option<String>> = Mutex::new(None);
+    /// Background events finished since the last prompt, e.g. a session compression
+    static ref NOTIFICATIONS: Mutex<Vec<String>> = Mutex::new(vec![]);
+}
+
+/// Whether a streamed reply is drawing the status in place of its spinner
+static STATUS_DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Show a transient status, returns false if nobody draws it and the caller has to
+pub fn set_status(text: &str) -> bool {
+    *STATUS.lock() = Some(text.to_string());
+    STATUS_DRAWN.load(Ordering::Relaxed)
+}
+
+pub fn clear_status() {
+    *STATUS.lock() = None;
+}
+
+pub fn current_status() -> Option<String> {
+    STATUS.lock().clone()
+}
+
+pub fn set_status_drawn(value: bool) {
+    STATUS_DRAWN.store(value, Ordering::Relaxed);
+}
+
+/// Queue a message about a finished background event, shown above the next REPL prompt
+pub fn notify(text: &str) {
+    NOTIFICATIONS.lock().push(text.to_string());
+}
+
+pub fn take_notifications() -> Vec<String> {
+    std::mem::take(&mut *NOTIFICATIONS.lock())
+}